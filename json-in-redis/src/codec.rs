@@ -0,0 +1,179 @@
+//! Encodings of the stored values.
+//!
+//! Plain JSON is written as is, so it stays readable by other clients. The other codecs prefix the
+//! data with a 2-byte header, a NUL byte (which never starts a JSON text) and a tag naming the
+//! codec, so the readers detect the codec of each value and the existing plain JSON values are
+//! still read as JSON.
+
+use std::io::Read;
+
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::error::{Error, Result};
+
+const HEADER_MARKER: u8 = 0;
+
+/// The zstd compression level, which is the default of the zstd CLI.
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Codec {
+    /// JSON text without header.
+    #[default]
+    Json,
+    /// MessagePack, with the fields of structs as maps.
+    MessagePack,
+    Cbor,
+    /// zstd-compressed JSON.
+    ZstdJson,
+    /// Deflate-compressed JSON.
+    DeflateJson,
+}
+
+impl Codec {
+    fn tag(self) -> Option<u8> {
+        match self {
+            Codec::Json => None,
+            Codec::MessagePack => Some(b'm'),
+            Codec::Cbor => Some(b'c'),
+            Codec::ZstdJson => Some(b'z'),
+            Codec::DeflateJson => Some(b'd'),
+        }
+    }
+
+    /// Detects the codec of the encoded data by its header.
+    pub fn detect(data: &[u8]) -> Result<Self> {
+        match data {
+            [HEADER_MARKER, tag, ..] => match tag {
+                b'm' => Ok(Codec::MessagePack),
+                b'c' => Ok(Codec::Cbor),
+                b'z' => Ok(Codec::ZstdJson),
+                b'd' => Ok(Codec::DeflateJson),
+                tag => Err(Error::UnknownCodecError(*tag)),
+            },
+            [HEADER_MARKER] => Err(Error::CodecError("Truncated header".to_owned())),
+            _ => Ok(Codec::Json),
+        }
+    }
+
+    pub fn encode<T: Serialize + ?Sized>(self, value: &T) -> Result<Vec<u8>> {
+        let mut data = match self.tag() {
+            Some(tag) => vec![HEADER_MARKER, tag],
+            None => vec![],
+        };
+        match self {
+            Codec::Json => serde_json::to_writer(&mut data, value)?,
+            Codec::MessagePack => {
+                let mut serializer = rmp_serde::Serializer::new(&mut data).with_struct_map();
+                value.serialize(&mut serializer).map_err(codec_error)?;
+            }
+            Codec::Cbor => ciborium::into_writer(value, &mut data).map_err(codec_error)?,
+            Codec::ZstdJson => {
+                let mut encoder = zstd::Encoder::new(data, ZSTD_LEVEL)?;
+                serde_json::to_writer(&mut encoder, value)?;
+                data = encoder.finish()?;
+            }
+            Codec::DeflateJson => {
+                let mut encoder = DeflateEncoder::new(data, Compression::default());
+                serde_json::to_writer(&mut encoder, value)?;
+                data = encoder.finish()?;
+            }
+        }
+        Ok(data)
+    }
+
+    /// Decodes the data in any codec, detected by the header.
+    pub fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T> {
+        let codec = Self::detect(data)?;
+        let body = if codec.tag().is_some() { &data[2..] } else { data };
+        match codec {
+            Codec::Json => Ok(serde_json::from_slice(body)?),
+            Codec::MessagePack => rmp_serde::from_slice(body).map_err(codec_error),
+            Codec::Cbor => ciborium::from_reader(body).map_err(codec_error),
+            Codec::ZstdJson => Ok(serde_json::from_reader(zstd::Decoder::new(body)?)?),
+            Codec::DeflateJson => {
+                // Decompresses first, as `serde_json::from_reader` is slow on unbuffered readers.
+                let mut json = vec![];
+                DeflateDecoder::new(body).read_to_end(&mut json)?;
+                Ok(serde_json::from_slice(&json)?)
+            }
+        }
+    }
+}
+
+fn codec_error<E: std::fmt::Display>(e: E) -> Error {
+    Error::CodecError(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde::Deserialize;
+    use serde_json::{json, Value};
+
+    const CODECS: [Codec; 5] =
+        [Codec::Json, Codec::MessagePack, Codec::Cbor, Codec::ZstdJson, Codec::DeflateJson];
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Record {
+        code: u16,
+        success: bool,
+        features: Vec<String>,
+        ratio: Option<f64>,
+    }
+
+    #[test]
+    fn round_trips() -> Result<()> {
+        let record = Record {
+            code: 200,
+            success: true,
+            features: vec!["serde".to_owned(), "json".to_owned()],
+            ratio: Some(-0.5),
+        };
+        let value = json!({ "a": [1, -2, 3.5, null, "x"], "b": { "c": u64::MAX } });
+        for codec in CODECS {
+            let data = codec.encode(&record)?;
+            assert_eq!(Codec::detect(&data)?, codec);
+            assert_eq!(Codec::decode::<Record>(&data)?, record);
+            // Self-describing, so it can be read without knowing the type.
+            let json = Codec::decode::<Value>(&data)?;
+            assert_eq!(json["features"][1], "json");
+
+            assert_eq!(Codec::decode::<Value>(&codec.encode(&value)?)?, value);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn plain_json_and_headers() -> Result<()> {
+        for json in [&b"{\"a\":1}"[..], b" [1]", b"\"\\u0000\"", b"null", b"-1"] {
+            assert_eq!(Codec::detect(json)?, Codec::Json);
+            Codec::decode::<Value>(json)?;
+        }
+        assert_eq!(Codec::Json.encode(&json!({ "a": 1 }))?, b"{\"a\":1}");
+
+        assert!(matches!(Codec::detect(b"\0x{}"), Err(Error::UnknownCodecError(b'x'))));
+        assert!(Codec::decode::<Value>(b"\0").is_err());
+        assert!(Codec::decode::<Value>(b"\0z\x01\x02").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn compression() -> Result<()> {
+        let items: Vec<_> = (0..100)
+            .map(|i| json!({ "id": i, "name": format!("item-{}", i), "enabled": true }))
+            .collect();
+        let json_size = Codec::Json.encode(&items)?.len();
+        for codec in [Codec::MessagePack, Codec::Cbor, Codec::ZstdJson, Codec::DeflateJson] {
+            let size = codec.encode(&items)?.len();
+            assert!(size < json_size, "{:?}: {} >= {}", codec, size, json_size);
+        }
+        assert!(Codec::ZstdJson.encode(&items)?.len() * 4 < json_size);
+        Ok(())
+    }
+}
@@ -1,5 +1,7 @@
 use std::net::AddrParseError;
 
+use redis_async::error::Error as RedisError;
+
 use thiserror::Error;
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -8,12 +10,47 @@ pub enum Error {
     #[error("Internal error: {0}")]
     InternalError(String),
 
+    #[error("The transaction on '{0}' keeps conflicting with other writers")]
+    TransactionConflictError(String),
+
+    #[error("The connection is closed")]
+    ConnectionClosedError,
+
+    #[error("Timed out after {0}")]
+    TimeoutError(String),
+
+    #[error("Unknown codec tag {0:#04x}")]
+    UnknownCodecError(u8),
+
+    #[error("Codec error: {0}")]
+    CodecError(String),
+
     #[error("{0}")]
     AddrParseError(#[from] AddrParseError),
 
+    #[error("{0}")]
+    IoError(#[from] std::io::Error),
+
     #[error("{0}")]
     JsonError(#[from] serde_json::Error),
 
     #[error("{0}")]
-    RedisError(#[from] redis_async::error::Error),
+    RedisError(#[from] RedisError),
+
+    #[error("{0}")]
+    JsonPathError(#[from] jsonpath::error::Error),
+}
+
+impl Error {
+    /// Returns whether the error is caused by a broken or unresponsive connection, rather than
+    /// being replied by the server.
+    pub fn is_connection_error(&self) -> bool {
+        match self {
+            Error::ConnectionClosedError | Error::TimeoutError(_) | Error::IoError(_) => true,
+            Error::RedisError(e) => {
+                matches!(e, RedisError::IO(_) | RedisError::Connection(_) | RedisError::Internal(_))
+            }
+            _ => false,
+        }
+    }
 }
@@ -0,0 +1,11 @@
+//! Stores JSON data into Redis
+
+pub mod codec;
+pub mod error;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod pool;
+pub mod store;
+
+pub use crate::codec::Codec;
+pub use crate::store::JsonStore;
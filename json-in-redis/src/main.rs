@@ -1,40 +1,16 @@
-//! Stores JSON data into Redis
+//! Stores a JSON record into Redis and reads it back.
 
 use std::env;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::time::Duration;
 
-use redis_async::client::{self, PairedConnection};
-use redis_async::resp_array;
-use serde_json::{self, json, Value};
+use serde_json::{json, Value};
 
-mod error;
-
-use crate::error::{Error, Result};
-
-async fn async_put_json<'a>(conn: &'a Arc<PairedConnection>, key: &'a str, value: &'a Value)
-    -> Result<()>
-{
-    let value_str = value.to_string();
-    let _ret = conn.send::<String>(resp_array!["SET", key, value_str]).await?;
-    println!("async_put_json");
-    Ok(())
-}
-
-async fn async_check_key<'a>(conn: &'a Arc<PairedConnection>, key: &'a str) -> Result<bool> {
-    let key_exist = conn.send(resp_array!["EXISTS", key]).await?;
-    println!("async_check_key");
-    Ok(key_exist)
-}
-
-async fn async_fetch_json<'a>(conn: &'a Arc<PairedConnection>, key: &'a str) -> Result<Value> {
-    let ret: String = conn.send(resp_array!["GET", key]).await?;
-    println!("async_fetch_json");
-    Ok(serde_json::from_str(&ret)?)
-}
+use json_in_redis::JsonStore;
+use json_in_redis::error::{self, Error, Result};
 
 async fn run_client(addr: SocketAddr) -> Result<()> {
-    let conn = Arc::new(client::paired_connect(&addr).await?);
+    let store = JsonStore::connect(&addr).await?;
 
     let input_value = json!({
         "code": 200,
@@ -49,16 +25,18 @@ async fn run_client(addr: SocketAddr) -> Result<()> {
 
     const KEY: &str = "foo";
 
-    async_put_json(&conn, KEY, &input_value).await?;
+    store.put_with_ttl(KEY, &input_value, Duration::from_secs(60)).await?;
 
-    let key_exists = async_check_key(&conn, KEY).await?;
+    let key_exists = store.exists(KEY).await?;
     if !key_exists {
         return Err(Error::InternalError("The key does NOT exist.".to_owned()));
     }
 
-    let output_value = async_fetch_json(&conn, KEY).await?;
-    println!("{:?}", output_value);
-    assert_eq!(input_value, output_value, "The output JSON is not the same as the input one");
+    let output_value: Option<Value> = store.get(KEY).await?;
+    println!("{:?} (TTL: {:?})", output_value, store.ttl(KEY).await?);
+    assert_eq!(Some(input_value), output_value, "The output JSON is not the same as the input one");
+
+    store.delete(KEY).await?;
 
     Ok(())
 }
@@ -70,7 +48,18 @@ async fn main() -> error::Result<()> {
         .unwrap_or_else(|| "127.0.0.1:6379".to_string())
         .parse()?;
 
-    run_client(addr).await.expect("run_client error");
+    run_client(addr).await
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use json_in_redis::mock::MockServer;
+
+    #[tokio::test]
+    async fn client_against_mock() -> Result<()> {
+        let server = MockServer::start().await?;
+        run_client(server.addr()).await
+    }
 }
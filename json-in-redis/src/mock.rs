@@ -0,0 +1,549 @@
+//! An in-process stand-in of a Redis server for tests, keeping the data in memory.
+//!
+//! It speaks RESP2, or RESP3 after `HELLO 3`, and supports `PING`, `HELLO`, `GET`, `SET` (with
+//! `EX`, `PX` and `KEEPTTL`), `EXISTS`, `DEL`, `EXPIRE`, `PEXPIRE`, `TTL`, `PTTL` and the
+//! transactions with `WATCH`, `UNWATCH`, `MULTI`, `EXEC` and `DISCARD`. Faults can be injected to
+//! test how clients cope with dropped connections and slow replies.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+use futures::{SinkExt, StreamExt};
+use redis_async::error::Error as RedisError;
+use redis_async::resp::{RespCodec, RespValue};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::time;
+use tokio_util::codec::{Decoder, Encoder, Framed};
+
+/// A mock server listening on a local port. It runs on the current Tokio runtime until the runtime
+/// shuts down.
+#[derive(Clone)]
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    // Closes all the open connections when sent to.
+    drop_tx: broadcast::Sender<()>,
+}
+
+impl MockServer {
+    pub async fn start() -> io::Result<Self> {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await?;
+        let (drop_tx, _) = broadcast::channel(1);
+        let server = Self {
+            addr: listener.local_addr()?,
+            state: Arc::new(Mutex::new(State::default())),
+            drop_tx,
+        };
+
+        let state = Arc::clone(&server.state);
+        let drop_tx = server.drop_tx.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                state.lock().unwrap().connections += 1;
+                tokio::spawn(serve(stream, Arc::clone(&state), drop_tx.subscribe()));
+            }
+        });
+        Ok(server)
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns the number of connections accepted so far.
+    pub fn connections(&self) -> usize {
+        self.state.lock().unwrap().connections
+    }
+
+    /// Returns the number of transactions aborted because a watched key was modified.
+    pub fn aborted_transactions(&self) -> usize {
+        self.state.lock().unwrap().aborted_transactions
+    }
+
+    /// Delays every reply by `delay`. A zero delay turns it off.
+    pub fn set_reply_delay(&self, delay: Duration) {
+        self.state.lock().unwrap().reply_delay = delay;
+    }
+
+    /// Closes the connections receiving the next `n` commands, without running or replying to the
+    /// commands.
+    pub fn drop_next_commands(&self, n: usize) {
+        self.state.lock().unwrap().commands_to_drop = n;
+    }
+
+    /// Closes all the open connections. The server keeps accepting new ones.
+    pub fn drop_connections(&self) {
+        // Fails only if there is no open connection.
+        let _ = self.drop_tx.send(());
+    }
+}
+
+async fn serve(stream: TcpStream, state: Arc<Mutex<State>>, mut drop_rx: broadcast::Receiver<()>) {
+    let mut framed = Framed::new(stream, MockCodec::default());
+    let mut session = Session::default();
+    loop {
+        let request = tokio::select! {
+            request = framed.next() => request,
+            _ = drop_rx.recv() => return,
+        };
+        let args = match request {
+            Some(Ok(request)) => parse_command(request),
+            _ => return,
+        };
+
+        let (reply, delay) = {
+            let mut state = state.lock().unwrap();
+            if state.commands_to_drop > 0 {
+                state.commands_to_drop -= 1;
+                return;
+            }
+            let reply = match args {
+                Some(args) => session.handle(&mut state, args),
+                None => Reply::error("ERR Protocol error: expected an array of bulk strings"),
+            };
+            (reply, state.reply_delay)
+        };
+        if delay > Duration::from_secs(0) {
+            time::delay_for(delay).await;
+        }
+
+        framed.codec_mut().protocol = session.protocol;
+        if framed.send(reply).await.is_err() {
+            return;
+        }
+    }
+}
+
+fn parse_command(request: RespValue) -> Option<Vec<Vec<u8>>> {
+    match request {
+        RespValue::Array(items) if !items.is_empty() => items
+            .into_iter()
+            .map(|item| match item {
+                RespValue::BulkString(s) => Some(s),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+fn parse_int(arg: &[u8]) -> Option<i64> {
+    std::str::from_utf8(arg).ok()?.parse().ok()
+}
+
+/// A reply, which is encoded according to the protocol version of the connection.
+#[derive(Debug, Clone, PartialEq)]
+enum Reply {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    /// The null bulk string `$-1` in RESP2.
+    Null,
+    /// The null array `*-1` in RESP2.
+    NullArray,
+    Array(Vec<Reply>),
+    /// A flat array of keys and values in RESP2.
+    Map(Vec<(Reply, Reply)>),
+}
+
+impl Reply {
+    fn ok() -> Self {
+        Reply::Simple("OK".to_owned())
+    }
+
+    fn error(message: &str) -> Self {
+        Reply::Error(message.to_owned())
+    }
+
+    fn wrong_arity(name: &str) -> Self {
+        Reply::Error(format!("ERR wrong number of arguments for '{}' command", name.to_lowercase()))
+    }
+
+    fn encode(&self, protocol: u8, buf: &mut Vec<u8>) {
+        let is_resp3 = protocol >= 3;
+        match self {
+            Reply::Simple(s) => buf.extend_from_slice(format!("+{}\r\n", s).as_bytes()),
+            Reply::Error(s) => buf.extend_from_slice(format!("-{}\r\n", s).as_bytes()),
+            Reply::Integer(n) => buf.extend_from_slice(format!(":{}\r\n", n).as_bytes()),
+            Reply::Bulk(s) => {
+                buf.extend_from_slice(format!("${}\r\n", s.len()).as_bytes());
+                buf.extend_from_slice(s);
+                buf.extend_from_slice(b"\r\n");
+            }
+            Reply::Null | Reply::NullArray if is_resp3 => buf.extend_from_slice(b"_\r\n"),
+            Reply::Null => buf.extend_from_slice(b"$-1\r\n"),
+            Reply::NullArray => buf.extend_from_slice(b"*-1\r\n"),
+            Reply::Array(items) => {
+                buf.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+                for item in items {
+                    item.encode(protocol, buf);
+                }
+            }
+            Reply::Map(entries) => {
+                let header = if is_resp3 {
+                    format!("%{}\r\n", entries.len())
+                } else {
+                    format!("*{}\r\n", entries.len() * 2)
+                };
+                buf.extend_from_slice(header.as_bytes());
+                for (k, v) in entries {
+                    k.encode(protocol, buf);
+                    v.encode(protocol, buf);
+                }
+            }
+        }
+    }
+}
+
+/// Decodes the requests as `RespCodec` does, and encodes the replies in the protocol version.
+struct MockCodec {
+    protocol: u8,
+}
+
+impl Default for MockCodec {
+    fn default() -> Self {
+        Self { protocol: 2 }
+    }
+}
+
+impl Decoder for MockCodec {
+    type Item = RespValue;
+    type Error = RedisError;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<RespValue>, RedisError> {
+        RespCodec.decode(buf)
+    }
+}
+
+impl Encoder<Reply> for MockCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, reply: Reply, buf: &mut BytesMut) -> io::Result<()> {
+        let mut encoded = vec![];
+        reply.encode(self.protocol, &mut encoded);
+        buf.extend_from_slice(&encoded);
+        Ok(())
+    }
+}
+
+struct Entry {
+    value: Vec<u8>,
+    expires_at: Option<Instant>,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<Vec<u8>, Entry>,
+    // Bumped on every write of a key, so `EXEC` can tell whether a watched key was modified.
+    versions: HashMap<Vec<u8>, u64>,
+    connections: usize,
+    aborted_transactions: usize,
+    reply_delay: Duration,
+    commands_to_drop: usize,
+}
+
+impl State {
+    fn entry(&mut self, key: &[u8]) -> Option<&mut Entry> {
+        let now = Instant::now();
+        if self.entries.get(key)?.expires_at.is_some_and(|t| t <= now) {
+            self.entries.remove(key);
+            return None;
+        }
+        self.entries.get_mut(key)
+    }
+
+    fn version(&self, key: &[u8]) -> u64 {
+        self.versions.get(key).copied().unwrap_or(0)
+    }
+
+    fn touch(&mut self, key: &[u8]) {
+        *self.versions.entry(key.to_vec()).or_insert(0) += 1;
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], options: &[Vec<u8>]) -> Reply {
+        let mut expires_at = None;
+        let mut keeps_ttl = false;
+        let mut options = options.iter();
+        while let Some(option) = options.next() {
+            let unit = match option.to_ascii_uppercase().as_slice() {
+                b"EX" => 1000,
+                b"PX" => 1,
+                b"KEEPTTL" => {
+                    keeps_ttl = true;
+                    continue;
+                }
+                _ => return Reply::error("ERR syntax error"),
+            };
+            match options.next().and_then(|ttl| parse_int(ttl)) {
+                Some(ttl) if ttl > 0 => {
+                    let ttl = Duration::from_millis(ttl as u64 * unit);
+                    expires_at = Some(Instant::now() + ttl);
+                }
+                _ => return Reply::error("ERR invalid expire time in 'set' command"),
+            }
+        }
+        if keeps_ttl {
+            expires_at = self.entry(key).and_then(|entry| entry.expires_at);
+        }
+
+        let entry = Entry { value: value.to_vec(), expires_at };
+        self.entries.insert(key.to_vec(), entry);
+        self.touch(key);
+        Reply::ok()
+    }
+
+    fn expire(&mut self, key: &[u8], ttl: &[u8], unit: u64) -> Reply {
+        let ttl = match parse_int(ttl) {
+            Some(ttl) => ttl,
+            None => return Reply::error("ERR value is not an integer or out of range"),
+        };
+        let expires_at = Instant::now() + Duration::from_millis(ttl.max(0) as u64 * unit);
+        match self.entry(key) {
+            Some(entry) => {
+                entry.expires_at = Some(expires_at);
+                self.touch(key);
+                Reply::Integer(1)
+            }
+            None => Reply::Integer(0),
+        }
+    }
+
+    fn ttl(&mut self, key: &[u8], unit: u128) -> Reply {
+        match self.entry(key) {
+            Some(Entry { expires_at: Some(t), .. }) => {
+                let ttl = t.saturating_duration_since(Instant::now());
+                // Rounds to the nearest unit as Redis does.
+                Reply::Integer(((ttl.as_millis() + unit / 2) / unit) as i64)
+            }
+            Some(_) => Reply::Integer(-1),
+            None => Reply::Integer(-2),
+        }
+    }
+
+    fn execute(&mut self, name: &str, args: &[Vec<u8>]) -> Reply {
+        match (name, args) {
+            ("PING", []) => Reply::Simple("PONG".to_owned()),
+            ("GET", [key]) => match self.entry(key) {
+                Some(entry) => Reply::Bulk(entry.value.clone()),
+                None => Reply::Null,
+            },
+            ("SET", [key, value, options @ ..]) => self.set(key, value, options),
+            ("EXISTS", keys) if !keys.is_empty() => {
+                let count = keys.iter().filter(|key| self.entry(key).is_some()).count();
+                Reply::Integer(count as i64)
+            }
+            ("DEL", keys) if !keys.is_empty() => {
+                let mut count = 0;
+                for key in keys {
+                    if self.entry(key).is_some() {
+                        self.entries.remove(key);
+                        self.touch(key);
+                        count += 1;
+                    }
+                }
+                Reply::Integer(count)
+            }
+            ("EXPIRE", [key, ttl]) => self.expire(key, ttl, 1000),
+            ("PEXPIRE", [key, ttl]) => self.expire(key, ttl, 1),
+            ("TTL", [key]) => self.ttl(key, 1000),
+            ("PTTL", [key]) => self.ttl(key, 1),
+            ("PING", _) | ("GET", _) | ("SET", _) | ("EXISTS", _) | ("DEL", _) | ("EXPIRE", _)
+            | ("PEXPIRE", _) | ("TTL", _) | ("PTTL", _) => Reply::wrong_arity(name),
+            _ => Reply::Error(format!("ERR unknown command '{}'", name.to_lowercase())),
+        }
+    }
+}
+
+/// The state of a connection.
+struct Session {
+    protocol: u8,
+    // The watched keys with their versions at the time of `WATCH`.
+    watched: Vec<(Vec<u8>, u64)>,
+    // The queued commands after `MULTI`.
+    queue: Option<Vec<Vec<Vec<u8>>>>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            protocol: 2,
+            watched: vec![],
+            queue: None,
+        }
+    }
+}
+
+impl Session {
+    fn handle(&mut self, state: &mut State, mut args: Vec<Vec<u8>>) -> Reply {
+        let name = String::from_utf8_lossy(&args.remove(0)).to_uppercase();
+        match (name.as_str(), &mut self.queue) {
+            ("HELLO", None) => self.hello(&args),
+            ("MULTI", Some(_)) => Reply::error("ERR MULTI calls can not be nested"),
+            ("MULTI", None) => {
+                self.queue = Some(vec![]);
+                Reply::ok()
+            }
+            ("WATCH", Some(_)) => Reply::error("ERR WATCH inside MULTI is not allowed"),
+            ("WATCH", None) if !args.is_empty() => {
+                for key in args {
+                    let version = state.version(&key);
+                    self.watched.push((key, version));
+                }
+                Reply::ok()
+            }
+            ("UNWATCH", None) => {
+                self.watched.clear();
+                Reply::ok()
+            }
+            ("EXEC", None) => Reply::error("ERR EXEC without MULTI"),
+            ("EXEC", Some(_)) => {
+                let queue = self.queue.take().unwrap_or_default();
+                let watched = std::mem::take(&mut self.watched);
+                if watched.iter().any(|(key, version)| state.version(key) != *version) {
+                    state.aborted_transactions += 1;
+                    return Reply::NullArray;
+                }
+                let replies = queue
+                    .into_iter()
+                    .map(|mut args| {
+                        let name = String::from_utf8_lossy(&args.remove(0)).to_uppercase();
+                        state.execute(&name, &args)
+                    })
+                    .collect();
+                Reply::Array(replies)
+            }
+            ("DISCARD", None) => Reply::error("ERR DISCARD without MULTI"),
+            ("DISCARD", Some(_)) => {
+                self.queue = None;
+                self.watched.clear();
+                Reply::ok()
+            }
+            (_, Some(queue)) => {
+                args.insert(0, name.into_bytes());
+                queue.push(args);
+                Reply::Simple("QUEUED".to_owned())
+            }
+            (_, None) => state.execute(&name, &args),
+        }
+    }
+
+    /// Switches the protocol version with `HELLO [2|3]`, and replies with the server info.
+    fn hello(&mut self, args: &[Vec<u8>]) -> Reply {
+        match args.first().map(|version| parse_int(version)) {
+            None => {}
+            Some(Some(version @ 2..=3)) => self.protocol = version as u8,
+            Some(_) => return Reply::error("NOPROTO unsupported protocol version"),
+        }
+
+        let field = |name: &str| Reply::Bulk(name.as_bytes().to_vec());
+        Reply::Map(vec![
+            (field("server"), field("redis")),
+            (field("version"), field("7.0.0")),
+            (field("proto"), Reply::Integer(self.protocol as i64)),
+            (field("mode"), field("standalone")),
+            (field("role"), field("master")),
+            (field("modules"), Reply::Array(vec![])),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use redis_async::client::{self, connect::RespConnection};
+    use redis_async::resp_array;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Sends the raw request and reads the raw reply, which must arrive in a single read.
+    async fn raw_call(stream: &mut TcpStream, request: &[u8]) -> String {
+        stream.write_all(request).await.unwrap();
+        let mut buf = vec![0; 1024];
+        let n = stream.read(&mut buf).await.unwrap();
+        String::from_utf8_lossy(&buf[..n]).into_owned()
+    }
+
+    #[tokio::test]
+    async fn protocols() -> io::Result<()> {
+        let server = MockServer::start().await?;
+        let mut stream = TcpStream::connect(server.addr()).await?;
+
+        let get = b"*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n";
+        assert_eq!(raw_call(&mut stream, get).await, "$-1\r\n");
+        let hello = raw_call(&mut stream, b"*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n").await;
+        assert!(hello.starts_with("%6\r\n$6\r\nserver\r\n$5\r\nredis\r\n"));
+        assert!(hello.contains("$5\r\nproto\r\n:3\r\n"));
+        assert_eq!(raw_call(&mut stream, get).await, "_\r\n");
+        let hello = raw_call(&mut stream, b"*2\r\n$5\r\nHELLO\r\n$1\r\n4\r\n").await;
+        assert!(hello.starts_with("-NOPROTO"));
+
+        let set = b"*5\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$1\r\n1\r\n$2\r\nEX\r\n$2\r\n60\r\n";
+        assert_eq!(raw_call(&mut stream, set).await, "+OK\r\n");
+        assert_eq!(raw_call(&mut stream, b"*2\r\n$3\r\nTTL\r\n$3\r\nfoo\r\n").await, ":60\r\n");
+        assert_eq!(raw_call(&mut stream, b"*1\r\n$4\r\nECHO\r\n").await,
+                   "-ERR unknown command 'echo'\r\n");
+        Ok(())
+    }
+
+    async fn call(conn: &mut RespConnection, command: RespValue) -> Result<RespValue, RedisError> {
+        conn.send(command).await?;
+        conn.next().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn transactions() -> Result<(), RedisError> {
+        let server = MockServer::start().await?;
+        let mut conn = client::connect(&server.addr()).await?;
+        let other = client::paired_connect(&server.addr()).await?;
+
+        call(&mut conn, resp_array!["WATCH", "foo"]).await?;
+        call(&mut conn, resp_array!["MULTI"]).await?;
+        let queued = call(&mut conn, resp_array!["SET", "foo", "1"]).await?;
+        assert_eq!(queued, RespValue::SimpleString("QUEUED".to_owned()));
+        other.send::<String>(resp_array!["SET", "foo", "2"]).await?;
+        assert_eq!(call(&mut conn, resp_array!["EXEC"]).await?, RespValue::Nil);
+        assert_eq!(server.aborted_transactions(), 1);
+
+        call(&mut conn, resp_array!["MULTI"]).await?;
+        call(&mut conn, resp_array!["EXPIRE", "foo", "60"]).await?;
+        call(&mut conn, resp_array!["TTL", "foo"]).await?;
+        let replies = call(&mut conn, resp_array!["EXEC"]).await?;
+        assert_eq!(replies, RespValue::Array(vec![RespValue::Integer(1), RespValue::Integer(60)]));
+        let reply = call(&mut conn, resp_array!["EXEC"]).await?;
+        assert_eq!(reply, RespValue::Error("ERR EXEC without MULTI".to_owned()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn faults() -> Result<(), RedisError> {
+        let server = MockServer::start().await?;
+        let mut conn = client::connect(&server.addr()).await?;
+
+        server.drop_next_commands(1);
+        conn.send(resp_array!["PING"]).await?;
+        assert!(conn.next().await.is_none());
+        assert_eq!(server.connections(), 1);
+
+        let mut conn = client::connect(&server.addr()).await?;
+        conn.send(resp_array!["PING"]).await?;
+        assert!(conn.next().await.is_some());
+        server.drop_connections();
+        assert!(conn.next().await.is_none());
+
+        let mut conn = client::connect(&server.addr()).await?;
+        server.set_reply_delay(Duration::from_millis(200));
+        conn.send(resp_array!["PING"]).await?;
+        let reply = time::timeout(Duration::from_millis(20), conn.next()).await;
+        assert!(reply.is_err());
+        server.set_reply_delay(Duration::from_secs(0));
+        assert!(conn.next().await.is_some());
+        assert_eq!(server.connections(), 3);
+        Ok(())
+    }
+}
@@ -0,0 +1,576 @@
+//! A pool of paired connections with health checks, reconnection and retries.
+//!
+//! A connection is marked broken when a command on it fails with a connection error or times out,
+//! and it is reopened on next use, or by the periodic health check. Failed reconnections are
+//! retried with exponential backoff. Only the commands sent with `Pool::send_idempotent` are
+//! retried, on another connection, as a command whose connection breaks may or may not have run.
+//!
+//! The pool also keeps a few exclusive connections for the commands that change the state of the
+//! connection, such as `WATCH` and `MULTI`, which cannot run on the multiplexed ones. They are
+//! opened on demand, and are checked, reopened and measured in the same way.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use futures::{SinkExt, StreamExt};
+use redis_async::client::{self, PairedConnection};
+use redis_async::client::connect::RespConnection;
+use redis_async::resp::{FromResp, RespValue};
+use redis_async::resp_array;
+use tokio::sync::{Mutex, MutexGuard};
+use tokio::time;
+
+use crate::error::{Error, Result};
+
+/// The upper bounds of the buckets of the latency histogram. The last bucket is unbounded.
+pub const LATENCY_BUCKETS_MS: [u64; 10] = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000];
+
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    /// The number of connections.
+    pub size: usize,
+    /// The number of exclusive connections, i.e., how many transactions can run at once.
+    pub exclusive_size: usize,
+    pub connect_timeout: Duration,
+    pub command_timeout: Duration,
+    /// How many times an idempotent command is retried after a connection error or timeout.
+    pub max_retries: u32,
+    /// The delay before the first retry or reconnection, which doubles up to `max_backoff`.
+    pub min_backoff: Duration,
+    pub max_backoff: Duration,
+    /// How often the connections are pinged, or `None` to turn off health checks.
+    pub health_check_interval: Option<Duration>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            size: 4,
+            exclusive_size: 4,
+            connect_timeout: Duration::from_secs(5),
+            command_timeout: Duration::from_secs(5),
+            max_retries: 3,
+            min_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(5),
+            health_check_interval: Some(Duration::from_secs(10)),
+        }
+    }
+}
+
+impl PoolConfig {
+    /// Returns the delay after the `failures`-th consecutive failure (counting from 1).
+    fn backoff(&self, failures: u32) -> Duration {
+        let factor = 1u32 << failures.saturating_sub(1).min(16);
+        self.min_backoff.checked_mul(factor).map_or(self.max_backoff, |d| d.min(self.max_backoff))
+    }
+}
+
+/// A snapshot of the metrics of a pool. The counters are totals since the pool is created.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolMetrics {
+    /// The number of commands waiting for their replies.
+    pub in_flight: usize,
+    /// The number of commands sent, including retries and health checks.
+    pub commands: u64,
+    /// The number of commands failed, including timeouts.
+    pub errors: u64,
+    pub timeouts: u64,
+    pub retries: u64,
+    /// The number of connections reopened after breaking.
+    pub reconnects: u64,
+    pub connect_failures: u64,
+    pub latency: LatencyHistogram,
+}
+
+/// The number of commands by latency, in the buckets bounded by `LATENCY_BUCKETS_MS`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyHistogram {
+    /// The counts of the buckets, with one more for the latency above the last bound.
+    pub counts: Vec<u64>,
+}
+
+impl LatencyHistogram {
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Returns the upper bound of the bucket where the quantile `q` (in `[0, 1]`) falls, which is
+    /// `None` for the unbounded bucket or an empty histogram.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let total = self.count();
+        if total == 0 {
+            return None;
+        }
+
+        let rank = ((q.clamp(0.0, 1.0) * total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return LATENCY_BUCKETS_MS.get(i).map(|&ms| Duration::from_millis(ms));
+            }
+        }
+        None
+    }
+}
+
+#[derive(Default)]
+struct Metrics {
+    in_flight: AtomicUsize,
+    commands: AtomicU64,
+    errors: AtomicU64,
+    timeouts: AtomicU64,
+    retries: AtomicU64,
+    reconnects: AtomicU64,
+    connect_failures: AtomicU64,
+    latency: [AtomicU64; LATENCY_BUCKETS_MS.len() + 1],
+}
+
+impl Metrics {
+    fn record_latency(&self, latency: Duration) {
+        let ms = latency.as_millis();
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|&bound| ms < bound as u128)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.latency[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Decrements the in-flight commands when dropped, so cancelled commands are not left counted.
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl<'a> InFlight<'a> {
+    fn new(in_flight: &'a AtomicUsize) -> Self {
+        in_flight.fetch_add(1, Ordering::Relaxed);
+        Self(in_flight)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+struct Slot<C> {
+    conn: Option<C>,
+    has_connected: bool,
+    // The consecutive failed connections, and when the next one may be attempted.
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+impl<C> Default for Slot<C> {
+    fn default() -> Self {
+        Self {
+            conn: None,
+            has_connected: false,
+            failures: 0,
+            retry_at: None,
+        }
+    }
+}
+
+struct Inner {
+    addr: SocketAddr,
+    config: PoolConfig,
+    slots: Vec<Mutex<Slot<Arc<PairedConnection>>>>,
+    next: AtomicUsize,
+    exclusive_slots: Vec<Mutex<Slot<RespConnection>>>,
+    next_exclusive: AtomicUsize,
+    metrics: Metrics,
+}
+
+/// A pool of paired connections, which are picked in turn. It is cheap to clone, and the clones
+/// share the connections.
+#[derive(Clone)]
+pub struct Pool {
+    inner: Arc<Inner>,
+}
+
+impl Pool {
+    /// Opens all the connections, and starts the health checks on the current Tokio runtime.
+    pub async fn connect(addr: &SocketAddr, config: PoolConfig) -> Result<Self> {
+        let size = config.size.max(1);
+        let exclusive_size = config.exclusive_size.max(1);
+        let health_check_interval = config.health_check_interval;
+        let inner = Arc::new(Inner {
+            addr: *addr,
+            config,
+            slots: (0..size).map(|_| Mutex::default()).collect(),
+            next: AtomicUsize::new(0),
+            exclusive_slots: (0..exclusive_size).map(|_| Mutex::default()).collect(),
+            next_exclusive: AtomicUsize::new(0),
+            metrics: Metrics::default(),
+        });
+        for index in 0..size {
+            inner.conn(index).await?;
+        }
+
+        if let Some(interval) = health_check_interval {
+            tokio::spawn(check_health(Arc::downgrade(&inner), interval));
+        }
+        Ok(Self { inner })
+    }
+
+    pub fn config(&self) -> &PoolConfig {
+        &self.inner.config
+    }
+
+    /// Sends the command once, as it may not be safe to run it again if the connection breaks.
+    pub async fn send<T: FromResp>(&self, command: RespValue) -> Result<T> {
+        self.inner.execute(command, 0).await
+    }
+
+    /// Sends the command, and retries it after a connection error or timeout. The command must be
+    /// idempotent, i.e., running it more than once has the same effect as running it once.
+    pub async fn send_idempotent<T: FromResp>(&self, command: RespValue) -> Result<T> {
+        self.inner.execute(command, self.inner.config.max_retries).await
+    }
+
+    /// Checks out an exclusive connection, waiting for one if all of them are in use. It is
+    /// returned to the pool by `ExclusiveConnection::release`, and dropped otherwise, so a
+    /// connection left in the middle of a transaction is never reused.
+    pub async fn exclusive(&self) -> Result<ExclusiveConnection<'_>> {
+        let inner = &*self.inner;
+        let slots = &inner.exclusive_slots;
+        // Prefers the first free slot, so the connections are only opened when needed.
+        let mut slot = match slots.iter().find_map(|slot| slot.try_lock().ok()) {
+            Some(slot) => slot,
+            None => {
+                let index = inner.next_exclusive.fetch_add(1, Ordering::Relaxed) % slots.len();
+                slots[index].lock().await
+            }
+        };
+        let conn = match slot.conn.take() {
+            Some(conn) => conn,
+            None => {
+                let connect = async { Ok(client::connect(&inner.addr).await?) };
+                inner.open(&mut slot, connect).await?
+            }
+        };
+        Ok(ExclusiveConnection {
+            inner,
+            slot,
+            conn: Some(conn),
+        })
+    }
+
+    /// Counts a retry of a command, and waits before it.
+    pub(crate) async fn back_off(&self, retries: u32) {
+        self.inner.back_off(retries).await
+    }
+
+    pub fn metrics(&self) -> PoolMetrics {
+        let metrics = &self.inner.metrics;
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        PoolMetrics {
+            in_flight: metrics.in_flight.load(Ordering::Relaxed),
+            commands: load(&metrics.commands),
+            errors: load(&metrics.errors),
+            timeouts: load(&metrics.timeouts),
+            retries: load(&metrics.retries),
+            reconnects: load(&metrics.reconnects),
+            connect_failures: load(&metrics.connect_failures),
+            latency: LatencyHistogram {
+                counts: metrics.latency.iter().map(load).collect(),
+            },
+        }
+    }
+}
+
+impl Inner {
+    async fn execute<T: FromResp>(&self, command: RespValue, max_retries: u32) -> Result<T> {
+        let mut retries = 0;
+        loop {
+            let index = self.next.fetch_add(1, Ordering::Relaxed) % self.slots.len();
+            match self.try_send(index, command.clone()).await {
+                Err(e) if retries < max_retries && e.is_connection_error() => {
+                    retries += 1;
+                    self.back_off(retries).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn back_off(&self, retries: u32) {
+        self.metrics.retries.fetch_add(1, Ordering::Relaxed);
+        time::delay_for(self.config.backoff(retries)).await;
+    }
+
+    async fn try_send<T: FromResp>(&self, index: usize, command: RespValue) -> Result<T> {
+        let conn = self.conn(index).await?;
+        let result = self.measure(async { Ok(conn.send::<T>(command).await?) }).await;
+        if result.as_ref().is_err_and(Error::is_connection_error) {
+            self.mark_broken(index, &conn).await;
+        }
+        result
+    }
+
+    /// Runs a command with the command timeout, and records it in the metrics.
+    async fn measure<T, F>(&self, command: F) -> Result<T>
+        where F: Future<Output = Result<T>>
+    {
+        let metrics = &self.metrics;
+        metrics.commands.fetch_add(1, Ordering::Relaxed);
+
+        let in_flight = InFlight::new(&metrics.in_flight);
+        let start = Instant::now();
+        let result = time::timeout(self.config.command_timeout, command).await;
+        metrics.record_latency(start.elapsed());
+        drop(in_flight);
+
+        let result = match result {
+            Ok(result) => result,
+            Err(_) => {
+                metrics.timeouts.fetch_add(1, Ordering::Relaxed);
+                Err(Error::TimeoutError(format!("{:?}", self.config.command_timeout)))
+            }
+        };
+        if result.is_err() {
+            metrics.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Returns the connection of the slot, reopening it if it is broken.
+    async fn conn(&self, index: usize) -> Result<Arc<PairedConnection>> {
+        let mut slot = self.slots[index].lock().await;
+        if let Some(conn) = &slot.conn {
+            return Ok(Arc::clone(conn));
+        }
+
+        let connect = async { Ok(Arc::new(client::paired_connect(&self.addr).await?)) };
+        let conn = self.open(&mut slot, connect).await?;
+        slot.conn = Some(Arc::clone(&conn));
+        Ok(conn)
+    }
+
+    /// Opens a connection for the slot, unless the slot is backing off after failed connections.
+    async fn open<C, F>(&self, slot: &mut Slot<C>, connect: F) -> Result<C>
+        where F: Future<Output = Result<C>>
+    {
+        // Fails fast while backing off, so the retries go on with the other connections.
+        if slot.retry_at.is_some_and(|t| Instant::now() < t) {
+            return Err(Error::ConnectionClosedError);
+        }
+
+        let result = match time::timeout(self.config.connect_timeout, connect).await {
+            Ok(result) => result,
+            Err(_) => Err(Error::TimeoutError(format!("{:?}", self.config.connect_timeout))),
+        };
+        match result {
+            Ok(conn) => {
+                if slot.has_connected {
+                    self.metrics.reconnects.fetch_add(1, Ordering::Relaxed);
+                }
+                *slot = Slot {
+                    conn: None,
+                    has_connected: true,
+                    failures: 0,
+                    retry_at: None,
+                };
+                Ok(conn)
+            }
+            Err(e) => {
+                self.metrics.connect_failures.fetch_add(1, Ordering::Relaxed);
+                slot.failures += 1;
+                slot.retry_at = Some(Instant::now() + self.config.backoff(slot.failures));
+                Err(e)
+            }
+        }
+    }
+
+    async fn mark_broken(&self, index: usize, conn: &Arc<PairedConnection>) {
+        let mut slot = self.slots[index].lock().await;
+        // It may have been reopened by another command in the meantime.
+        if slot.conn.as_ref().is_some_and(|c| Arc::ptr_eq(c, conn)) {
+            slot.conn = None;
+        }
+    }
+}
+
+/// An exclusive connection checked out of the pool by `Pool::exclusive`.
+pub struct ExclusiveConnection<'a> {
+    inner: &'a Inner,
+    slot: MutexGuard<'a, Slot<RespConnection>>,
+    // Taken out while a command is running, so it is dropped if the command fails or is cancelled.
+    conn: Option<RespConnection>,
+}
+
+impl ExclusiveConnection<'_> {
+    /// Sends the command and waits for its reply. The connection is dropped after a connection
+    /// error or timeout, and the later commands fail.
+    pub async fn call<T: FromResp>(&mut self, command: RespValue) -> Result<T> {
+        let mut conn = self.conn.take().ok_or(Error::ConnectionClosedError)?;
+        let result = self.inner.measure(call(&mut conn, command)).await;
+        if !result.as_ref().is_err_and(Error::is_connection_error) {
+            self.conn = Some(conn);
+        }
+        result
+    }
+
+    /// Returns the connection to the pool. It must be in its initial state, e.g., not watching any
+    /// key or in the middle of a transaction.
+    pub fn release(mut self) {
+        self.slot.conn = self.conn.take();
+    }
+}
+
+/// Sends a command on the connection and waits for its reply.
+async fn call<T: FromResp>(conn: &mut RespConnection, command: RespValue) -> Result<T> {
+    conn.send(command).await?;
+    match conn.next().await {
+        Some(reply) => Ok(T::from_resp(reply?)?),
+        None => Err(Error::ConnectionClosedError),
+    }
+}
+
+/// Pings all the connections periodically until the pool is dropped, which marks the broken ones
+/// and reopens them. The exclusive connections in use are skipped, and the broken ones are
+/// reopened on next use.
+async fn check_health(inner: Weak<Inner>, interval: Duration) {
+    loop {
+        time::delay_for(interval).await;
+        let inner = match inner.upgrade() {
+            Some(inner) => inner,
+            None => return,
+        };
+        for index in 0..inner.slots.len() {
+            let _ = inner.try_send::<String>(index, resp_array!["PING"]).await;
+        }
+        for slot in &inner.exclusive_slots {
+            let mut slot = match slot.try_lock() {
+                Ok(slot) if slot.conn.is_some() => slot,
+                _ => continue,
+            };
+            let conn = slot.conn.take();
+            let mut conn = ExclusiveConnection { inner: &inner, slot, conn };
+            let _ = conn.call::<String>(resp_array!["PING"]).await;
+            conn.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::mock::MockServer;
+
+    fn test_config() -> PoolConfig {
+        PoolConfig {
+            size: 1,
+            command_timeout: Duration::from_millis(100),
+            min_backoff: Duration::from_millis(1),
+            health_check_interval: None,
+            ..PoolConfig::default()
+        }
+    }
+
+    #[test]
+    fn backoff_and_histogram() {
+        let config = PoolConfig {
+            min_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_millis(300),
+            ..PoolConfig::default()
+        };
+        let backoffs: Vec<_> = (1..=5).map(|n| config.backoff(n).as_millis()).collect();
+        assert_eq!(backoffs, [50, 100, 200, 300, 300]);
+        assert_eq!(config.backoff(100), Duration::from_millis(300));
+
+        let metrics = Metrics::default();
+        for ms in [0, 0, 3, 7, 2000] {
+            metrics.record_latency(Duration::from_millis(ms));
+        }
+        let histogram = LatencyHistogram {
+            counts: metrics.latency.iter().map(|c| c.load(Ordering::Relaxed)).collect(),
+        };
+        assert_eq!(histogram.counts, [2, 0, 1, 1, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(histogram.count(), 5);
+        assert_eq!(histogram.quantile(0.0), Some(Duration::from_millis(1)));
+        assert_eq!(histogram.quantile(0.5), Some(Duration::from_millis(5)));
+        assert_eq!(histogram.quantile(0.8), Some(Duration::from_millis(10)));
+        assert_eq!(histogram.quantile(1.0), None);
+        assert_eq!(LatencyHistogram { counts: vec![0; 11] }.quantile(0.5), None);
+    }
+
+    #[tokio::test]
+    async fn retries() -> Result<()> {
+        let server = MockServer::start().await?;
+        let pool = Pool::connect(&server.addr(), test_config()).await?;
+
+        server.drop_next_commands(1);
+        assert_eq!(pool.send_idempotent::<String>(resp_array!["PING"]).await?, "PONG");
+        let metrics = pool.metrics();
+        assert_eq!((metrics.commands, metrics.errors, metrics.retries), (2, 1, 1));
+        assert_eq!(metrics.reconnects, 1);
+
+        server.drop_next_commands(1);
+        assert!(pool.send::<String>(resp_array!["SET", "foo", "1"]).await.is_err());
+        assert_eq!(pool.metrics().retries, 1);
+        assert_eq!(pool.send::<Option<String>>(resp_array!["GET", "foo"]).await?, None);
+
+        // Errors replied by the server are not retried.
+        let result = pool.send_idempotent::<String>(resp_array!["NOPE"]).await;
+        assert!(matches!(result, Err(Error::RedisError(_))));
+        assert_eq!(pool.metrics().retries, 1);
+        assert_eq!(pool.metrics().reconnects, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn timeouts() -> Result<()> {
+        let server = MockServer::start().await?;
+        let config = PoolConfig { max_retries: 1, ..test_config() };
+        let pool = Pool::connect(&server.addr(), config).await?;
+
+        server.set_reply_delay(Duration::from_millis(300));
+        let result = pool.send_idempotent::<String>(resp_array!["PING"]).await;
+        assert!(matches!(result, Err(Error::TimeoutError(_))));
+        let metrics = pool.metrics();
+        assert_eq!((metrics.timeouts, metrics.retries, metrics.in_flight), (2, 1, 0));
+
+        server.set_reply_delay(Duration::from_millis(50));
+        let sender = pool.clone();
+        let ping = tokio::spawn(async move {
+            sender.send_idempotent::<String>(resp_array!["PING"]).await
+        });
+        time::delay_for(Duration::from_millis(20)).await;
+        assert_eq!(pool.metrics().in_flight, 1);
+        assert_eq!(ping.await.map_err(|e| Error::InternalError(e.to_string()))??, "PONG");
+        assert_eq!(pool.metrics().in_flight, 0);
+        // The timed out commands are counted at the timeout.
+        let latency = pool.metrics().latency;
+        assert_eq!(latency.count(), 3);
+        assert_eq!(latency.quantile(0.0), Some(Duration::from_millis(100)));
+        assert_eq!(latency.quantile(1.0), Some(Duration::from_millis(200)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn health_checks() -> Result<()> {
+        let server = MockServer::start().await?;
+        let config = PoolConfig {
+            size: 2,
+            health_check_interval: Some(Duration::from_millis(20)),
+            ..test_config()
+        };
+        let pool = Pool::connect(&server.addr(), config).await?;
+        assert_eq!(server.connections(), 2);
+
+        server.drop_connections();
+        time::delay_for(Duration::from_millis(150)).await;
+        // The broken connections are found and reopened without any command failing.
+        assert_eq!(pool.metrics().reconnects, 2);
+        for _ in 0..4 {
+            pool.send::<String>(resp_array!["PING"]).await?;
+        }
+        Ok(())
+    }
+}
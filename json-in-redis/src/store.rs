@@ -0,0 +1,374 @@
+//! A store of values serialized as JSON strings in Redis.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use redis_async::resp::{FromResp, RespValue};
+use redis_async::resp_array;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tokio::time;
+
+use jsonpath::Selector;
+
+use crate::codec::Codec;
+use crate::error::{Error, Result};
+use crate::pool::{Pool, PoolConfig};
+
+/// How many times `JsonStore::update` runs the transaction before giving up on conflicts.
+pub const MAX_TRANSACTION_ATTEMPTS: usize = 16;
+
+/// The delay before the second attempt of a conflicting transaction, which grows linearly.
+pub const TRANSACTION_BACKOFF: Duration = Duration::from_millis(1);
+
+/// Gets and puts values of any `Serialize`/`DeserializeOwned` type, encoded by the codec of the
+/// store (plain JSON by default). The values in any codec are read. It is cheap to clone, and the
+/// clones share the connections.
+///
+/// The reads and the plain writes (`put` and `put_with_ttl`) are retried after connection errors,
+/// while `delete` and `expire` are not, as their results depend on whether the first attempt has
+/// run. The transactions are retried unless the connection breaks after `EXEC` is sent.
+#[derive(Clone)]
+pub struct JsonStore {
+    pool: Pool,
+    codec: Codec,
+}
+
+impl JsonStore {
+    pub async fn connect(addr: &SocketAddr) -> Result<Self> {
+        Self::connect_with(addr, PoolConfig::default()).await
+    }
+
+    pub async fn connect_with(addr: &SocketAddr, config: PoolConfig) -> Result<Self> {
+        Ok(Self {
+            pool: Pool::connect(addr, config).await?,
+            codec: Codec::default(),
+        })
+    }
+
+    /// Sets the codec of the values written by the store.
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Returns the connection pool, e.g., to read its metrics.
+    pub fn pool(&self) -> &Pool {
+        &self.pool
+    }
+
+    /// Gets the value of the key, or `None` if the key does not exist.
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        let data: Option<Vec<u8>> = self.pool.send_idempotent(resp_array!["GET", key]).await?;
+        match data {
+            Some(data) => Ok(Some(Codec::decode(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Puts the value, replacing the existing one and its TTL.
+    pub async fn put<T: Serialize + ?Sized>(&self, key: &str, value: &T) -> Result<()> {
+        let data = self.codec.encode(value)?;
+        self.pool.send_idempotent::<String>(resp_array!["SET", key, data]).await?;
+        Ok(())
+    }
+
+    /// Puts the value, which expires after `ttl` (in milliseconds precision).
+    pub async fn put_with_ttl<T: Serialize + ?Sized>(&self, key: &str, value: &T, ttl: Duration)
+        -> Result<()>
+    {
+        let data = self.codec.encode(value)?;
+        let ttl = ttl.as_millis().to_string();
+        self.pool.send_idempotent::<String>(resp_array!["SET", key, data, "PX", ttl]).await?;
+        Ok(())
+    }
+
+    pub async fn exists(&self, key: &str) -> Result<bool> {
+        self.pool.send_idempotent(resp_array!["EXISTS", key]).await
+    }
+
+    /// Deletes the key. Returns whether the key existed.
+    pub async fn delete(&self, key: &str) -> Result<bool> {
+        self.pool.send(resp_array!["DEL", key]).await
+    }
+
+    /// Sets the TTL of the key. Returns whether the key exists.
+    pub async fn expire(&self, key: &str, ttl: Duration) -> Result<bool> {
+        let ttl = ttl.as_millis().to_string();
+        self.pool.send(resp_array!["PEXPIRE", key, ttl]).await
+    }
+
+    /// Gets the remaining TTL of the key, or `None` if the key does not exist or never expires.
+    pub async fn ttl(&self, key: &str) -> Result<Option<Duration>> {
+        let ttl: i64 = self.pool.send_idempotent(resp_array!["PTTL", key]).await?;
+        // PTTL returns -2 for a missing key and -1 for a key without TTL.
+        Ok(if ttl >= 0 { Some(Duration::from_millis(ttl as u64)) } else { None })
+    }
+
+    /// Gets the node of the document addressed by the JSONPath, e.g., `payload.features[0]`.
+    /// Returns `None` if the key or the node does not exist.
+    pub async fn get_path<T: DeserializeOwned>(&self, key: &str, path: &str) -> Result<Option<T>> {
+        let selector = Selector::new(path)?;
+        match self.get::<Value>(key).await? {
+            Some(document) => Ok(selector.get_as(&document)?),
+            None => Ok(None),
+        }
+    }
+
+    /// Sets the node of the document addressed by the JSONPath, creating the document and the
+    /// missing intermediate nodes. It runs as an optimistic transaction (see `update`), so the rest
+    /// of the document written concurrently is kept.
+    pub async fn set_path<T: Serialize + ?Sized>(&self, key: &str, path: &str, value: &T)
+        -> Result<()>
+    {
+        let selector = Selector::new(path)?;
+        let value = serde_json::to_value(value)?;
+        self.update(key, |document| {
+            selector.set(document, value.clone())?;
+            Ok(())
+        }).await
+    }
+
+    /// Reads, modifies and writes back the document in an optimistic transaction: the key is
+    /// `WATCH`ed while `f` modifies the document (`null` if the key does not exist), and the write
+    /// is discarded and retried if another client changes the key in between. The TTL of the key is
+    /// kept (`KEEPTTL` requires Redis 6.0).
+    ///
+    /// The transactions run on the exclusive connections of the pool, as `WATCH` applies to the
+    /// whole connection, so the updates of different keys run in parallel. `f` may run several
+    /// times, and the document is left untouched if it returns an error. A connection error is
+    /// retried up to the `max_retries` of the pool, unless it happens after `EXEC` is sent, when
+    /// the write may or may not have been done.
+    pub async fn update<F, R>(&self, key: &str, mut f: F) -> Result<R>
+        where F: FnMut(&mut Value) -> Result<R>
+    {
+        let mut conflicts = 0;
+        let mut retries = 0;
+        loop {
+            let mut is_committing = false;
+            match self.try_update(key, &mut f, &mut is_committing).await {
+                Ok(Some(result)) => return Ok(result),
+                Ok(None) => {
+                    conflicts += 1;
+                    if conflicts == MAX_TRANSACTION_ATTEMPTS {
+                        return Err(Error::TransactionConflictError(key.to_owned()));
+                    }
+                    // Backs off a little, so the writers do not keep conflicting in lockstep.
+                    time::delay_for(TRANSACTION_BACKOFF * conflicts as u32).await;
+                }
+                Err(e) if !is_committing &&
+                    e.is_connection_error() &&
+                    retries < self.pool.config().max_retries => {
+                    retries += 1;
+                    self.pool.back_off(retries).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Runs the transaction once. Returns `None` on conflict. `is_committing` is set once `EXEC` is
+    /// about to be sent.
+    async fn try_update<F, R>(&self, key: &str, f: &mut F, is_committing: &mut bool)
+        -> Result<Option<R>>
+        where F: FnMut(&mut Value) -> Result<R>
+    {
+        let mut conn = self.pool.exclusive().await?;
+        conn.call::<String>(resp_array!["WATCH", key]).await?;
+        let data: Option<Vec<u8>> = conn.call(resp_array!["GET", key]).await?;
+        let updated = data
+            .map_or(Ok(Value::Null), |data| Codec::decode(&data))
+            .and_then(|mut document| {
+                let result = f(&mut document)?;
+                Ok((result, self.codec.encode(&document)?))
+            });
+        let (result, data) = match updated {
+            Ok(updated) => updated,
+            Err(e) => {
+                conn.call::<String>(resp_array!["UNWATCH"]).await?;
+                conn.release();
+                return Err(e);
+            }
+        };
+
+        conn.call::<String>(resp_array!["MULTI"]).await?;
+        conn.call::<String>(resp_array!["SET", key, data, "KEEPTTL"]).await?;
+        *is_committing = true;
+        // A nil reply means the watched key was modified, and nothing was written.
+        let replies: Option<Vec<RespValue>> = conn.call(resp_array!["EXEC"]).await?;
+        conn.release();
+        match replies {
+            Some(replies) => {
+                for reply in replies {
+                    String::from_resp(reply)?;
+                }
+                Ok(Some(result))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Instant;
+
+    use serde_json::json;
+
+    use crate::mock::MockServer;
+
+    #[tokio::test]
+    async fn basic_ops() -> Result<()> {
+        let server = MockServer::start().await?;
+        let store = JsonStore::connect(&server.addr()).await?;
+
+        assert_eq!(store.get::<Value>("foo").await?, None);
+        store.put_with_ttl("foo", &json!({ "code": 200 }), Duration::from_secs(60)).await?;
+        assert!(store.exists("foo").await?);
+        assert_eq!(store.get("foo").await?, Some(json!({ "code": 200 })));
+        assert!(store.ttl("foo").await?.is_some_and(|ttl| ttl <= Duration::from_secs(60)));
+
+        store.put("foo", &[1, 2]).await?;
+        assert_eq!(store.ttl("foo").await?, None);
+        assert!(store.expire("foo", Duration::from_secs(1)).await?);
+        assert!(store.delete("foo").await?);
+        assert!(!store.delete("foo").await?);
+        assert!(!store.expire("foo", Duration::from_secs(1)).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn path_ops() -> Result<()> {
+        let server = MockServer::start().await?;
+        let store = JsonStore::connect(&server.addr()).await?;
+
+        let document = json!({ "code": 200, "payload": { "features": ["serde", "json"] } });
+        store.put_with_ttl("foo", &document, Duration::from_secs(60)).await?;
+        let feature: Option<String> = store.get_path("foo", "payload.features[0]").await?;
+        assert_eq!(feature.as_deref(), Some("serde"));
+        assert_eq!(store.get_path::<Value>("foo", "payload.missing").await?, None);
+        assert_eq!(store.get_path::<Value>("bar", "code").await?, None);
+
+        store.set_path("foo", "payload.features[2]", "redis").await?;
+        store.set_path("foo", "payload.size.max", &10).await?;
+        assert_eq!(store.get("foo").await?, Some(json!({
+            "code": 200,
+            "payload": { "features": ["serde", "json", "redis"], "size": { "max": 10 } },
+        })));
+        assert!(store.ttl("foo").await?.is_some());
+
+        store.set_path("bar", "a[1]", &true).await?;
+        assert_eq!(store.get("bar").await?, Some(json!({ "a": [null, true] })));
+
+        assert!(store.set_path("foo", "code.x", &1).await.is_err());
+        assert!(store.get_path::<Value>("foo", "[").await.is_err());
+        // The connection is still usable after an error.
+        store.set_path("foo", "code", &201).await?;
+        assert_eq!(store.get_path("foo", "code").await?, Some(201));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn codecs() -> Result<()> {
+        let server = MockServer::start().await?;
+        let json_store = JsonStore::connect(&server.addr()).await?;
+        let zstd_store = json_store.clone().with_codec(Codec::ZstdJson);
+
+        let document = json!({ "payload": { "features": ["serde", "json"] } });
+        json_store.put("old", &document).await?;
+        zstd_store.put("new", &document).await?;
+        assert_eq!(zstd_store.get("old").await?, Some(document.clone()));
+        assert_eq!(json_store.get("new").await?, Some(document.clone()));
+
+        // The transactions write in the codec of the store, whatever the codec read.
+        zstd_store.set_path("old", "payload.size", &1).await?;
+        assert_eq!(json_store.get_path("old", "payload.size").await?, Some(1));
+        let pool = json_store.pool();
+        let data: Vec<u8> = pool.send(resp_array!["GET", "old"]).await?;
+        assert_eq!(Codec::detect(&data)?, Codec::ZstdJson);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn faults() -> Result<()> {
+        let server = MockServer::start().await?;
+        let store = JsonStore::connect(&server.addr()).await?;
+        store.set_path("foo", "a", &1).await?;
+        // The commands of the transactions are measured by the pool.
+        assert_eq!(store.pool().metrics().commands, 5);
+
+        // The broken exclusive connection is reopened, and the transaction is retried.
+        server.drop_connections();
+        time::delay_for(Duration::from_millis(50)).await;
+        store.set_path("foo", "b", &2).await?;
+        let metrics = store.pool().metrics();
+        assert_eq!((metrics.retries, metrics.reconnects), (1, 1));
+
+        server.drop_next_commands(1);
+        store.set_path("foo", "c", &3).await?;
+        assert_eq!(store.pool().metrics().retries, 2);
+
+        server.set_reply_delay(Duration::from_millis(200));
+        let result = time::timeout(Duration::from_millis(20), store.set_path("foo", "d", &4)).await;
+        assert!(result.is_err());
+        server.set_reply_delay(Duration::from_secs(0));
+        // The connection left in the middle of the cancelled transaction is not reused.
+        let document = store.update("foo", |document| Ok(document.clone())).await?;
+        assert_eq!(document, json!({ "a": 1, "b": 2, "c": 3 }));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn parallel_updates() -> Result<()> {
+        let server = MockServer::start().await?;
+        let store = JsonStore::connect(&server.addr()).await?;
+        server.set_reply_delay(Duration::from_millis(40));
+
+        let start = Instant::now();
+        let (a, b) = futures::join!(store.set_path("a", "n", &1), store.set_path("b", "n", &2));
+        a?;
+        b?;
+        // Each transaction takes 5 round trips on its own connection.
+        assert!(start.elapsed() < Duration::from_millis(350), "{:?}", start.elapsed());
+        assert_eq!(server.connections(), PoolConfig::default().size + 2);
+        let metrics = store.pool().metrics();
+        assert_eq!((metrics.commands, metrics.errors), (10, 0));
+        Ok(())
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn concurrent_updates() -> Result<()> {
+        const WRITERS: usize = 4;
+        const INCREMENTS: usize = 25;
+
+        let server = MockServer::start().await?;
+        let store = JsonStore::connect(&server.addr()).await?;
+        store.put("counter", &json!({ "n": 0 })).await?;
+
+        let mut tasks = vec![];
+        for _ in 0..WRITERS {
+            // The clones run their transactions on separate exclusive connections, so the
+            // transactions interleave.
+            let store = store.clone();
+            tasks.push(tokio::spawn(async move {
+                for _ in 0..INCREMENTS {
+                    store.update("counter", |document| {
+                        let n = document["n"].as_u64().unwrap_or(0);
+                        document["n"] = json!(n + 1);
+                        Ok(())
+                    }).await?;
+                }
+                Ok::<_, Error>(())
+            }));
+        }
+        for task in tasks {
+            task.await.map_err(|e| Error::InternalError(e.to_string()))??;
+        }
+
+        assert_eq!(store.get_path("counter", "n").await?, Some(WRITERS * INCREMENTS));
+        Ok(())
+    }
+}
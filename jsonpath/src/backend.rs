@@ -0,0 +1,447 @@
+//! The value types that selectors can navigate. Besides `serde_json::Value`, the values of `toml`,
+//! `serde_yaml` and `config` are supported behind the features of the same names. Note that the
+//! `config` backend requires config 0.13 or later, the first version exposing the kinds of values.
+//!
+//! Filters navigate and compare the nodes of any backend natively, and only the scalars being
+//! compared are converted into JSON.
+
+use std::borrow::Cow;
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::error::{JsonType, Result};
+
+pub trait JsonLike: Sized {
+    type Members<'a>: Iterator<Item = (&'a str, &'a Self)> where Self: 'a;
+
+    fn json_type(&self) -> JsonType;
+
+    /// Returns the members of an object, or `None` if the value is not an object. The order of
+    /// members is that of the underlying map if it has one, or else the keys are sorted.
+    fn members(&self) -> Option<Self::Members<'_>>;
+
+    fn member(&self, key: &str) -> Option<&Self>;
+
+    fn member_mut(&mut self, key: &str) -> Option<&mut Self>;
+
+    fn remove_member(&mut self, key: &str) -> Option<Self>;
+
+    /// Returns the elements of an array, or `None` if the value is not an array.
+    fn elements(&self) -> Option<&[Self]>;
+
+    fn elements_mut(&mut self) -> Option<&mut Vec<Self>>;
+
+    /// Converts the value into JSON. Values without a JSON counterpart, e.g., TOML datetimes, are
+    /// converted into strings.
+    fn to_json(&self) -> Cow<'_, Value>;
+
+    /// Converts a scalar into JSON, or returns `None` for an array or an object, so unlike
+    /// `to_json` it never converts a whole subtree.
+    fn scalar_to_json(&self) -> Option<Cow<'_, Value>> {
+        match self.json_type() {
+            JsonType::Array | JsonType::Object => None,
+            _ => Some(self.to_json()),
+        }
+    }
+
+    fn deserialize_into<T: DeserializeOwned>(&self) -> Result<T>;
+}
+
+/// Borrows the key of a map entry as `&str`, as a function pointer to be named in `Members`.
+fn str_key<'a, K: AsRef<str>, V>((k, v): (&'a K, &'a V)) -> (&'a str, &'a V) {
+    (k.as_ref(), v)
+}
+
+impl JsonLike for Value {
+    type Members<'a> = std::iter::Map<
+        serde_json::map::Iter<'a>,
+        fn((&'a String, &'a Self)) -> (&'a str, &'a Self),
+    >;
+
+    fn json_type(&self) -> JsonType {
+        JsonType::of(self)
+    }
+
+    fn members(&self) -> Option<Self::Members<'_>> {
+        self.as_object().map(|m| m.iter().map(str_key as fn(_) -> _))
+    }
+
+    fn member(&self, key: &str) -> Option<&Self> {
+        self.as_object().and_then(|m| m.get(key))
+    }
+
+    fn member_mut(&mut self, key: &str) -> Option<&mut Self> {
+        self.as_object_mut().and_then(|m| m.get_mut(key))
+    }
+
+    fn remove_member(&mut self, key: &str) -> Option<Self> {
+        self.as_object_mut().and_then(|m| m.remove(key))
+    }
+
+    fn elements(&self) -> Option<&[Self]> {
+        self.as_array().map(Vec::as_slice)
+    }
+
+    fn elements_mut(&mut self) -> Option<&mut Vec<Self>> {
+        self.as_array_mut()
+    }
+
+    fn to_json(&self) -> Cow<'_, Value> {
+        Cow::Borrowed(self)
+    }
+
+    fn deserialize_into<T: DeserializeOwned>(&self) -> Result<T> {
+        Ok(T::deserialize(self)?)
+    }
+}
+
+#[cfg(any(feature = "toml", feature = "serde_yaml", feature = "config"))]
+fn deserialize_error<E: std::error::Error + Send + Sync + 'static>(e: E) -> crate::error::Error {
+    crate::error::Error::DeserializeError(Box::new(e))
+}
+
+#[cfg(any(feature = "toml", feature = "serde_yaml", feature = "config"))]
+fn float_to_json(f: f64) -> Value {
+    serde_json::Number::from_f64(f).map_or(Value::Null, Value::Number)
+}
+
+#[cfg(feature = "toml")]
+impl JsonLike for toml::Value {
+    type Members<'a> = std::iter::Map<
+        toml::map::Iter<'a>,
+        fn((&'a String, &'a Self)) -> (&'a str, &'a Self),
+    >;
+
+    fn json_type(&self) -> JsonType {
+        match self {
+            toml::Value::String(_) | toml::Value::Datetime(_) => JsonType::String,
+            toml::Value::Integer(_) | toml::Value::Float(_) => JsonType::Number,
+            toml::Value::Boolean(_) => JsonType::Bool,
+            toml::Value::Array(_) => JsonType::Array,
+            toml::Value::Table(_) => JsonType::Object,
+        }
+    }
+
+    fn members(&self) -> Option<Self::Members<'_>> {
+        self.as_table().map(|m| m.iter().map(str_key as fn(_) -> _))
+    }
+
+    fn member(&self, key: &str) -> Option<&Self> {
+        self.as_table().and_then(|m| m.get(key))
+    }
+
+    fn member_mut(&mut self, key: &str) -> Option<&mut Self> {
+        self.as_table_mut().and_then(|m| m.get_mut(key))
+    }
+
+    fn remove_member(&mut self, key: &str) -> Option<Self> {
+        self.as_table_mut().and_then(|m| m.remove(key))
+    }
+
+    fn elements(&self) -> Option<&[Self]> {
+        self.as_array().map(Vec::as_slice)
+    }
+
+    fn elements_mut(&mut self) -> Option<&mut Vec<Self>> {
+        self.as_array_mut()
+    }
+
+    fn to_json(&self) -> Cow<'_, Value> {
+        Cow::Owned(match self {
+            toml::Value::String(s) => Value::from(s.as_str()),
+            toml::Value::Datetime(d) => Value::from(d.to_string()),
+            toml::Value::Integer(i) => Value::from(*i),
+            toml::Value::Float(f) => float_to_json(*f),
+            toml::Value::Boolean(b) => Value::Bool(*b),
+            toml::Value::Array(a) => a.iter().map(|v| v.to_json().into_owned()).collect(),
+            toml::Value::Table(m) => {
+                m.iter().map(|(k, v)| (k.to_owned(), v.to_json().into_owned())).collect()
+            }
+        })
+    }
+
+    fn deserialize_into<T: DeserializeOwned>(&self) -> Result<T> {
+        self.clone().try_into().map_err(deserialize_error)
+    }
+}
+
+#[cfg(feature = "serde_yaml")]
+impl JsonLike for serde_yaml::Value {
+    type Members<'a> = std::iter::FilterMap<
+        serde_yaml::mapping::Iter<'a>,
+        fn((&'a Self, &'a Self)) -> Option<(&'a str, &'a Self)>,
+    >;
+
+    fn json_type(&self) -> JsonType {
+        match self {
+            serde_yaml::Value::Null => JsonType::Null,
+            serde_yaml::Value::Bool(_) => JsonType::Bool,
+            serde_yaml::Value::Number(_) => JsonType::Number,
+            serde_yaml::Value::String(_) => JsonType::String,
+            serde_yaml::Value::Sequence(_) => JsonType::Array,
+            serde_yaml::Value::Mapping(_) => JsonType::Object,
+        }
+    }
+
+    /// Only the members with string keys are visible.
+    fn members(&self) -> Option<Self::Members<'_>> {
+        fn string_key<'a, V>((k, v): (&'a serde_yaml::Value, &'a V)) -> Option<(&'a str, &'a V)> {
+            Some((k.as_str()?, v))
+        }
+
+        self.as_mapping().map(|m| m.iter().filter_map(string_key as fn(_) -> _))
+    }
+
+    fn member(&self, key: &str) -> Option<&Self> {
+        self.as_mapping().and_then(|m| m.get(&serde_yaml::Value::from(key)))
+    }
+
+    fn member_mut(&mut self, key: &str) -> Option<&mut Self> {
+        self.as_mapping_mut().and_then(|m| m.get_mut(&serde_yaml::Value::from(key)))
+    }
+
+    fn remove_member(&mut self, key: &str) -> Option<Self> {
+        self.as_mapping_mut().and_then(|m| m.remove(&serde_yaml::Value::from(key)))
+    }
+
+    fn elements(&self) -> Option<&[Self]> {
+        self.as_sequence().map(Vec::as_slice)
+    }
+
+    fn elements_mut(&mut self) -> Option<&mut Vec<Self>> {
+        self.as_sequence_mut()
+    }
+
+    fn to_json(&self) -> Cow<'_, Value> {
+        Cow::Owned(match self {
+            serde_yaml::Value::Null => Value::Null,
+            serde_yaml::Value::Bool(b) => Value::Bool(*b),
+            serde_yaml::Value::Number(n) => {
+                if let Some(i) = n.as_i64() {
+                    Value::from(i)
+                } else if let Some(u) = n.as_u64() {
+                    Value::from(u)
+                } else {
+                    n.as_f64().map_or(Value::Null, float_to_json)
+                }
+            }
+            serde_yaml::Value::String(s) => Value::from(s.as_str()),
+            serde_yaml::Value::Sequence(a) => a.iter().map(|v| v.to_json().into_owned()).collect(),
+            serde_yaml::Value::Mapping(_) => {
+                let members = self.members().into_iter().flatten();
+                members.map(|(k, v)| (k.to_owned(), v.to_json().into_owned())).collect()
+            }
+        })
+    }
+
+    fn deserialize_into<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_yaml::from_value(self.clone()).map_err(deserialize_error)
+    }
+}
+
+#[cfg(feature = "config")]
+impl JsonLike for config::Value {
+    /// The members are collected to be sorted, as the table is a `HashMap`.
+    type Members<'a> = std::vec::IntoIter<(&'a str, &'a Self)>;
+
+    fn json_type(&self) -> JsonType {
+        use config::ValueKind;
+
+        match self.kind {
+            ValueKind::Nil => JsonType::Null,
+            ValueKind::Boolean(_) => JsonType::Bool,
+            ValueKind::I64(_) | ValueKind::I128(_) | ValueKind::U64(_) | ValueKind::U128(_) |
+            ValueKind::Float(_) => JsonType::Number,
+            ValueKind::String(_) => JsonType::String,
+            ValueKind::Array(_) => JsonType::Array,
+            ValueKind::Table(_) => JsonType::Object,
+        }
+    }
+
+    fn members(&self) -> Option<Self::Members<'_>> {
+        match &self.kind {
+            config::ValueKind::Table(m) => {
+                let mut members: Vec<_> = m.iter().map(str_key).collect();
+                members.sort_by_key(|&(k, _v)| k);
+                Some(members.into_iter())
+            }
+            _ => None,
+        }
+    }
+
+    fn member(&self, key: &str) -> Option<&Self> {
+        match &self.kind {
+            config::ValueKind::Table(m) => m.get(key),
+            _ => None,
+        }
+    }
+
+    fn member_mut(&mut self, key: &str) -> Option<&mut Self> {
+        match &mut self.kind {
+            config::ValueKind::Table(m) => m.get_mut(key),
+            _ => None,
+        }
+    }
+
+    fn remove_member(&mut self, key: &str) -> Option<Self> {
+        match &mut self.kind {
+            config::ValueKind::Table(m) => m.remove(key),
+            _ => None,
+        }
+    }
+
+    fn elements(&self) -> Option<&[Self]> {
+        match &self.kind {
+            config::ValueKind::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    fn elements_mut(&mut self) -> Option<&mut Vec<Self>> {
+        match &mut self.kind {
+            config::ValueKind::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    fn to_json(&self) -> Cow<'_, Value> {
+        use std::convert::TryFrom;
+
+        use config::ValueKind;
+
+        Cow::Owned(match &self.kind {
+            ValueKind::Nil => Value::Null,
+            ValueKind::Boolean(b) => Value::Bool(*b),
+            ValueKind::I64(i) => Value::from(*i),
+            ValueKind::U64(u) => Value::from(*u),
+            ValueKind::I128(i) => i64::try_from(*i).map_or(float_to_json(*i as f64), Value::from),
+            ValueKind::U128(u) => u64::try_from(*u).map_or(float_to_json(*u as f64), Value::from),
+            ValueKind::Float(f) => float_to_json(*f),
+            ValueKind::String(s) => Value::from(s.as_str()),
+            ValueKind::Array(a) => a.iter().map(|v| v.to_json().into_owned()).collect(),
+            ValueKind::Table(m) => {
+                m.iter().map(|(k, v)| (k.to_owned(), v.to_json().into_owned())).collect()
+            }
+        })
+    }
+
+    fn deserialize_into<T: DeserializeOwned>(&self) -> Result<T> {
+        self.clone().try_deserialize().map_err(deserialize_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use crate::Selector;
+    use super::*;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Server {
+        host: String,
+        port: u16,
+    }
+
+    #[test]
+    fn backend_json() -> Result<()> {
+        let data = json!({ "servers": [{ "host": "a", "port": 80 }, { "host": "b", "port": 81 }] });
+
+        let selector = Selector::new("$.servers[-1]")?;
+        assert_eq!(
+            selector.get_as::<Server>(&data)?,
+            Some(Server { host: "b".into(), port: 81 })
+        );
+        assert_eq!(Selector::new("$.servers[0].port")?.get_as::<u16>(&data)?, Some(80));
+        assert_eq!(Selector::new("$.clients")?.get_as::<u16>(&data)?, None);
+        assert!(Selector::new("$.servers[0].host")?.get_as::<u16>(&data).is_err());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn backend_toml() -> Result<()> {
+        use crate::Mode;
+
+        let mut data: toml::Value = toml::from_str(r#"
+            [[servers]]
+            host = "a"
+            port = 80
+            started = 1979-05-27T07:32:00Z
+
+            [[servers]]
+            host = "b"
+            port = 8080
+        "#).unwrap();
+
+        let selector = Selector::new("$.servers[?@.port > 1024].host")?;
+        assert_eq!(selector.get_as::<String>(&data)?, Some("b".to_owned()));
+        let selector = Selector::new("$.servers[?@.started == '1979-05-27T07:32:00Z'].port")?;
+        assert_eq!(selector.get_as::<u16>(&data)?, Some(80));
+        let selector = Selector::with_mode("$.servers[?length(@) == 3].host", Mode::Rfc9535)?;
+        assert_eq!(selector.get_as::<String>(&data)?, Some("a".to_owned()));
+        let selector = Selector::new("$.servers[?@.port == $.servers[-1].port].host")?;
+        assert_eq!(selector.get_as::<String>(&data)?, Some("b".to_owned()));
+
+        assert_eq!(Selector::new("$..port")?.select(&data).len(), 2);
+        assert_eq!(
+            Selector::new("$..port")?.select_paths(&data),
+            vec!["$['servers'][0]['port']", "$['servers'][1]['port']"]
+        );
+        assert_eq!(Selector::new("$.servers[*].started")?.remove_all(&mut data).len(), 1);
+        assert_eq!(
+            Selector::new("$.servers[0]")?.get_as::<Server>(&data)?,
+            Some(Server { host: "a".into(), port: 80 })
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "serde_yaml")]
+    #[test]
+    fn backend_yaml() -> Result<()> {
+        let mut data: serde_yaml::Value = serde_yaml::from_str("
+            servers:
+              - host: a
+                port: 80
+              - host: b
+                port: 81
+            1: not visible
+        ").unwrap();
+
+        assert_eq!(Selector::new("$.*")?.select(&data).len(), 1);
+        let selector = Selector::new("$.servers[?@.host == 'b'].port")?;
+        assert_eq!(selector.get_as::<u16>(&data)?, Some(81));
+        let selector = Selector::new("$..port")?;
+        assert_eq!(selector.update_with(&mut data, |v| *v = serde_yaml::Value::from(0)), 2);
+        assert_eq!(selector.get_as::<u16>(&data)?, Some(0));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn backend_config() -> Result<()> {
+        let data: config::Value = config::Config::builder()
+            .set_override("servers.a.port", 80)
+            .and_then(|b| b.set_override("servers.b.port", 81))
+            .and_then(|b| b.set_override("servers.b.host", "b"))
+            .and_then(|b| b.build())
+            .and_then(|c| c.try_deserialize())
+            .unwrap();
+
+        assert_eq!(
+            Selector::new("$.servers.*.port")?.select_paths(&data),
+            vec!["$['servers']['a']['port']", "$['servers']['b']['port']"]
+        );
+        assert_eq!(
+            Selector::new("$.servers.b")?.get_as::<Server>(&data)?,
+            Some(Server { host: "b".into(), port: 81 })
+        );
+
+        Ok(())
+    }
+}
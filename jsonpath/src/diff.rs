@@ -0,0 +1,166 @@
+//! Structural diff between two JSON values.
+
+use serde_json::Value;
+
+use crate::{PathItem, PatchOp, Selector, normalized_path};
+
+/// A change at a single location. `old` is `None` for an added node and `new` is `None` for a
+/// removed node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    /// The location of the node, which only consists of children and non-negative indices.
+    pub path: Vec<PathItem>,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+impl Change {
+    /// Returns a singular selector addressing the changed node.
+    pub fn selector(&self) -> Selector {
+        Selector {
+            json_path: self.path.clone(),
+        }
+    }
+
+    /// Returns the normalized path of the changed node, e.g., `$['foo'][0]`.
+    pub fn normalized_path(&self) -> String {
+        normalized_path(&self.path)
+    }
+
+    /// Converts the change into a JSON Patch operation.
+    pub fn to_patch_op(&self) -> PatchOp {
+        let path = self
+            .selector()
+            .to_pointer()
+            .expect("A location only consists of children and non-negative indices");
+        match (&self.old, &self.new) {
+            (_, None) => PatchOp::Remove { path },
+            (None, Some(new)) => PatchOp::Add { path, value: new.clone() },
+            (Some(_), Some(new)) => PatchOp::Replace { path, value: new.clone() },
+        }
+    }
+}
+
+/// Computes the changes that turn `old` into `new`. Objects are compared member by member and
+/// arrays element by element, and any other difference, including a change of type, replaces the
+/// whole node.
+///
+/// The changes can be applied one after another. In particular, the trailing elements of a shrunk
+/// array are removed from the last one, so the indices of the remaining ones are still valid.
+pub fn diff(old: &Value, new: &Value) -> Vec<Change> {
+    let mut changes = vec![];
+    diff_at(&mut vec![], old, new, &mut changes);
+    changes
+}
+
+/// Computes the changes that turn `old` into `new` as a JSON Patch.
+pub fn diff_patch(old: &Value, new: &Value) -> Vec<PatchOp> {
+    diff(old, new).iter().map(Change::to_patch_op).collect()
+}
+
+fn diff_at(path: &mut Vec<PathItem>, old: &Value, new: &Value, changes: &mut Vec<Change>) {
+    let change = |path: &[PathItem], old: Option<&Value>, new: Option<&Value>| Change {
+        path: path.to_vec(),
+        old: old.cloned(),
+        new: new.cloned(),
+    };
+
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            for (k, old_v) in old {
+                path.push(PathItem::Child(k.to_owned()));
+                match new.get(k) {
+                    Some(new_v) => diff_at(path, old_v, new_v, changes),
+                    None => changes.push(change(path, Some(old_v), None)),
+                }
+                path.pop();
+            }
+            for (k, new_v) in new.iter().filter(|(k, _v)| !old.contains_key(*k)) {
+                path.push(PathItem::Child(k.to_owned()));
+                changes.push(change(path, None, Some(new_v)));
+                path.pop();
+            }
+        }
+        (Value::Array(old), Value::Array(new)) => {
+            for (i, (old_v, new_v)) in old.iter().zip(new).enumerate() {
+                path.push(PathItem::Index(i as isize));
+                diff_at(path, old_v, new_v, changes);
+                path.pop();
+            }
+            for (i, old_v) in old.iter().enumerate().skip(new.len()).rev() {
+                path.push(PathItem::Index(i as isize));
+                changes.push(change(path, Some(old_v), None));
+                path.pop();
+            }
+            for (i, new_v) in new.iter().enumerate().skip(old.len()) {
+                path.push(PathItem::Index(i as isize));
+                changes.push(change(path, None, Some(new_v)));
+                path.pop();
+            }
+        }
+        _ => {
+            if old != new {
+                changes.push(change(path, Some(old), Some(new)));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    use crate::apply_patch;
+    use crate::error::Result;
+
+    #[test]
+    fn diff_changes() -> Result<()> {
+        let old = json!({
+            "name": "foo",
+            "tags": ["a", "b", "c"],
+            "nested": { "x": 1, "y": [1, 2] },
+            "type": { "a": 1 },
+            "gone": null,
+        });
+        let new = json!({
+            "name": "bar",
+            "tags": ["a"],
+            "nested": { "x": 1, "y": [1, 2, { "z": 3 }], "w": true },
+            "type": [1],
+            "new.key": 0,
+        });
+
+        let changes = diff(&old, &new);
+        let paths: Vec<String> = changes.iter().map(Change::normalized_path).collect();
+        assert_eq!(paths, vec![
+            "$['gone']",
+            "$['name']",
+            "$['nested']['y'][2]",
+            "$['nested']['w']",
+            "$['tags'][2]",
+            "$['tags'][1]",
+            "$['type']",
+            "$['new.key']",
+        ]);
+
+        assert_eq!(changes[0].old, Some(Value::Null));
+        assert_eq!(changes[0].new, None);
+        assert_eq!(changes[1].selector().get(&old), Some(&json!("foo")));
+        assert_eq!(changes[1].selector().get(&new), changes[1].new.as_ref());
+        assert_eq!(changes[6].new, Some(json!([1])));
+
+        let patch = diff_patch(&old, &new);
+        assert_eq!(patch[0], PatchOp::Remove { path: "/gone".into() });
+        assert_eq!(patch[7], PatchOp::Add { path: "/new.key".into(), value: json!(0) });
+
+        let mut patched = old.clone();
+        apply_patch(&mut patched, &patch)?;
+        assert_eq!(patched, new);
+
+        assert!(diff(&new, &new).is_empty());
+
+        Ok(())
+    }
+}
@@ -1,45 +1,209 @@
-use std::num::ParseIntError;
+use std::fmt;
+use std::ops::Range;
 
-use pest::error::Error as PestError;
+use pest::RuleType;
+use pest::error::{Error as PestError, InputLocation};
+use serde_json::{Value, Error as JsonError};
 
-use crate::parser::Rule;
-
-use failure::Fail;
+use thiserror::Error;
 pub type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Fail, Debug)]
+#[derive(Error, Debug)]
 pub enum Error {
-    #[fail(display = "The next item of pairs does not exist")]
+    #[error("The next item of pairs does not exist")]
     PairsNextItemError,
 
-    #[fail(display = "{}", _0)]
-    ParseIntError(#[fail(cause)] ParseIntError),
+    #[error("{0}")]
+    SyntaxError(#[from] SyntaxError),
 
-    #[fail(display = "{}", _0)]
-    PestError(#[fail(cause)] PestError<Rule>),
+    #[error("{0}")]
+    JsonError(#[from] JsonError),
 
-    #[fail(display = "The value is not a JSON map")]
-    NotJsonMapError,
+    #[error("{0}")]
+    DeserializeError(Box<dyn std::error::Error + Send + Sync>),
 
-    #[fail(display = "The value is not a JSON array")]
-    NotJsonArrayError,
+    #[error("Expected {expected} at '{path}', but found {actual}")]
+    TypeMismatchError {
+        path: String,
+        expected: JsonType,
+        actual: JsonType,
+    },
 
-    #[fail(display = "The key '{}' is invalid for JSON object", _0)]
+    #[error("The key '{0}' is invalid for JSON object")]
     JsonInvalidKeyError(String),
 
-    #[fail(display = "The index '{}' is invalid for JSON array", _0)]
-    JsonInvalidArrayIndexError(isize),
+    #[error("The index '{index}' at '{path}' is invalid for JSON array")]
+    JsonInvalidArrayIndexError {
+        path: String,
+        index: isize,
+    },
+
+    #[error("The JSONPath may match more than one node")]
+    NotSingularPathError,
+
+    #[error("Filters referring to the root node '$' cannot be streamed")]
+    StreamRootFilterError,
+
+    #[error("The JSON Pointer '{0}' is invalid")]
+    InvalidPointerError(String),
+
+    #[error("The JSON Pointer '{0}' refers to a nonexistent value")]
+    PointerNotFoundError(String),
+
+    #[error("The value at '{0}' does not pass the test")]
+    PatchTestError(String),
+
+    #[error("Cannot move the value at '{0}' into its child '{1}'")]
+    PatchMoveError(String, String),
 }
 
-macro_rules! impl_from {
-    ($e:ident $(<$t:ty>)*) => {
-        impl From<$e $(<$t>)*> for Error {
-            fn from(e: $e $(<$t>)*) -> Self {
-                Error::$e(e)
-            }
+/// An error in a JSONPath expression, located by the byte span of the offending part.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub expression: String,
+    pub span: Range<usize>,
+    pub message: String,
+}
+
+impl SyntaxError {
+    pub(crate) fn new(expression: &str, span: Range<usize>, message: String) -> Self {
+        Self {
+            expression: expression.to_owned(),
+            span,
+            message,
+        }
+    }
+
+    /// Converts a pest error, naming the expected rules after their identifiers in the grammar.
+    pub(crate) fn from_pest<R: RuleType>(e: PestError<R>, expression: &str) -> Self {
+        let span = match e.location {
+            InputLocation::Pos(pos) => pos..pos,
+            InputLocation::Span((start, end)) => start..end,
+        };
+        let e = e.renamed_rules(|rule| format!("{:?}", rule).replace('_', " "));
+        let mut message = e.variant.message().into_owned();
+        if let Some(first) = message.get(..1) {
+            message.replace_range(..1, &first.to_uppercase());
+        }
+        Self::new(expression, span, message)
+    }
+
+    /// Returns the part of the expression before the error.
+    pub fn prefix(&self) -> &str {
+        &self.expression[..self.span.start]
+    }
+
+    /// Renders the line of the expression containing the error, with carets under the span:
+    ///
+    /// ```text
+    /// foo.[bar]
+    ///     ^
+    /// ```
+    pub fn render(&self) -> String {
+        let line_start = self.prefix().rfind('\n').map_or(0, |i| i + 1);
+        let line_end = self.expression[line_start..]
+            .find('\n')
+            .map_or(self.expression.len(), |i| line_start + i);
+        let line = &self.expression[line_start..line_end];
+
+        let indent = self.expression[line_start..self.span.start].chars().count();
+        let width = self.expression[self.span.start..self.span.end.min(line_end)]
+            .chars()
+            .count()
+            .max(1);
+        format!("{}\n{}{}", line, " ".repeat(indent), "^".repeat(width))
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at byte {}\n{}", self.message, self.span.start, self.render())
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+/// The type of a JSON value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JsonType {
+    Null,
+    Bool,
+    Number,
+    String,
+    Array,
+    Object,
+}
+
+impl JsonType {
+    pub fn of(value: &Value) -> Self {
+        match value {
+            Value::Null => JsonType::Null,
+            Value::Bool(_) => JsonType::Bool,
+            Value::Number(_) => JsonType::Number,
+            Value::String(_) => JsonType::String,
+            Value::Array(_) => JsonType::Array,
+            Value::Object(_) => JsonType::Object,
         }
-    };
+    }
 }
 
-impl_from!(ParseIntError);
-impl_from!(PestError<Rule>);
+impl fmt::Display for JsonType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            JsonType::Null => "null",
+            JsonType::Bool => "a boolean",
+            JsonType::Number => "a number",
+            JsonType::String => "a string",
+            JsonType::Array => "an array",
+            JsonType::Object => "an object",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{Selector, Mode};
+    use super::*;
+
+    fn syntax_error(expression: &str, mode: Mode) -> SyntaxError {
+        match Selector::with_mode(expression, mode) {
+            Err(Error::SyntaxError(e)) => e,
+            _ => panic!("'{}' should be a syntax error", expression),
+        }
+    }
+
+    #[test]
+    fn error_diagnostics() {
+        let e = syntax_error("foo.[bar]", Mode::Lenient);
+        assert_eq!(e.span, 4..4);
+        assert_eq!(e.prefix(), "foo.");
+        assert_eq!(e.render(), "foo.[bar]\n    ^");
+
+        let e = syntax_error("$.a[?foo(@.b) == 1]", Mode::Rfc9535);
+        assert_eq!(e.span, 5..8);
+        assert_eq!(e.message, "Unknown function 'foo'");
+        assert_eq!(e.render(), "$.a[?foo(@.b) == 1]\n     ^^^");
+        assert_eq!(
+            e.to_string(),
+            "Unknown function 'foo' at byte 5\n$.a[?foo(@.b) == 1]\n     ^^^"
+        );
+
+        let e = syntax_error("$['a',\n  'é\\x']", Mode::Rfc9535);
+        assert_eq!(e.render(), "  'é\\x']\n  ^");
+
+        let e = syntax_error("$[?@.a == 'b\\q']", Mode::Lenient);
+        assert_eq!(e.span, 11..14);
+
+        let mut data = json!({ "foo": [1, { "bar": true }] });
+        match Selector::new("foo[1].bar.baz").unwrap().set(&mut data, json!(0)) {
+            Err(Error::TypeMismatchError { path, expected, actual }) => {
+                assert_eq!(path, "$['foo'][1]['bar']");
+                assert_eq!(expected, JsonType::Object);
+                assert_eq!(actual, JsonType::Bool);
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Writing files in place without ever leaving them half-written.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Tells apart the temporary files of the concurrent writes in the process.
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Replaces the content of the file atomically. The content is written and synced to a temporary
+/// file next to it, which takes the permissions of the existing file and is then renamed over it.
+pub fn write_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_owned();
+    let counter = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    temp_name.push(format!(".{}.{}.tmp", std::process::id(), counter));
+    let temp_path = path.with_file_name(temp_name);
+
+    let write = || -> io::Result<()> {
+        let mut file = File::create(&temp_path)?;
+        match fs::metadata(path) {
+            Ok(metadata) => file.set_permissions(metadata.permissions())?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+        file.write_all(content)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+    };
+    write().inspect_err(|_| {
+        let _ = fs::remove_file(&temp_path);
+    })?;
+
+    // Makes the rename durable as well.
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn atomic_write() -> io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("jsonpath-file-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("a.json");
+
+        write_atomically(&path, b"1")?;
+        assert_eq!(fs::read(&path)?, b"1");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+            write_atomically(&path, b"2")?;
+            assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+        }
+        write_atomically(&path, b"3")?;
+        assert_eq!(fs::read(&path)?, b"3");
+        assert_eq!(fs::read_dir(&dir)?.count(), 1);
+
+        assert!(write_atomically(&dir.join("missing/a.json"), b"").is_err());
+
+        // The concurrent writes to the same file do not share a temporary file.
+        let writers: Vec<_> = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || write_atomically(&path, &[b'0' + i; 4096]))
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap()?;
+        }
+        let content = fs::read(&path)?;
+        assert!(content.len() == 4096 && content.iter().all(|&b| b == content[0]));
+        assert_eq!(fs::read_dir(&dir)?.count(), 1);
+        fs::remove_dir_all(&dir)
+    }
+}
@@ -0,0 +1,290 @@
+//! Filter expressions, i.e., the predicate part of `[?(...)]`.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+use serde_json::Value;
+
+use crate::{JsonLike, PathItem, locate};
+use crate::function::Function;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterExpr {
+    Or(Box<FilterExpr>, Box<FilterExpr>),
+    And(Box<FilterExpr>, Box<FilterExpr>),
+    Not(Box<FilterExpr>),
+    /// `@.foo`, `$.foo` or a function call. A path is true if it matches at least one node, and a
+    /// function call is true if it returns `true` or a non-empty list of nodes.
+    Test(Operand),
+    Compare(Operand, Comparison, Operand),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Literal(Value),
+    /// A path relative to the current node `@`. When compared, it only yields a value if exactly
+    /// one node is matched.
+    Current(Vec<PathItem>),
+    /// A path relative to the root node `$`.
+    Root(Vec<PathItem>),
+    Function(Function, Vec<Operand>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// The result of evaluating an operand, corresponding to the three types in RFC 9535.
+pub(crate) enum Evaluated<'a, V> {
+    Nodes(Vec<&'a V>),
+    /// `None` stands for "Nothing", e.g., the value of a path that matches no node.
+    Value(Option<Item<'a, V>>),
+    Logical(bool),
+}
+
+/// A value yielded by an operand, which is either a node of the document or a JSON value, e.g., a
+/// literal or the result of a function.
+pub(crate) enum Item<'a, V> {
+    Node(&'a V),
+    Json(Cow<'a, Value>),
+}
+
+impl FilterExpr {
+    /// Tests the current node against the filter.
+    pub fn test<V: JsonLike>(&self, root: &V, current: &V) -> bool {
+        match self {
+            FilterExpr::Or(lhs, rhs) => lhs.test(root, current) || rhs.test(root, current),
+            FilterExpr::And(lhs, rhs) => lhs.test(root, current) && rhs.test(root, current),
+            FilterExpr::Not(expr) => !expr.test(root, current),
+            FilterExpr::Test(operand) => {
+                match operand.evaluate(root, current) {
+                    Evaluated::Nodes(nodes) => !nodes.is_empty(),
+                    Evaluated::Logical(b) => b,
+                    Evaluated::Value(_) => false,
+                }
+            }
+            FilterExpr::Compare(lhs, op, rhs) => {
+                let lhs = lhs.evaluate(root, current).into_value();
+                let rhs = rhs.evaluate(root, current).into_value();
+                compare(lhs.as_ref(), *op, rhs.as_ref())
+            }
+        }
+    }
+}
+
+impl FilterExpr {
+    pub(crate) fn refers_to_root(&self) -> bool {
+        match self {
+            FilterExpr::Or(lhs, rhs) | FilterExpr::And(lhs, rhs) => {
+                lhs.refers_to_root() || rhs.refers_to_root()
+            }
+            FilterExpr::Not(expr) => expr.refers_to_root(),
+            FilterExpr::Test(operand) => operand.refers_to_root(),
+            FilterExpr::Compare(lhs, _op, rhs) => lhs.refers_to_root() || rhs.refers_to_root(),
+        }
+    }
+}
+
+impl Operand {
+    /// Returns `true` if the operand is a literal or a path that can select at most one node.
+    pub fn is_singular(&self) -> bool {
+        match self {
+            Operand::Literal(_) => true,
+            Operand::Current(json_path) | Operand::Root(json_path) => {
+                json_path.iter().all(PathItem::is_singular)
+            }
+            Operand::Function(..) => false,
+        }
+    }
+
+    fn refers_to_root(&self) -> bool {
+        match self {
+            Operand::Literal(_) => false,
+            Operand::Current(json_path) => json_path.iter().any(PathItem::refers_to_root),
+            Operand::Root(_) => true,
+            Operand::Function(_, args) => args.iter().any(Operand::refers_to_root),
+        }
+    }
+
+    pub(crate) fn evaluate<'a, V>(&'a self, root: &'a V, current: &'a V) -> Evaluated<'a, V>
+        where V: JsonLike
+    {
+        let select = |json_path, value| {
+            locate(json_path, root, value)
+                .into_iter()
+                .map(|(_path, v)| v)
+                .collect()
+        };
+
+        match self {
+            Operand::Literal(v) => Evaluated::Value(Some(Item::Json(Cow::Borrowed(v)))),
+            Operand::Current(json_path) => Evaluated::Nodes(select(json_path, current)),
+            Operand::Root(json_path) => Evaluated::Nodes(select(json_path, root)),
+            Operand::Function(function, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.evaluate(root, current))
+                    .collect();
+                function.call(args)
+            }
+        }
+    }
+}
+
+impl<'a, V> Evaluated<'a, V> {
+    /// Converts the result into a single value. A list of nodes only converts to a value if it
+    /// contains exactly one node.
+    pub(crate) fn into_value(self) -> Option<Item<'a, V>> {
+        match self {
+            Evaluated::Nodes(mut nodes) => {
+                if nodes.len() == 1 {
+                    nodes.pop().map(Item::Node)
+                } else {
+                    None
+                }
+            }
+            Evaluated::Value(v) => v,
+            Evaluated::Logical(_) => None,
+        }
+    }
+}
+
+impl<V: JsonLike> Item<'_, V> {
+    /// Converts the value into JSON if it is a scalar, see `JsonLike::scalar_to_json`.
+    pub(crate) fn scalar_to_json(&self) -> Option<Cow<'_, Value>> {
+        match self {
+            Item::Node(v) => v.scalar_to_json(),
+            Item::Json(v) => v.scalar_to_json(),
+        }
+    }
+}
+
+/// Compares two operands, where `None` stands for an operand that yields nothing. Only numbers and
+/// strings are ordered; any other combination is only comparable with `==` and `!=`.
+fn compare<V: JsonLike>(lhs: Option<&Item<'_, V>>, op: Comparison, rhs: Option<&Item<'_, V>>)
+    -> bool
+{
+    match op {
+        Comparison::Eq => equals(lhs, rhs),
+        Comparison::Ne => !equals(lhs, rhs),
+        Comparison::Lt => less_than(lhs, rhs),
+        Comparison::Le => less_than(lhs, rhs) || equals(lhs, rhs),
+        Comparison::Gt => less_than(rhs, lhs),
+        Comparison::Ge => less_than(rhs, lhs) || equals(lhs, rhs),
+    }
+}
+
+fn equals<V: JsonLike>(lhs: Option<&Item<'_, V>>, rhs: Option<&Item<'_, V>>) -> bool {
+    match (lhs, rhs) {
+        (None, None) => true,
+        (Some(Item::Node(x)), Some(Item::Node(y))) => json_equals(*x, *y),
+        (Some(Item::Node(x)), Some(Item::Json(y))) => json_equals(*x, y.as_ref()),
+        (Some(Item::Json(x)), Some(Item::Node(y))) => json_equals(x.as_ref(), *y),
+        (Some(Item::Json(x)), Some(Item::Json(y))) => json_equals(x.as_ref(), y.as_ref()),
+        _ => false,
+    }
+}
+
+fn less_than<V: JsonLike>(lhs: Option<&Item<'_, V>>, rhs: Option<&Item<'_, V>>) -> bool {
+    let lhs = lhs.and_then(Item::scalar_to_json);
+    let rhs = rhs.and_then(Item::scalar_to_json);
+    match (lhs.as_deref(), rhs.as_deref()) {
+        (Some(x @ Value::Number(_)), Some(y @ Value::Number(_))) |
+        (Some(x @ Value::String(_)), Some(y @ Value::String(_))) => {
+            json_cmp(x, y) == Some(Ordering::Less)
+        }
+        _ => false,
+    }
+}
+
+/// Deep equality, in which numbers are compared by their numeric values, so `1 == 1.0`. The values
+/// may be of different backends.
+pub(crate) fn json_equals<A: JsonLike, B: JsonLike>(lhs: &A, rhs: &B) -> bool {
+    if let (Some(a), Some(b)) = (lhs.elements(), rhs.elements()) {
+        return a.len() == b.len() && a.iter().zip(b).all(|(x, y)| json_equals(x, y));
+    }
+    if let (Some(mut a), Some(b)) = (lhs.members(), rhs.members()) {
+        let mut len = 0;
+        let all_equal = a.all(|(k, x)| {
+            len += 1;
+            rhs.member(k).is_some_and(|y| json_equals(x, y))
+        });
+        return all_equal && len == b.count();
+    }
+    match (lhs.scalar_to_json().as_deref(), rhs.scalar_to_json().as_deref()) {
+        (Some(x @ Value::Number(_)), Some(y @ Value::Number(_))) => {
+            json_cmp(x, y) == Some(Ordering::Equal)
+        }
+        (Some(x), Some(y)) => x == y,
+        _ => false,
+    }
+}
+
+fn json_cmp(lhs: &Value, rhs: &Value) -> Option<Ordering> {
+    match (lhs, rhs) {
+        (Value::Number(a), Value::Number(b)) => {
+            if let (Some(a), Some(b)) = (a.as_i64(), b.as_i64()) {
+                Some(a.cmp(&b))
+            } else if let (Some(a), Some(b)) = (a.as_u64(), b.as_u64()) {
+                Some(a.cmp(&b))
+            } else {
+                a.as_f64()?.partial_cmp(&b.as_f64()?)
+            }
+        }
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn json(value: &Value) -> Item<'_, Value> {
+        Item::Json(Cow::Borrowed(value))
+    }
+
+    #[test]
+    fn filter_compare() {
+        use Comparison::*;
+
+        let cases = vec![
+            (json!(1), Eq, json!(1.0), true),
+            (json!(1), Lt, json!(2), true),
+            (json!(-1), Lt, json!(18446744073709551615u64), true),
+            (json!("a"), Lt, json!("b"), true),
+            (json!("a"), Lt, json!(1), false),
+            (json!("a"), Ne, json!(1), true),
+            (json!(true), Le, json!(true), true),
+            (json!(true), Lt, json!(false), false),
+            (json!([1, {"a": 2}]), Eq, json!([1.0, {"a": 2}]), true),
+            (json!({"a": 1}), Eq, json!({"a": 1, "b": 2}), false),
+            (json!(null), Ge, json!(null), true),
+        ];
+
+        for (lhs, op, rhs, expected) in cases {
+            assert_eq!(
+                compare(Some(&Item::Node(&lhs)), op, Some(&json(&rhs))),
+                expected,
+                "{} {:?} {}", lhs, op, rhs
+            );
+            assert_eq!(compare(Some(&json(&lhs)), op, Some(&Item::Node(&rhs))), expected);
+        }
+
+        // Nothing only equals to nothing.
+        let none: Option<&Item<'_, Value>> = None;
+        assert!(compare(none, Eq, none));
+        assert!(compare(none, Le, none));
+        assert!(!compare(none, Lt, none));
+        assert!(!compare(Some(&json(&json!(null))), Eq, none));
+        assert!(compare(Some(&json(&json!(null))), Ne, none));
+    }
+}
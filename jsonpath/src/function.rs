@@ -0,0 +1,236 @@
+//! Function extensions of filter expressions defined in RFC 9535.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::JsonLike;
+use crate::filter::{Evaluated, Item};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    /// `length(value)`, the length of a string, an array or an object.
+    Length,
+    /// `count(nodes)`, the number of nodes.
+    Count,
+    /// `match(string, regex)`, whether the whole string matches the I-Regexp.
+    Match,
+    /// `search(string, regex)`, whether a substring matches the I-Regexp.
+    Search,
+    /// `value(nodes)`, the value of the only node.
+    Value,
+}
+
+/// The declared types of function parameters and results, see RFC 9535, Section 2.4.1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FunctionType {
+    Value,
+    Logical,
+    Nodes,
+}
+
+impl Function {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "length" => Function::Length,
+            "count" => Function::Count,
+            "match" => Function::Match,
+            "search" => Function::Search,
+            "value" => Function::Value,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Function::Length => "length",
+            Function::Count => "count",
+            Function::Match => "match",
+            Function::Search => "search",
+            Function::Value => "value",
+        }
+    }
+
+    pub fn parameter_types(self) -> &'static [FunctionType] {
+        match self {
+            Function::Length => &[FunctionType::Value],
+            Function::Count | Function::Value => &[FunctionType::Nodes],
+            Function::Match | Function::Search => &[FunctionType::Value, FunctionType::Value],
+        }
+    }
+
+    pub fn result_type(self) -> FunctionType {
+        match self {
+            Function::Length | Function::Count | Function::Value => FunctionType::Value,
+            Function::Match | Function::Search => FunctionType::Logical,
+        }
+    }
+
+    pub(crate) fn call<'a, V>(self, mut args: Vec<Evaluated<'a, V>>) -> Evaluated<'a, V>
+        where V: JsonLike
+    {
+        let number = |n: usize| Evaluated::Value(Some(Item::Json(Cow::Owned(Value::from(n)))));
+
+        match self {
+            Function::Length => {
+                let length = match args.pop().and_then(Evaluated::into_value) {
+                    Some(Item::Node(v)) => length_of(v),
+                    Some(Item::Json(v)) => length_of(v.as_ref()),
+                    None => None,
+                };
+                length.map_or(Evaluated::Value(None), number)
+            }
+
+            Function::Count => {
+                match args.pop() {
+                    Some(Evaluated::Nodes(nodes)) => number(nodes.len()),
+                    _ => Evaluated::Value(None),
+                }
+            }
+
+            Function::Match | Function::Search => {
+                let regex = args.pop().and_then(Evaluated::into_value);
+                let s = args.pop().and_then(Evaluated::into_value);
+                let regex = regex.as_ref().and_then(Item::scalar_to_json);
+                let s = s.as_ref().and_then(Item::scalar_to_json);
+                let is_match = match (s.as_deref(), regex.as_deref()) {
+                    (Some(Value::String(s)), Some(Value::String(regex))) => {
+                        cached_iregexp(regex, self == Function::Match)
+                            .is_some_and(|regex| regex.is_match(s))
+                    }
+                    _ => false,
+                };
+                Evaluated::Logical(is_match)
+            }
+
+            Function::Value => {
+                Evaluated::Value(args.pop().and_then(Evaluated::into_value))
+            }
+        }
+    }
+}
+
+/// The length of a string in characters, or the number of elements or members.
+fn length_of<V: JsonLike>(value: &V) -> Option<usize> {
+    if let Some(a) = value.elements() {
+        Some(a.len())
+    } else if let Some(m) = value.members() {
+        Some(m.count())
+    } else {
+        value.scalar_to_json()?.as_str().map(|s| s.chars().count())
+    }
+}
+
+/// The number of the translated I-Regexps cached by each thread.
+const REGEX_CACHE_SIZE: usize = 16;
+
+thread_local! {
+    // The I-Regexps with their `full_match` flags and translations, the most recently used first.
+    static REGEX_CACHE: RefCell<VecDeque<(String, bool, Option<Regex>)>> =
+        RefCell::new(VecDeque::with_capacity(REGEX_CACHE_SIZE + 1));
+}
+
+/// `translate_iregexp` with a small LRU cache, as a filter usually applies the same literal
+/// pattern to every node, which would otherwise be compiled again for each of them.
+fn cached_iregexp(iregexp: &str, full_match: bool) -> Option<Regex> {
+    REGEX_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        let position = cache.iter().position(|(s, f, _)| s == iregexp && *f == full_match);
+        let entry = match position.and_then(|i| cache.remove(i)) {
+            Some(entry) => entry,
+            None => (iregexp.to_owned(), full_match, translate_iregexp(iregexp, full_match)),
+        };
+        // Cloning a `Regex` only bumps a reference count.
+        let regex = entry.2.clone();
+        cache.push_front(entry);
+        cache.truncate(REGEX_CACHE_SIZE);
+        regex
+    })
+}
+
+/// Translates an I-Regexp (RFC 9485) into a `Regex`, in which `.` does not match line breaks.
+/// Returns `None` for an invalid pattern.
+fn translate_iregexp(iregexp: &str, full_match: bool) -> Option<Regex> {
+    let mut pattern = String::with_capacity(iregexp.len() + 8);
+    if full_match {
+        pattern.push_str(r"\A(?:");
+    }
+
+    let mut in_class = false;
+    let mut chars = iregexp.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                pattern.push(c);
+                pattern.push(chars.next()?);
+            }
+            '[' if !in_class => {
+                in_class = true;
+                pattern.push(c);
+            }
+            ']' if in_class => {
+                in_class = false;
+                pattern.push(c);
+            }
+            '.' if !in_class => pattern.push_str(r"[^\n\r]"),
+            _ => pattern.push(c),
+        }
+    }
+
+    if full_match {
+        pattern.push_str(r")\z");
+    }
+    Regex::new(&pattern).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iregexp() {
+        let is_match = |regex, s| translate_iregexp(regex, true).unwrap().is_match(s);
+        let is_found = |regex, s| translate_iregexp(regex, false).unwrap().is_match(s);
+
+        assert!(is_match("a.c", "abc"));
+        assert!(!is_match("a.c", "a\nc"));
+        assert!(!is_match("a.c", "abcd"));
+        assert!(is_match("a[.]c", "a.c"));
+        assert!(!is_match("a[.]c", "abc"));
+        assert!(is_match(r"a\.c", "a.c"));
+        assert!(is_match("a|b", "b"));
+        assert!(is_match(r"\p{Lu}+", "ABC"));
+
+        assert!(is_found("b.", "abc"));
+        assert!(is_found("^a", "abc"));
+        assert!(!is_found("^b", "abc"));
+
+        assert!(translate_iregexp("[a", true).is_none());
+    }
+
+    #[test]
+    fn iregexp_cache() {
+        let cached = || REGEX_CACHE.with(|c| c.borrow().iter().map(|e| e.0.clone()).collect());
+        REGEX_CACHE.with(|c| c.borrow_mut().clear());
+
+        assert!(cached_iregexp("a.c", true).unwrap().is_match("abc"));
+        assert!(cached_iregexp("a.c", false).unwrap().is_match("abcd"));
+        assert!(!cached_iregexp("a.c", true).unwrap().is_match("abcd"));
+        assert!(cached_iregexp("[a", true).is_none());
+        assert!(cached_iregexp("[a", true).is_none());
+        assert_eq!(cached(), vec!["[a", "a.c", "a.c"]);
+
+        // The least recently used ones are evicted.
+        for i in 0..REGEX_CACHE_SIZE - 1 {
+            cached_iregexp(&i.to_string(), true);
+        }
+        cached_iregexp("a.c", true);
+        let patterns: Vec<String> = cached();
+        assert_eq!(patterns.len(), REGEX_CACHE_SIZE);
+        assert_eq!(patterns[0], "a.c");
+        assert!(!patterns.iter().any(|p| p == "[a"));
+    }
+}
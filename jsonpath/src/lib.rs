@@ -1,21 +1,84 @@
 #[macro_use]
 extern crate pest_derive;
 
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::iter::FromIterator;
 use std::mem;
 
+use serde::de::DeserializeOwned;
 use serde_json::{Value, Map, map::Entry};
 
 mod parser;
+mod filter;
+mod function;
+mod stream;
+mod pointer;
+mod patch;
+mod diff;
+mod backend;
 pub mod error;
 
-use crate::parser::parse;
-use crate::error::{Error, Result};
+pub use crate::filter::{FilterExpr, Operand, Comparison};
+pub use crate::function::{Function, FunctionType};
+pub use crate::patch::{PatchOp, apply_patch};
+pub use crate::diff::{Change, diff, diff_patch};
+pub use crate::backend::JsonLike;
+pub use crate::pointer::escape_pointer_token;
+use crate::parser::{parse, parse_rfc9535};
+use crate::pointer::parse_array_index;
+use crate::error::{Error, JsonType, Result};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum PathItem {
     Child(String),
     Index(isize),
+    /// A JSON Pointer reference token, which selects the member of that name of an object, or the
+    /// element of an array if the token is an array index. Only created by `from_pointer`.
+    Token(String),
+    /// `*` or `[*]`, selects all the members of an object or all the elements of an array.
+    Wildcard,
+    /// `..`, selects the current node and all of its descendants. It is always followed by another
+    /// item which is then applied to each of them.
+    RecursiveDescent,
+    /// `[start:end:step]`, selects array elements in the same way as Python's slices.
+    Slice {
+        start: Option<isize>,
+        end: Option<isize>,
+        step: Option<isize>,
+    },
+    /// `[?(...)]`, selects the members of an object or the elements of an array that satisfy the
+    /// filter expression.
+    Filter(FilterExpr),
+    /// `[a, b, ...]`, concatenates the nodes selected by each of the items. It is only available in
+    /// RFC 9535 mode.
+    Union(Vec<PathItem>),
+}
+
+impl PathItem {
+    /// Returns `true` if the item can select at most one node.
+    pub fn is_singular(&self) -> bool {
+        matches!(self, PathItem::Child(_) | PathItem::Index(_) | PathItem::Token(_))
+    }
+
+    /// Returns `true` if the item contains a filter that refers to the root node `$`.
+    pub(crate) fn refers_to_root(&self) -> bool {
+        match self {
+            PathItem::Filter(filter) => filter.refers_to_root(),
+            PathItem::Union(items) => items.iter().any(PathItem::refers_to_root),
+            _ => false,
+        }
+    }
+}
+
+/// The syntax accepted by the parser.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// The original syntax of this crate, which allows omitting the leading `$`, bare keys in
+    /// brackets (`[foo]`) and keys such as `-` or `-100u`.
+    Lenient,
+    /// Strictly follows RFC 9535, including function extensions and unions (`[a, b]`).
+    Rfc9535,
 }
 
 pub struct Selector {
@@ -24,27 +87,70 @@ pub struct Selector {
 
 impl Selector {
     pub fn new(expression: &str) -> Result<Self> {
+        Self::with_mode(expression, Mode::Lenient)
+    }
+
+    pub fn with_mode(expression: &str, mode: Mode) -> Result<Self> {
+        let json_path = match mode {
+            Mode::Lenient => parse(expression)?,
+            Mode::Rfc9535 => parse_rfc9535(expression)?,
+        };
         Ok(Self {
-            json_path: parse(expression)?,
+            json_path,
         })
     }
 
-    pub fn get<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+    /// Returns `true` if the JSONPath can match at most one node, i.e., it only consists of
+    /// children and indices.
+    pub fn is_singular(&self) -> bool {
+        self.json_path.iter().all(PathItem::is_singular)
+    }
+
+    /// Returns all the nodes matched by the JSONPath, in document order.
+    pub fn select<'a, V: JsonLike>(&self, value: &'a V) -> Vec<&'a V> {
+        self.locate(value)
+            .into_iter()
+            .map(|(_path, v)| v)
+            .collect()
+    }
+
+    /// Returns the distinct nodes matched by the JSONPath in document order, which are the nodes
+    /// yielded by `stream`. Unlike `select`, a node matched in several ways is returned once.
+    pub fn select_distinct<'a, V: JsonLike>(&self, value: &'a V) -> Vec<&'a V> {
+        let mut nodes = self.locate(value);
+        nodes.sort_by(|(a, _), (b, _)| cmp_path(a, b));
+        nodes.dedup_by(|(a, _), (b, _)| a == b);
+        nodes.into_iter().map(|(_path, v)| v).collect()
+    }
+
+    /// Returns the normalized paths (RFC 9535, Section 2.7) of all the nodes matched by the
+    /// JSONPath, e.g., `$['foo'][0]`.
+    pub fn select_paths<V: JsonLike>(&self, value: &V) -> Vec<String> {
+        self.locate(value)
+            .into_iter()
+            .map(|(path, _v)| normalized_path(&path))
+            .collect()
+    }
+
+    /// Returns the first node matched by the JSONPath.
+    pub fn get<'a, V: JsonLike>(&self, value: &'a V) -> Option<&'a V> {
+        if !self.is_singular() {
+            return self.select(value).into_iter().next();
+        }
+
         let mut curr = Some(value);
         for item in &self.json_path {
             curr = match curr {
                 Some(curr) => {
                     match item {
-                        PathItem::Child(ident) => {
-                            curr
-                                .as_object()
-                                .and_then(|m| m.get(ident))
-                        }
+                        PathItem::Child(ident) => curr.member(ident),
                         PathItem::Index(index) => {
                             curr
-                                .as_array()
+                                .elements()
                                 .and_then(|a| signed_get(a, *index))
                         }
+                        PathItem::Token(token) => token_get(curr, token),
+                        _ => unreachable!("The JSONPath is singular"),
                     }
                 }
                 None => return None,
@@ -54,24 +160,28 @@ impl Selector {
         curr
     }
 
-    fn json_path_get_mut<'a>(json_path: &[PathItem], value: &'a mut Value)
-        -> Option<&'a mut Value>
+    /// Deserializes the first node matched by the JSONPath into `T`. Returns `None` if no node is
+    /// matched.
+    pub fn get_as<T: DeserializeOwned>(&self, value: &impl JsonLike) -> Result<Option<T>> {
+        self.get(value).map(JsonLike::deserialize_into).transpose()
+    }
+
+    fn json_path_get_mut<'a, V: JsonLike>(json_path: &[PathItem], value: &'a mut V)
+        -> Option<&'a mut V>
     {
         let mut curr = Some(value);
         for item in json_path {
             curr = match curr {
                 Some(curr) => {
                     match item {
-                        PathItem::Child(ident) => {
-                            curr
-                                .as_object_mut()
-                                .and_then(|m| m.get_mut(ident))
-                        }
+                        PathItem::Child(ident) => curr.member_mut(ident),
                         PathItem::Index(index) => {
                             curr
-                                .as_array_mut()
+                                .elements_mut()
                                 .and_then(|a| signed_get_mut(a, *index))
                         }
+                        PathItem::Token(token) => token_get_mut(curr, token),
+                        _ => return None,
                     }
                 }
                 None => return None,
@@ -81,14 +191,41 @@ impl Selector {
         curr
     }
 
-    pub fn get_mut<'a>(&self, value: &'a mut Value) -> Option<&'a mut Value> {
-        Self::json_path_get_mut(&self.json_path, value)
+    /// Returns the first node matched by the JSONPath as mutable.
+    pub fn get_mut<'a, V: JsonLike>(&self, value: &'a mut V) -> Option<&'a mut V> {
+        if self.is_singular() {
+            Self::json_path_get_mut(&self.json_path, value)
+        } else {
+            let (path, _) = self.locate(value).into_iter().next()?;
+            Self::json_path_get_mut(&path, value)
+        }
     }
 
+    /// Sets the value of the node addressed by the JSONPath, creating the missing intermediate
+    /// objects and arrays on the way. Returns the old value if the node exists.
+    ///
+    /// The JSONPath must be singular. Unlike the other methods, it only works on JSON values.
     pub fn set(&self, value: &mut Value, new_value: Value) -> Result<Option<Value>> {
+        if !self.is_singular() {
+            return Err(Error::NotSingularPathError);
+        }
+
         let mut curr = value;
         let mut is_missing = false;
-        for item in &self.json_path {
+        for (i, item) in self.json_path.iter().enumerate() {
+            let prefix = || normalized_path(&self.json_path[..i]);
+            // A token is an index on arrays, and also on the missing nodes if it looks like one.
+            let item = match item {
+                PathItem::Token(token) => {
+                    match parse_array_index(token) {
+                        Some(index) if curr.is_array() || curr.is_null() => {
+                            Cow::Owned(PathItem::Index(index as isize))
+                        }
+                        _ => Cow::Owned(PathItem::Child(token.to_owned())),
+                    }
+                }
+                item => Cow::Borrowed(item),
+            };
             // JSON type mismatch is treated as error in `set` method.
             curr = match *item {
                 PathItem::Child(ref ident) => {
@@ -97,9 +234,10 @@ impl Selector {
                         *curr = Value::Object(Map::new());
                     }
 
-                    let m = curr
-                        .as_object_mut()
-                        .ok_or_else(|| Error::NotJsonMapError)?;
+                    let m = match curr {
+                        Value::Object(m) => m,
+                        v => return Err(type_mismatch(prefix(), JsonType::Object, v)),
+                    };
                     match m.entry(ident.to_owned()) {
                         Entry::Occupied(o) => o.into_mut(),
                         Entry::Vacant(v) => {
@@ -113,17 +251,21 @@ impl Selector {
                         *curr = Value::Array(vec![]);
                     }
 
-                    let a = curr
-                        .as_array_mut()
-                        .ok_or_else(|| Error::NotJsonArrayError)?;
+                    let a = match curr {
+                        Value::Array(a) => a,
+                        v => return Err(type_mismatch(prefix(), JsonType::Array, v)),
+                    };
 
                     (0 ..= (index - a.len() as isize)).for_each(|_| a.push(Value::Null));
 
                     match signed_get_mut(a, index) {
                         Some(v) => v,
-                        None => return Err(Error::JsonInvalidArrayIndexError(index)),
+                        None => {
+                            return Err(Error::JsonInvalidArrayIndexError { path: prefix(), index })
+                        }
                     }
                 }
+                _ => unreachable!("The JSONPath is singular"),
             };
         }
 
@@ -135,52 +277,273 @@ impl Selector {
         })
     }
 
-    pub fn remove(&self, value: &mut Value) -> Option<Value> {
-        let last_index = self.json_path.len() - 1;
-        let json_path_but_last = &self.json_path[..last_index];
-        if let Some(last_level) = Self::json_path_get_mut(json_path_but_last, value) {
-            let last_path_item = &self.json_path[last_index];
-            match last_path_item {
-                PathItem::Child(ident) => {
-                    last_level
-                        .as_object_mut()
-                        .and_then(|m| m.remove(ident))
+    /// Removes the first node matched by the JSONPath and returns it.
+    pub fn remove<V: JsonLike>(&self, value: &mut V) -> Option<V> {
+        if self.is_singular() {
+            Self::json_path_remove(&self.json_path, value)
+        } else {
+            let (path, _) = self.locate(value).into_iter().next()?;
+            Self::json_path_remove(&path, value)
+        }
+    }
+
+    /// Replaces every node matched by the JSONPath with `new_value`. Unlike `set`, no missing node
+    /// is created. Returns the number of replaced nodes, not counting the ones inside another
+    /// replaced node.
+    pub fn set_all<V: JsonLike + Clone>(&self, value: &mut V, new_value: V) -> usize {
+        let paths = outermost_paths(self.matched_paths(value));
+        for path in &paths {
+            if let Some(v) = Self::json_path_get_mut(path, value) {
+                *v = new_value.clone();
+            }
+        }
+        paths.len()
+    }
+
+    /// Removes every node matched by the JSONPath and returns them in document order. The nodes
+    /// inside another removed node are not returned separately.
+    pub fn remove_all<V: JsonLike>(&self, value: &mut V) -> Vec<V> {
+        let paths = outermost_paths(self.matched_paths(value));
+        // Removes from the back so that the indices of the remaining array elements are unchanged.
+        let mut removed: Vec<V> = paths
+            .iter()
+            .rev()
+            .filter_map(|path| Self::json_path_remove(path, value))
+            .collect();
+        removed.reverse();
+        removed
+    }
+
+    /// Calls `f` on every node matched by the JSONPath and returns the number of nodes. If a node
+    /// is inside another matched node, the inner one is updated first.
+    pub fn update_with<V, F>(&self, value: &mut V, mut f: F) -> usize
+        where V: JsonLike,
+              F: FnMut(&mut V),
+    {
+        let paths = self.matched_paths(value);
+        for path in paths.iter().rev() {
+            if let Some(v) = Self::json_path_get_mut(path, value) {
+                f(v);
+            }
+        }
+        paths.len()
+    }
+
+    /// Returns the deduplicated locations of all the matched nodes in document order.
+    fn matched_paths<V: JsonLike>(&self, value: &V) -> Vec<Vec<PathItem>> {
+        let mut paths: Vec<Vec<PathItem>> = self
+            .locate(value)
+            .into_iter()
+            .map(|(path, _v)| path)
+            .collect();
+        paths.sort_by(|a, b| cmp_path(a, b));
+        paths.dedup();
+        paths
+    }
+
+    fn json_path_remove<V: JsonLike>(json_path: &[PathItem], value: &mut V) -> Option<V> {
+        let (last_path_item, json_path_but_last) = json_path.split_last()?;
+        let last_level = Self::json_path_get_mut(json_path_but_last, value)?;
+        match last_path_item {
+            PathItem::Child(ident) => last_level.remove_member(ident),
+            PathItem::Index(index) => {
+                last_level
+                    .elements_mut()
+                    .and_then(|a| signed_remove(a, *index))
+            }
+            PathItem::Token(token) => match last_level.elements_mut() {
+                Some(a) => parse_array_index(token).filter(|&i| i < a.len()).map(|i| a.remove(i)),
+                None => last_level.remove_member(token),
+            },
+            _ => None,
+        }
+    }
+
+    fn locate<'a, V: JsonLike>(&self, value: &'a V) -> Vec<(Vec<PathItem>, &'a V)> {
+        locate(&self.json_path, value, value)
+    }
+
+    /// Returns `true` if a filter refers to the root node `$`, which `stream` does not support.
+    pub fn refers_to_root(&self) -> bool {
+        self.json_path.iter().any(PathItem::refers_to_root)
+    }
+}
+
+/// Finds all the nodes matched by the JSONPath starting from `value`, along with their locations
+/// relative to `value`. A location only consists of children and non-negative indices. The `root`
+/// node is used by the filters that refer to `$`.
+pub(crate) fn locate<'a, V: JsonLike>(json_path: &[PathItem], root: &V, value: &'a V)
+    -> Vec<(Vec<PathItem>, &'a V)>
+{
+    let mut curr = vec![(vec![], value)];
+    for item in json_path {
+        let mut next = Vec::with_capacity(curr.len());
+        for (path, v) in curr {
+            step(item, &path, root, v, &mut next);
+        }
+        curr = next;
+    }
+
+    curr
+}
+
+/// Applies a single path item to the node `value` located at `path`, and pushes the selected nodes
+/// into `out`.
+fn step<'a, V: JsonLike>(
+    item: &PathItem,
+    path: &[PathItem],
+    root: &V,
+    value: &'a V,
+    out: &mut Vec<(Vec<PathItem>, &'a V)>,
+) {
+    let child_path = |item: PathItem| {
+        let mut p = path.to_vec();
+        p.push(item);
+        p
+    };
+
+    match item {
+        PathItem::Child(ident) => {
+            if let Some(v) = value.member(ident) {
+                out.push((child_path(PathItem::Child(ident.to_owned())), v));
+            }
+        }
+        PathItem::Index(index) => {
+            if let Some(a) = value.elements() {
+                if let Some(i) = signed_index(*index, a.len()) {
+                    out.push((child_path(PathItem::Index(i as isize)), &a[i]));
                 }
-                PathItem::Index(index) => {
-                    last_level
-                        .as_array_mut()
-                        .and_then(|a| signed_remove(a, *index))
+            }
+        }
+        PathItem::Token(token) => {
+            if let Some(a) = value.elements() {
+                if let Some(i) = parse_array_index(token).filter(|&i| i < a.len()) {
+                    out.push((child_path(PathItem::Index(i as isize)), &a[i]));
                 }
+            } else if let Some(v) = value.member(token) {
+                out.push((child_path(PathItem::Child(token.to_owned())), v));
             }
-        } else {
-            None
         }
+        PathItem::Wildcard => {
+            if let Some(m) = value.members() {
+                for (k, v) in m {
+                    out.push((child_path(PathItem::Child(k.to_owned())), v));
+                }
+            } else if let Some(a) = value.elements() {
+                for (i, v) in a.iter().enumerate() {
+                    out.push((child_path(PathItem::Index(i as isize)), v));
+                }
+            }
+        }
+        PathItem::RecursiveDescent => {
+            descend(path.to_vec(), value, out);
+        }
+        PathItem::Slice { start, end, step } => {
+            if let Some(a) = value.elements() {
+                for i in slice_indices(*start, *end, *step, a.len()) {
+                    out.push((child_path(PathItem::Index(i as isize)), &a[i]));
+                }
+            }
+        }
+        PathItem::Filter(filter) => {
+            if let Some(m) = value.members() {
+                for (k, v) in m.filter(|(_k, v)| filter.test(root, *v)) {
+                    out.push((child_path(PathItem::Child(k.to_owned())), v));
+                }
+            } else if let Some(a) = value.elements() {
+                for (i, v) in a.iter().enumerate().filter(|(_i, v)| filter.test(root, *v)) {
+                    out.push((child_path(PathItem::Index(i as isize)), v));
+                }
+            }
+        }
+        PathItem::Union(items) => {
+            for item in items {
+                step(item, path, root, value, out);
+            }
+        }
+    }
+}
+
+/// Compares two locations in document order, so an ancestor is ordered before its descendants.
+pub(crate) fn cmp_path(a: &[PathItem], b: &[PathItem]) -> Ordering {
+    for (x, y) in a.iter().zip(b) {
+        let ordering = match (x, y) {
+            (PathItem::Child(x), PathItem::Child(y)) => x.cmp(y),
+            (PathItem::Index(x), PathItem::Index(y)) => x.cmp(y),
+            (PathItem::Child(_), _) => Ordering::Less,
+            _ => Ordering::Greater,
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+/// Drops the locations inside another location, given the locations sorted in document order.
+fn outermost_paths(paths: Vec<Vec<PathItem>>) -> Vec<Vec<PathItem>> {
+    let mut outermost: Vec<Vec<PathItem>> = Vec::with_capacity(paths.len());
+    for path in paths {
+        if let Some(last) = outermost.last() {
+            if path.starts_with(last) {
+                continue;
+            }
+        }
+        outermost.push(path);
+    }
+    outermost
+}
+
+/// Pushes `value` and all of its descendants into `out` in pre-order.
+fn descend<'a, V>(path: Vec<PathItem>, value: &'a V, out: &mut Vec<(Vec<PathItem>, &'a V)>)
+    where V: JsonLike
+{
+    out.push((path.clone(), value));
+    if let Some(m) = value.members() {
+        for (k, v) in m {
+            let mut p = path.clone();
+            p.push(PathItem::Child(k.to_owned()));
+            descend(p, v, out);
+        }
+    } else if let Some(a) = value.elements() {
+        for (i, v) in a.iter().enumerate() {
+            let mut p = path.clone();
+            p.push(PathItem::Index(i as isize));
+            descend(p, v, out);
+        }
+    }
+}
+
+/// Resolves a JSON Pointer reference token against an object or an array.
+fn token_get<'a, V: JsonLike>(value: &'a V, token: &str) -> Option<&'a V> {
+    match value.elements() {
+        Some(a) => parse_array_index(token).and_then(|i| a.get(i)),
+        None => value.member(token),
+    }
+}
+
+fn token_get_mut<'a, V: JsonLike>(value: &'a mut V, token: &str) -> Option<&'a mut V> {
+    if value.elements().is_some() {
+        value.elements_mut().and_then(|a| parse_array_index(token).and_then(move |i| a.get_mut(i)))
+    } else {
+        value.member_mut(token)
     }
 }
 
 // We can't write `impl<T> SliceIndex<[T]> for isize`.
 fn signed_get<T>(arr: &[T], index: isize) -> Option<&T> {
-    match signed_index(index, arr.len()) {
-        Some(i) => arr.get(i),
-        None => None,
-    }
+    signed_index(index, arr.len()).and_then(|i| arr.get(i))
 }
 
 fn signed_get_mut<T>(arr: &mut [T], index: isize) -> Option<&mut T> {
-    match signed_index(index, arr.len()) {
-        Some(i) => arr.get_mut(i),
-        None => None,
-    }
+    signed_index(index, arr.len()).and_then(move |i| arr.get_mut(i))
 }
 
 fn signed_remove<T>(arr: &mut Vec<T>, index: isize) -> Option<T> {
-    match signed_index(index, arr.len()) {
-        Some(i) => Some(arr.remove(i)),
-        None => None,
-    }
+    signed_index(index, arr.len()).map(|i| arr.remove(i))
 }
 
-fn signed_index(index: isize, len: usize) -> Option<usize> {
+pub(crate) fn signed_index(index: isize, len: usize) -> Option<usize> {
     if index >= 0 {
         let u_index = index as usize;
         if u_index >= len {
@@ -189,7 +552,108 @@ fn signed_index(index: isize, len: usize) -> Option<usize> {
             Some(u_index)
         }
     } else {
-        len.checked_sub(index.abs() as usize)
+        len.checked_sub(index.unsigned_abs())
+    }
+}
+
+/// Computes the bounds of the slice `[start:end:step]` on an array of length `len`. The selected
+/// indices are in `[lower, upper)` for a positive step, and in `(lower, upper]` for a negative one.
+fn slice_bounds(start: Option<isize>, end: Option<isize>, step: isize, len: usize)
+    -> (isize, isize)
+{
+    let len = len as isize;
+    let normalize = |i: isize| if i >= 0 { i } else { len + i };
+    if step > 0 {
+        let lower = start.map_or(0, normalize).max(0).min(len);
+        let upper = end.map_or(len, normalize).max(0).min(len);
+        (lower, upper)
+    } else {
+        let upper = start.map_or(len - 1, normalize).max(-1).min(len - 1);
+        let lower = end.map_or(-1, normalize).max(-1).min(len - 1);
+        (lower, upper)
+    }
+}
+
+/// Computes the indices selected by the slice `[start:end:step]` on an array of length `len`. The
+/// semantics follows RFC 9535 (which is the same as Python's), so a zero `step` selects nothing.
+fn slice_indices(start: Option<isize>, end: Option<isize>, step: Option<isize>, len: usize)
+    -> Vec<usize>
+{
+    let step = step.unwrap_or(1);
+    let (lower, upper) = slice_bounds(start, end, step, len);
+
+    let mut indices = Vec::new();
+    if step > 0 {
+        let mut i = lower;
+        while i < upper {
+            indices.push(i as usize);
+            i += step;
+        }
+    } else if step < 0 {
+        let mut i = upper;
+        while lower < i {
+            indices.push(i as usize);
+            i += step;
+        }
+    }
+
+    indices
+}
+
+/// Returns `true` if the slice `[start:end:step]` on an array of length `len` selects `index`.
+pub(crate) fn slice_contains(
+    start: Option<isize>,
+    end: Option<isize>,
+    step: Option<isize>,
+    len: usize,
+    index: usize,
+) -> bool {
+    let step = step.unwrap_or(1);
+    let (lower, upper) = slice_bounds(start, end, step, len);
+    let i = index as isize;
+    if step > 0 {
+        lower <= i && i < upper && (i - lower) % step == 0
+    } else if step < 0 {
+        lower < i && i <= upper && (upper - i) % step == 0
+    } else {
+        false
+    }
+}
+
+/// Formats a location, or any singular path, as a normalized path, e.g., `$['foo'][0]`.
+pub(crate) fn normalized_path(path: &[PathItem]) -> String {
+    let mut s = String::from("$");
+    for item in path {
+        match item {
+            PathItem::Child(ident) | PathItem::Token(ident) => {
+                s.push_str("['");
+                for c in ident.chars() {
+                    match c {
+                        '\u{8}' => s.push_str(r"\b"),
+                        '\u{c}' => s.push_str(r"\f"),
+                        '\n' => s.push_str(r"\n"),
+                        '\r' => s.push_str(r"\r"),
+                        '\t' => s.push_str(r"\t"),
+                        '\'' => s.push_str(r"\'"),
+                        '\\' => s.push_str(r"\\"),
+                        '\u{0}' ..= '\u{1f}' => s.push_str(&format!(r"\u{:04x}", c as u32)),
+                        _ => s.push(c),
+                    }
+                }
+                s.push_str("']");
+            }
+            PathItem::Index(index) => s.push_str(&format!("[{}]", index)),
+            _ => unreachable!("A location only consists of children and indices"),
+        }
+    }
+    s
+}
+
+fn type_mismatch(path: String, expected: JsonType, actual: &Value) -> Error {
+    Error::TypeMismatchError {
+        path,
+        expected,
+        actual: JsonType::of(actual),
     }
 }
 
@@ -211,20 +675,22 @@ fn signed_index(index: isize, len: usize) -> Option<usize> {
 #[allow(dead_code)]
 fn create_from_path(json_path: &[PathItem], leaf_value: Value) -> Result<Value> {
     let mut curr = leaf_value;
-    for item in json_path.iter().rev() {
+    for (i, item) in json_path.iter().enumerate().rev() {
         match *item {
             PathItem::Child(ref ident) => {
                 curr = Value::Object(Map::from_iter(vec![(ident.to_owned(), curr)]));
             }
             PathItem::Index(index) => {
                 if index < 0 {
-                    return Err(Error::JsonInvalidArrayIndexError(index));
+                    let path = normalized_path(&json_path[..i]);
+                    return Err(Error::JsonInvalidArrayIndexError { path, index });
                 }
                 let index = index as usize;
                 let mut a: Vec<Value> = (0..=index).map(|_| Value::Null).collect();
                 a[index] = curr;
                 curr = Value::Array(a);
             }
+            _ => return Err(Error::NotSingularPathError),
         }
     }
     Ok(curr)
@@ -401,4 +867,228 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn selector_wildcard() -> Result<()> {
+        let mut data = json!({
+            "foo": [
+                { "bar": 1 },
+                { "bar": 2 },
+                { "baz": 3 }
+            ],
+            "qux": {
+                "a": "x",
+                "b": "y"
+            }
+        });
+
+        let selector = Selector::new("foo[*].bar")?;
+        assert!(!selector.is_singular());
+        assert_eq!(selector.select(&data), vec![&json!(1), &json!(2)]);
+        assert_eq!(selector.get(&data), Some(&json!(1)));
+
+        let selector = Selector::new("qux.*")?;
+        assert_eq!(selector.select(&data), vec![&json!("x"), &json!("y")]);
+
+        let selector = Selector::new("*")?;
+        assert_eq!(selector.select(&data).len(), 2);
+
+        // `get_mut` and `remove` work on the first match.
+        let selector = Selector::new("foo.*.bar")?;
+        *selector.get_mut(&mut data).unwrap() = json!(100);
+        assert_eq!(selector.remove(&mut data), Some(json!(100)));
+        assert_eq!(selector.select(&data), vec![&json!(2)]);
+
+        // `set` requires a singular path.
+        assert!(selector.set(&mut data, json!(0)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn selector_recursive_descent() -> Result<()> {
+        let data = json!({
+            "name": "root",
+            "children": [
+                {
+                    "name": "a",
+                    "children": [
+                        { "name": "b" }
+                    ]
+                },
+                { "name": "c" }
+            ]
+        });
+
+        let selector = Selector::new("..name")?;
+        assert_eq!(
+            selector.select(&data),
+            vec![&json!("root"), &json!("a"), &json!("b"), &json!("c")]
+        );
+
+        let selector = Selector::new("children..name")?;
+        assert_eq!(selector.select(&data), vec![&json!("a"), &json!("b"), &json!("c")]);
+
+        let selector = Selector::new("..children[0].name")?;
+        assert_eq!(selector.select(&data), vec![&json!("a"), &json!("b")]);
+
+        let selector = Selector::new("children[1]..*")?;
+        assert_eq!(selector.select(&data), vec![&json!("c")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn selector_slice() -> Result<()> {
+        let data = json!([0, 1, 2, 3, 4, 5]);
+        let select = |expression: &str| -> Result<Vec<Value>> {
+            Ok(Selector::new(expression)?
+                .select(&data)
+                .into_iter()
+                .cloned()
+                .collect())
+        };
+
+        assert_eq!(select("[1:3]")?, vec![json!(1), json!(2)]);
+        assert_eq!(select("[:2]")?, vec![json!(0), json!(1)]);
+        assert_eq!(select("[4:]")?, vec![json!(4), json!(5)]);
+        assert_eq!(select("[-2:]")?, vec![json!(4), json!(5)]);
+        assert_eq!(select("[::2]")?, vec![json!(0), json!(2), json!(4)]);
+        assert_eq!(select("[::-2]")?, vec![json!(5), json!(3), json!(1)]);
+        assert_eq!(select("[4:1:-1]")?, vec![json!(4), json!(3), json!(2)]);
+        assert_eq!(select("[-100:100]")?.len(), 6);
+        assert_eq!(select("[3:1]")?, Vec::<Value>::new());
+        assert_eq!(select("[::0]")?, Vec::<Value>::new());
+
+        Ok(())
+    }
+
+    #[test]
+    fn selector_filter() -> Result<()> {
+        let mut data = json!({
+            "books": [
+                { "title": "Sayings of the Century", "price": 8.95, "isbn": "0-553-21311-3" },
+                { "title": "Sword of Honour", "price": 12.99 },
+                { "title": "Moby Dick", "price": 8.99, "isbn": "0-395-19395-8" },
+                { "title": "The Lord of the Rings", "price": 22.99, "isbn": "0-395-19395-8" }
+            ],
+            "bicycle": { "color": "red", "price": 19.95 }
+        });
+
+        let selector = Selector::new("books[?(@.price < 10)].title")?;
+        assert_eq!(
+            selector.select(&data),
+            vec![&json!("Sayings of the Century"), &json!("Moby Dick")]
+        );
+
+        let expression = "books[?(@.isbn && @.price > 10 || @.title == 'Sword of Honour')].title";
+        let selector = Selector::new(expression)?;
+        assert_eq!(
+            selector.select(&data),
+            vec![&json!("Sword of Honour"), &json!("The Lord of the Rings")]
+        );
+
+        let selector = Selector::new("books[?(!@.isbn)]")?;
+        assert_eq!(selector.select(&data).len(), 1);
+
+        // Filters apply to object members as well.
+        let selector = Selector::new("..[?(@.price > 19)].price")?;
+        assert_eq!(selector.select(&data), vec![&json!(19.95), &json!(22.99)]);
+
+        let selector = Selector::new("books[?(@.price > 100)]")?;
+        assert_eq!(selector.get(&data), None);
+
+        // `get_mut` and `remove` work on the first match.
+        let selector = Selector::new("books[?(@.price > 10)].price")?;
+        *selector.get_mut(&mut data).unwrap() = json!(9.99);
+        assert_eq!(selector.get(&data), Some(&json!(22.99)));
+
+        let selector = Selector::new("books[?(@.price < 9)]")?;
+        assert_eq!(selector.remove(&mut data).unwrap()["title"], json!("Sayings of the Century"));
+        assert_eq!(selector.remove(&mut data).unwrap()["title"], json!("Moby Dick"));
+        assert_eq!(selector.remove(&mut data), None);
+        assert_eq!(data["books"].as_array().unwrap().len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn selector_set_all() -> Result<()> {
+        let mut data = json!({
+            "users": [
+                { "name": "Alice", "email": "alice@example.com" },
+                { "name": "Bob" },
+                { "name": "Carol", "email": "carol@example.com" }
+            ]
+        });
+
+        let selector = Selector::new("users[*].email")?;
+        assert_eq!(selector.set_all(&mut data, json!("<redacted>")), 2);
+        assert_eq!(data["users"][0]["email"], json!("<redacted>"));
+        assert_eq!(data["users"][1].get("email"), None);
+        assert_eq!(data["users"][2]["email"], json!("<redacted>"));
+
+        // Nested matches are only counted once.
+        let mut data = json!({ "a": { "a": { "a": 1 } } });
+        assert_eq!(Selector::new("..a")?.set_all(&mut data, json!(0)), 1);
+        assert_eq!(data, json!({ "a": 0 }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn selector_remove_all() -> Result<()> {
+        let mut data = json!({
+            "items": [0, 1, 2, 3, 4, 5, 6],
+            "nested": [{ "secret": 1, "tail": [{ "secret": 2 }] }]
+        });
+
+        // Index shifting is handled.
+        let selector = Selector::new("items[?(@ > 1 && @ < 5)]")?;
+        assert_eq!(selector.remove_all(&mut data), vec![json!(2), json!(3), json!(4)]);
+        assert_eq!(data["items"], json!([0, 1, 5, 6]));
+
+        let selector = Selector::new("items[::2]")?;
+        assert_eq!(selector.remove_all(&mut data), vec![json!(0), json!(5)]);
+        assert_eq!(data["items"], json!([1, 6]));
+
+        // Duplicates in a union are removed once.
+        let selector = Selector::with_mode("$.items[0, 0, -1]", Mode::Rfc9535)?;
+        assert_eq!(selector.remove_all(&mut data), vec![json!(1), json!(6)]);
+        assert_eq!(data["items"], json!([]));
+
+        let selector = Selector::new("..secret")?;
+        assert_eq!(selector.remove_all(&mut data), vec![json!(1), json!(2)]);
+        assert_eq!(data["nested"], json!([{ "tail": [{}] }]));
+
+        assert_eq!(Selector::new("..nested")?.remove_all(&mut data).len(), 1);
+        assert_eq!(Selector::new("..nested")?.remove_all(&mut data).len(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn selector_update_with() -> Result<()> {
+        let mut data = json!({
+            "a": { "n": 1, "b": { "n": 2 } },
+            "c": [{ "n": 3 }, { "m": 4 }]
+        });
+
+        let selector = Selector::new("..n")?;
+        let count = selector.update_with(&mut data, |v| {
+            *v = json!(v.as_i64().unwrap() * 10);
+        });
+        assert_eq!(count, 3);
+        assert_eq!(selector.select(&data), vec![&json!(10), &json!(20), &json!(30)]);
+
+        // Inner nodes are updated before outer ones.
+        let mut visited = vec![];
+        Selector::new("..*")?.update_with(&mut data, |v| visited.push(v.clone()));
+        assert_eq!(visited.first(), Some(&json!(4)));
+        assert_eq!(visited.last(), Some(&data["a"]));
+
+        assert_eq!(Selector::new("nothing")?.update_with(&mut data, |_| unreachable!()), 0);
+
+        Ok(())
+    }
 }
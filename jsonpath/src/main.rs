@@ -0,0 +1,346 @@
+//! A command-line tool to query and edit JSON files with the JSONPaths of this crate.
+//!
+//! Usage:
+//!
+//!     jsonpath [OPTIONS] get|select|delete <PATH> [FILE]...
+//!     jsonpath [OPTIONS] set <PATH> <VALUE> [FILE]...
+
+mod file;
+
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::process;
+
+use serde_json::Value;
+
+use jsonpath::{Mode, Selector};
+
+use crate::file::write_atomically;
+
+const USAGE: &str = "\
+Usage: jsonpath [OPTIONS] get|select|delete <PATH> [FILE]...
+       jsonpath [OPTIONS] set <PATH> <VALUE> [FILE]...
+
+Reads JSON from the files, or from stdin if no file (or `-`) is given.
+
+Commands:
+    get       Prints the first node matched by PATH in document order
+    select    Prints all the nodes matched by PATH in document order
+    set       Sets the node at PATH to VALUE, a JSON text, and prints the document
+    delete    Removes all the nodes matched by PATH and prints the document
+
+Options:
+    --rfc9535         Parses PATH strictly following RFC 9535
+    --ndjson          Treats each line of the input as a document
+    -i, --in-place    Writes the edited documents back to the files (`set` and `delete` only)
+    -c, --compact     Prints compact JSON (always the case with `--ndjson`)
+    -h, --help        Prints this message
+
+Exits with 1 if `get` or `select` matches nothing, and with 2 on errors.";
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Get,
+    Select,
+    Set(Value),
+    Delete,
+}
+
+#[derive(Debug)]
+struct Args {
+    command: Command,
+    path: String,
+    mode: Mode,
+    ndjson: bool,
+    in_place: bool,
+    compact: bool,
+    files: Vec<String>,
+}
+
+/// Parses the arguments, excluding the program name. Returns `Ok(None)` if help is requested.
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Option<Args>> {
+    let mut mode = Mode::Lenient;
+    let (mut ndjson, mut in_place, mut compact) = (false, false, false);
+    let mut positional = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rfc9535" => mode = Mode::Rfc9535,
+            "--ndjson" => ndjson = true,
+            "-i" | "--in-place" => in_place = true,
+            "-c" | "--compact" => compact = true,
+            "-h" | "--help" => return Ok(None),
+            "--" => {
+                positional.extend(args.by_ref());
+            }
+            // `-` stands for stdin, and paths like `-foo` are allowed after the command.
+            s if s.starts_with('-') && s != "-" && positional.is_empty() => {
+                return Err(format!("Unknown option '{}'", s).into());
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let command = positional.next().ok_or("Missing command")?;
+    let path = positional.next().ok_or("Missing PATH")?;
+    let command = match command.as_str() {
+        "get" => Command::Get,
+        "select" => Command::Select,
+        "set" => {
+            let value = positional.next().ok_or("Missing VALUE")?;
+            Command::Set(serde_json::from_str(&value)?)
+        }
+        "delete" => Command::Delete,
+        _ => return Err(format!("Unknown command '{}'", command).into()),
+    };
+
+    let is_editing = matches!(command, Command::Set(_) | Command::Delete);
+    let files: Vec<String> = positional.collect();
+    if in_place && (!is_editing || files.is_empty() || files.iter().any(|f| f == "-")) {
+        return Err("`--in-place` only works with `set` or `delete` on files".into());
+    }
+
+    Ok(Some(Args {
+        command,
+        path,
+        mode,
+        ndjson,
+        in_place,
+        compact: compact || ndjson,
+        files,
+    }))
+}
+
+/// Runs the command on the input, which contains one document or, in NDJSON mode, one document
+/// per line, and writes the result of each document as soon as it is ready. Returns whether
+/// anything is matched.
+fn process<R: Read, W: Write>(
+    args: &Args,
+    selector: &Selector,
+    mut input: BufReader<R>,
+    output: &mut W,
+) -> Result<bool> {
+    if !args.ndjson {
+        return process_document(args, selector, input, output);
+    }
+
+    let mut is_matched = false;
+    let mut line = String::new();
+    for line_number in 1.. {
+        // Flushes before the read may block, so the results are not held back on slow pipes.
+        if input.buffer().is_empty() {
+            output.flush()?;
+        }
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            break;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        is_matched |= process_document(args, selector, line.as_bytes(), output)
+            .map_err(|e| format!("line {}: {}", line_number, e))?;
+    }
+    Ok(is_matched)
+}
+
+/// Runs the command on a single document. `get` and `select` stream the distinct matched nodes in
+/// document order without building the document, unless a filter refers to the root node `$`.
+fn process_document<R: Read, W: Write>(
+    args: &Args,
+    selector: &Selector,
+    document: R,
+    output: &mut W,
+) -> Result<bool> {
+    let is_read_only = matches!(args.command, Command::Get | Command::Select);
+    if is_read_only && !selector.refers_to_root() {
+        let is_get = args.command == Command::Get;
+        let mut is_matched = false;
+        let mut result = Ok(());
+        selector.stream(document, |node| {
+            if result.is_ok() && !(is_get && is_matched) {
+                is_matched = true;
+                result = write_node(args, &node, output);
+            }
+        })?;
+        result?;
+        return Ok(is_matched);
+    }
+
+    let mut value: Value = serde_json::from_reader(document)?;
+    let nodes = match &args.command {
+        // The same nodes as the ones streamed.
+        Command::Get => selector.select_distinct(&value).into_iter().take(1).cloned().collect(),
+        Command::Select => selector.select_distinct(&value).into_iter().cloned().collect(),
+        Command::Set(new_value) => {
+            if selector.is_singular() {
+                selector.set(&mut value, new_value.clone())?;
+            } else {
+                selector.set_all(&mut value, new_value.clone());
+            }
+            vec![value]
+        }
+        Command::Delete => {
+            selector.remove_all(&mut value);
+            vec![value]
+        }
+    };
+    for node in &nodes {
+        write_node(args, node, output)?;
+    }
+    Ok(!nodes.is_empty())
+}
+
+fn write_node<W: Write>(args: &Args, node: &Value, output: &mut W) -> Result<()> {
+    if args.compact {
+        serde_json::to_writer(&mut *output, node)?;
+    } else {
+        serde_json::to_writer_pretty(&mut *output, node)?;
+    }
+    output.write_all(b"\n")?;
+    Ok(())
+}
+
+fn run(args: &Args) -> Result<bool> {
+    let selector = Selector::with_mode(&args.path, args.mode)?;
+
+    let stdin = ["-".to_owned()];
+    let files = if args.files.is_empty() { &stdin[..] } else { &args.files[..] };
+    let stdout = io::stdout();
+    let mut stdout = BufWriter::new(stdout.lock());
+    let mut is_matched = false;
+    for file in files {
+        let context = |e: Box<dyn Error>| format!("{}: {}", file, e);
+        let input: BufReader<Box<dyn Read>> = if file == "-" {
+            BufReader::new(Box::new(io::stdin()))
+        } else {
+            let f = File::open(file).map_err(|e| context(e.into()))?;
+            BufReader::new(Box::new(f))
+        };
+
+        if args.in_place {
+            let mut output = vec![];
+            is_matched |= process(args, &selector, input, &mut output).map_err(context)?;
+            write_atomically(Path::new(file), &output)?;
+        } else {
+            is_matched |= process(args, &selector, input, &mut stdout).map_err(context)?;
+        }
+    }
+    stdout.flush()?;
+
+    Ok(is_matched || matches!(args.command, Command::Set(_) | Command::Delete))
+}
+
+fn main() {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("jsonpath: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+
+    match run(&args) {
+        Ok(true) => (),
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            eprintln!("jsonpath: {}", e);
+            process::exit(2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn args(s: &str) -> Result<Option<Args>> {
+        parse_args(s.split_whitespace().map(str::to_owned))
+    }
+
+    fn run_on(s: &str, input: &str) -> (String, bool) {
+        let args = args(s).unwrap().unwrap();
+        let selector = Selector::with_mode(&args.path, args.mode).unwrap();
+        let mut output = vec![];
+        let input = BufReader::new(input.as_bytes());
+        let is_matched = process(&args, &selector, input, &mut output).unwrap();
+        (String::from_utf8(output).unwrap(), is_matched)
+    }
+
+    #[test]
+    fn cli_args() {
+        let a = args("--ndjson set $.a[0] {\"b\":1} x.json -").unwrap().unwrap();
+        assert_eq!(a.command, Command::Set(json!({ "b": 1 })));
+        assert_eq!(a.path, "$.a[0]");
+        assert_eq!(a.files, vec!["x.json", "-"]);
+        assert!(a.ndjson && a.compact && !a.in_place);
+
+        let a = args("-i --rfc9535 delete -- -a x.json").unwrap().unwrap();
+        assert_eq!((a.command, a.path.as_str(), a.mode), (Command::Delete, "-a", Mode::Rfc9535));
+        assert!(a.in_place);
+
+        assert!(args("get a --help").unwrap().is_none());
+        assert!(args("").is_err());
+        assert!(args("--foo get a").is_err());
+        assert!(args("patch a").is_err());
+        assert!(args("set a").is_err());
+        assert!(args("set a {").is_err());
+        assert!(args("-i get a x.json").is_err());
+        assert!(args("-i delete a").is_err());
+    }
+
+    #[test]
+    fn cli_process() {
+        let input = r#"{ "a": [{ "b": 1 }, { "b": 2 }] }"#;
+        assert_eq!(run_on("-c get a[*].b", input), ("1\n".to_owned(), true));
+        assert_eq!(run_on("-c select a[*].b", input), ("1\n2\n".to_owned(), true));
+        assert_eq!(run_on("-c select a[*].c", input), ("".to_owned(), false));
+        assert_eq!(
+            run_on("-c set a[*].b null", input),
+            ("{\"a\":[{\"b\":null},{\"b\":null}]}\n".to_owned(), true)
+        );
+        assert_eq!(
+            run_on("-c set c.d 0", input).0,
+            "{\"a\":[{\"b\":1},{\"b\":2}],\"c\":{\"d\":0}}\n"
+        );
+        assert_eq!(run_on("delete a", input).0, "{}\n");
+        assert_eq!(run_on("get a[1]", input).0, "{\n  \"b\": 2\n}\n");
+
+        let input = "{\"a\":1}\n\n{\"a\":2,\"b\":3}\n";
+        assert_eq!(run_on("--ndjson select a", input), ("1\n2\n".to_owned(), true));
+        assert_eq!(run_on("--ndjson delete a", input).0, "{}\n{\"b\":3}\n");
+
+        // Streamed in document order, except for the filters referring to the root node, which
+        // print the same distinct nodes in memory.
+        let input = r#"{ "a": [1, 2, 3], "b": 2 }"#;
+        assert_eq!(run_on("-c select a[::-1]", input).0, "1\n2\n3\n");
+        assert_eq!(run_on("-c get a[::-1]", input).0, "1\n");
+        assert_eq!(run_on("-c select a[?(@>=$.b)]", input).0, "2\n3\n");
+        assert_eq!(run_on("-c get ..[?(@>=$.b)]", input).0, "2\n");
+        assert_eq!(run_on("-c select ..[?(@>=$.b)]", input).0, "2\n3\n2\n");
+    }
+
+    #[test]
+    fn cli_streaming() {
+        // The result of each line is written before the next line is read.
+        let args = args("--ndjson select a").unwrap().unwrap();
+        let selector = Selector::new("a").unwrap();
+        let mut output = vec![];
+        let input = BufReader::new(&b"{\"a\":1}\n{\"a\":2}\n{\"a\":"[..]);
+        let error = process(&args, &selector, input, &mut output).unwrap_err();
+        assert!(error.to_string().starts_with("line 3: "));
+        assert_eq!(output, b"1\n2\n");
+    }
+}
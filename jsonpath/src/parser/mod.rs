@@ -1,17 +1,30 @@
-use pest::Parser;
+use std::str::Chars;
 
-use crate::PathItem;
-use crate::error::{Error, Result};
+use pest::{Parser, RuleType, Span};
+use pest::iterators::{Pair, Pairs};
+use serde_json::{Value, Number};
+
+use crate::{PathItem, FilterExpr, Operand, Comparison};
+use crate::error::{Error, Result, SyntaxError};
+
+mod rfc9535;
+
+pub use self::rfc9535::parse as parse_rfc9535;
 
 #[derive(Parser)]
 #[grammar = "parser/grammar.pest"]
 pub struct JsonPathParser;
 
 pub fn parse(expression: &str) -> Result<Vec<PathItem>> {
-    let mut json_path = Vec::new();
-    let mut pairs = JsonPathParser::parse(Rule::expression, expression)?;
+    let mut pairs = JsonPathParser::parse(Rule::expression, expression)
+        .map_err(|e| SyntaxError::from_pest(e, expression))?;
     let items = pairs.next().ok_or_else(|| Error::PairsNextItemError)?;
-    for item in items.into_inner() {
+    parse_items(items.into_inner())
+}
+
+fn parse_items(items: Pairs<Rule>) -> Result<Vec<PathItem>> {
+    let mut json_path = Vec::new();
+    for item in items {
         match item.as_rule() {
             Rule::child |
             Rule::first_child |
@@ -28,13 +41,48 @@ pub fn parse(expression: &str) -> Result<Vec<PathItem>> {
             }
 
             Rule::indexed_child => {
-                let index: isize = item
+                let index = item
                     .into_inner()
                     .next()
-                    .ok_or_else(|| Error::PairsNextItemError)?
-                    .as_str()
-                    .parse()?;
-                json_path.push(PathItem::Index(index));
+                    .ok_or_else(|| Error::PairsNextItemError)?;
+                json_path.push(PathItem::Index(parse_index(index)?));
+            }
+
+            Rule::wildcard |
+            Rule::wildcard_child |
+            Rule::bracketed_wildcard => {
+                json_path.push(PathItem::Wildcard);
+            }
+
+            Rule::recursive_descent => {
+                json_path.push(PathItem::RecursiveDescent);
+            }
+
+            Rule::slice => {
+                let (mut start, mut end, mut step) = (None, None, None);
+                for bound in item.into_inner() {
+                    let rule = bound.as_rule();
+                    let number = parse_index(
+                        bound
+                            .into_inner()
+                            .next()
+                            .ok_or(Error::PairsNextItemError)?
+                    )?;
+                    match rule {
+                        Rule::slice_start => start = Some(number),
+                        Rule::slice_end => end = Some(number),
+                        _ => step = Some(number),
+                    }
+                }
+                json_path.push(PathItem::Slice { start, end, step });
+            }
+
+            Rule::filter => {
+                let or_expr = item
+                    .into_inner()
+                    .next()
+                    .ok_or(Error::PairsNextItemError)?;
+                json_path.push(PathItem::Filter(parse_filter(or_expr)?));
             }
 
             _rule => (),
@@ -44,6 +92,160 @@ pub fn parse(expression: &str) -> Result<Vec<PathItem>> {
     Ok(json_path)
 }
 
+fn parse_filter(expr: Pair<Rule>) -> Result<FilterExpr> {
+    match expr.as_rule() {
+        Rule::or_expr | Rule::and_expr => {
+            let is_or = expr.as_rule() == Rule::or_expr;
+            let mut operands = expr.into_inner();
+            let first = operands.next().ok_or(Error::PairsNextItemError)?;
+            operands.try_fold(parse_filter(first)?, |lhs, rhs| {
+                let (lhs, rhs) = (Box::new(lhs), Box::new(parse_filter(rhs)?));
+                Ok(if is_or {
+                    FilterExpr::Or(lhs, rhs)
+                } else {
+                    FilterExpr::And(lhs, rhs)
+                })
+            })
+        }
+
+        Rule::not_expr => {
+            let operand = expr
+                .into_inner()
+                .next()
+                .ok_or(Error::PairsNextItemError)?;
+            Ok(FilterExpr::Not(Box::new(parse_filter(operand)?)))
+        }
+
+        Rule::existence => {
+            let path = expr
+                .into_inner()
+                .next()
+                .ok_or(Error::PairsNextItemError)?;
+            Ok(FilterExpr::Test(parse_operand(path)?))
+        }
+
+        Rule::comparison => {
+            let mut inner = expr.into_inner();
+            let mut next = || inner.next().ok_or(Error::PairsNextItemError);
+            let lhs = parse_operand(next()?)?;
+            let op = match next()?.as_str() {
+                "==" => Comparison::Eq,
+                "!=" => Comparison::Ne,
+                "<" => Comparison::Lt,
+                "<=" => Comparison::Le,
+                ">" => Comparison::Gt,
+                _ => Comparison::Ge,
+            };
+            let rhs = parse_operand(next()?)?;
+            Ok(FilterExpr::Compare(lhs, op, rhs))
+        }
+
+        _rule => Err(Error::PairsNextItemError),
+    }
+}
+
+fn parse_operand(operand: Pair<Rule>) -> Result<Operand> {
+    let literal = match operand.as_rule() {
+        Rule::current_path => {
+            return Ok(Operand::Current(parse_items(operand.into_inner())?));
+        }
+
+        Rule::root_path => {
+            return Ok(Operand::Root(parse_items(operand.into_inner())?));
+        }
+
+        Rule::number_literal => {
+            let s = operand.as_str();
+            match s.parse::<i64>() {
+                Ok(i) => Value::from(i),
+                Err(_) => {
+                    let f: f64 = s.parse().map_err(|_| {
+                        syntax_error(operand.as_span(), format!("The number '{}' is invalid", s))
+                    })?;
+                    Number::from_f64(f).map_or(Value::Null, Value::Number)
+                }
+            }
+        }
+
+        Rule::string_literal => {
+            let s = operand
+                .into_inner()
+                .next()
+                .ok_or(Error::PairsNextItemError)?;
+            Value::String(unescape(s)?)
+        }
+
+        Rule::true_literal => Value::Bool(true),
+        Rule::false_literal => Value::Bool(false),
+        _ => Value::Null,
+    };
+
+    Ok(Operand::Literal(literal))
+}
+
+fn parse_index(number: Pair<Rule>) -> Result<isize> {
+    number.as_str().parse().map_err(|_| {
+        syntax_error(number.as_span(), format!("The index '{}' is out of range", number.as_str()))
+    })
+}
+
+/// Unescapes the content of a quoted string, using the escape sequences of JSON plus `\'`.
+fn unescape<R: RuleType>(string: Pair<R>) -> Result<String> {
+    let s = string.as_str();
+    let invalid = || {
+        let message = format!("The string '{}' contains an invalid escape sequence", s);
+        syntax_error(string.as_span(), message)
+    };
+
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        let c = match chars.next().ok_or_else(invalid)? {
+            'b' => '\u{8}',
+            'f' => '\u{c}',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            c @ ('/' | '\\' | '\'' | '"') => c,
+            'u' => {
+                let hex4 = |chars: &mut Chars| -> Option<u32> {
+                    let hex: String = chars.take(4).collect();
+                    if hex.len() == 4 { u32::from_str_radix(&hex, 16).ok() } else { None }
+                };
+
+                let high = hex4(&mut chars).ok_or_else(invalid)?;
+                let code_point = if (0xD800..0xDC00).contains(&high) {
+                    // A surrogate pair must follow as `\uXXXX`.
+                    if chars.next() != Some('\\') || chars.next() != Some('u') {
+                        return Err(invalid());
+                    }
+                    let low = hex4(&mut chars).ok_or_else(invalid)?;
+                    if !(0xDC00..0xE000).contains(&low) {
+                        return Err(invalid());
+                    }
+                    0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                } else {
+                    high
+                };
+                std::char::from_u32(code_point).ok_or_else(invalid)?
+            }
+            _ => return Err(invalid()),
+        };
+        unescaped.push(c);
+    }
+
+    Ok(unescaped)
+}
+
+fn syntax_error(span: Span, message: String) -> Error {
+    SyntaxError::new(span.get_input(), span.start()..span.end(), message).into()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -95,4 +297,130 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn parser_wildcard_and_descendant() -> Result<()> {
+        use PathItem::*;
+
+        assert_eq!(
+            parse("foo.*[*]..bar..*..[0]")?,
+            &[
+                Child("foo".into()),
+                Wildcard,
+                Wildcard,
+                RecursiveDescent,
+                Child("bar".into()),
+                RecursiveDescent,
+                Wildcard,
+                RecursiveDescent,
+                Index(0),
+            ][..]
+        );
+
+        assert_eq!(
+            parse("..baz")?,
+            &[
+                RecursiveDescent,
+                Child("baz".into()),
+            ][..]
+        );
+
+        assert!(parse("foo...bar").is_err());
+
+        // optional root anchor
+        assert_eq!(parse("$")?, &[][..]);
+        assert_eq!(parse("$.foo[0]..bar")?, parse("foo[0]..bar")?);
+        assert_eq!(parse("$[*]")?, &[Wildcard][..]);
+        assert!(parse("foo..").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parser_slice() -> Result<()> {
+        use PathItem::*;
+
+        assert_eq!(
+            parse("foo[1:3][:-1][::2][-2:][:][::-1]")?,
+            &[
+                Child("foo".into()),
+                Slice { start: Some(1), end: Some(3), step: None },
+                Slice { start: None, end: Some(-1), step: None },
+                Slice { start: None, end: None, step: Some(2) },
+                Slice { start: Some(-2), end: None, step: None },
+                Slice { start: None, end: None, step: None },
+                Slice { start: None, end: None, step: Some(-1) },
+            ][..]
+        );
+
+        assert!(parse("foo[1:2:3:4]").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parser_filter() -> Result<()> {
+        use PathItem::*;
+
+        let current = |name: &str| Operand::Current(vec![Child(name.into())]);
+
+        assert_eq!(
+            parse("books[?(@.price < 10)]")?,
+            &[
+                Child("books".into()),
+                Filter(FilterExpr::Compare(
+                    current("price"),
+                    Comparison::Lt,
+                    Operand::Literal(Value::from(10)),
+                )),
+            ][..]
+        );
+
+        assert_eq!(
+            parse("[?@.isbn && !(@.author == 'Nigel \\'Rees\\'' || @.price >= 8.5e0)]")?,
+            &[
+                Filter(FilterExpr::And(
+                    Box::new(FilterExpr::Test(current("isbn"))),
+                    Box::new(FilterExpr::Not(Box::new(FilterExpr::Or(
+                        Box::new(FilterExpr::Compare(
+                            current("author"),
+                            Comparison::Eq,
+                            Operand::Literal(Value::from("Nigel 'Rees'")),
+                        )),
+                        Box::new(FilterExpr::Compare(
+                            current("price"),
+                            Comparison::Ge,
+                            Operand::Literal(Value::from(8.5)),
+                        )),
+                    )))),
+                )),
+            ][..]
+        );
+
+        assert_eq!(
+            parse(r#"[?(@ != "\u00e9\ud83d\ude00" && @[0].a != null)]"#)?,
+            &[
+                Filter(FilterExpr::And(
+                    Box::new(FilterExpr::Compare(
+                        Operand::Current(vec![]),
+                        Comparison::Ne,
+                        Operand::Literal(Value::from("\u{e9}\u{1f600}")),
+                    )),
+                    Box::new(FilterExpr::Compare(
+                        Operand::Current(vec![Index(0), Child("a".into())]),
+                        Comparison::Ne,
+                        Operand::Literal(Value::Null),
+                    )),
+                )),
+            ][..]
+        );
+
+        assert!(parse("[?(@.a < )]").is_err());
+        assert!(parse("[?(1 == 1)]").is_ok());
+        assert!(parse("[?(1)]").is_err());
+        assert!(parse(r"[?(@.a == '\x')]").is_err());
+        assert!(parse(r"[?(@.a == '\ud83d')]").is_err());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,318 @@
+//! The parser of RFC 9535 mode.
+
+use pest::{Parser, Span};
+use pest::iterators::{Pair, Pairs};
+use serde_json::{Value, Number};
+
+use crate::{PathItem, FilterExpr, Operand, Comparison, Function, FunctionType};
+use crate::error::{Error, Result, SyntaxError};
+
+use super::{unescape, syntax_error};
+
+#[derive(Parser)]
+#[grammar = "parser/rfc9535.pest"]
+pub struct Rfc9535Parser;
+
+/// The range of integers is limited to the exact integers of IEEE 754 doubles.
+const MAX_INT: i64 = (1 << 53) - 1;
+
+pub fn parse(expression: &str) -> Result<Vec<PathItem>> {
+    let mut pairs = Rfc9535Parser::parse(Rule::jsonpath_query, expression)
+        .map_err(|e| SyntaxError::from_pest(e, expression))?;
+    let query = pairs.next().ok_or(Error::PairsNextItemError)?;
+    parse_segments(query.into_inner())
+}
+
+fn parse_segments(segments: Pairs<Rule>) -> Result<Vec<PathItem>> {
+    let mut json_path = Vec::new();
+    for segment in segments {
+        let rule = segment.as_rule();
+        if rule == Rule::descendant_segment {
+            json_path.push(PathItem::RecursiveDescent);
+        } else if rule != Rule::child_segment {
+            continue;
+        }
+
+        let selection = first_inner(segment)?;
+        let item = match selection.as_rule() {
+            Rule::bracketed_selection => {
+                let mut selectors = selection
+                    .into_inner()
+                    .map(parse_selector)
+                    .collect::<Result<Vec<_>>>()?;
+                if selectors.len() == 1 {
+                    selectors.pop().ok_or(Error::PairsNextItemError)?
+                } else {
+                    PathItem::Union(selectors)
+                }
+            }
+            Rule::wildcard_selector => PathItem::Wildcard,
+            _ => PathItem::Child(selection.as_str().to_owned()),
+        };
+        json_path.push(item);
+    }
+
+    Ok(json_path)
+}
+
+fn parse_selector(selector: Pair<Rule>) -> Result<PathItem> {
+    Ok(match selector.as_rule() {
+        Rule::name_selector => PathItem::Child(parse_string(first_inner(selector)?)?),
+        Rule::wildcard_selector => PathItem::Wildcard,
+        Rule::index_selector => PathItem::Index(parse_int(first_inner(selector)?)?),
+        Rule::slice_selector => {
+            let (mut start, mut end, mut step) = (None, None, None);
+            for bound in selector.into_inner() {
+                let rule = bound.as_rule();
+                let number = Some(parse_int(first_inner(bound)?)?);
+                match rule {
+                    Rule::slice_start => start = number,
+                    Rule::slice_end => end = number,
+                    _ => step = number,
+                }
+            }
+            PathItem::Slice { start, end, step }
+        }
+        _ => PathItem::Filter(parse_logical(first_inner(selector)?)?),
+    })
+}
+
+fn parse_logical(expr: Pair<Rule>) -> Result<FilterExpr> {
+    match expr.as_rule() {
+        Rule::logical_or_expr | Rule::logical_and_expr => {
+            let is_or = expr.as_rule() == Rule::logical_or_expr;
+            let mut operands = expr.into_inner();
+            let first = operands.next().ok_or(Error::PairsNextItemError)?;
+            operands.try_fold(parse_logical(first)?, |lhs, rhs| {
+                let (lhs, rhs) = (Box::new(lhs), Box::new(parse_logical(rhs)?));
+                Ok(if is_or {
+                    FilterExpr::Or(lhs, rhs)
+                } else {
+                    FilterExpr::And(lhs, rhs)
+                })
+            })
+        }
+
+        Rule::paren_expr | Rule::test_expr => {
+            let is_paren = expr.as_rule() == Rule::paren_expr;
+            let mut inner = expr.into_inner().peekable();
+            let negated = inner.peek().map(Pair::as_rule) == Some(Rule::logical_not_op);
+            if negated {
+                inner.next();
+            }
+
+            let operand = inner.next().ok_or(Error::PairsNextItemError)?;
+            let span = operand.as_span();
+            let filter = if is_paren {
+                parse_logical(operand)?
+            } else {
+                let operand = parse_operand(operand)?;
+                if let Operand::Function(function, _) = &operand {
+                    if function.result_type() == FunctionType::Value {
+                        return Err(syntax_error(span, format!(
+                            "The result of function '{}' cannot be tested", function.name()
+                        )));
+                    }
+                }
+                FilterExpr::Test(operand)
+            };
+
+            Ok(if negated {
+                FilterExpr::Not(Box::new(filter))
+            } else {
+                filter
+            })
+        }
+
+        _ => {
+            let mut inner = expr.into_inner();
+            let mut next = || inner.next().ok_or(Error::PairsNextItemError);
+            let lhs = parse_comparable(next()?)?;
+            let op = match next()?.as_str() {
+                "==" => Comparison::Eq,
+                "!=" => Comparison::Ne,
+                "<" => Comparison::Lt,
+                "<=" => Comparison::Le,
+                ">" => Comparison::Gt,
+                _ => Comparison::Ge,
+            };
+            let rhs = parse_comparable(next()?)?;
+            Ok(FilterExpr::Compare(lhs, op, rhs))
+        }
+    }
+}
+
+/// Parses an operand of comparison, which must be of value type.
+fn parse_comparable(operand: Pair<Rule>) -> Result<Operand> {
+    let span = operand.as_span();
+    let operand = parse_operand(operand)?;
+    check_value_type(&operand, span)?;
+    Ok(operand)
+}
+
+fn parse_operand(operand: Pair<Rule>) -> Result<Operand> {
+    let literal = match operand.as_rule() {
+        Rule::rel_query => return Ok(Operand::Current(parse_segments(operand.into_inner())?)),
+        Rule::abs_query => return Ok(Operand::Root(parse_segments(operand.into_inner())?)),
+        Rule::function_expr => return parse_function(operand),
+
+        Rule::number => {
+            let s = operand.as_str();
+            match s.parse::<i64>() {
+                Ok(i) => Value::from(i),
+                Err(_) => {
+                    let f: f64 = s.parse().map_err(|_| {
+                        syntax_error(operand.as_span(), format!("The number '{}' is invalid", s))
+                    })?;
+                    let message = format!("The number '{}' is out of range", s);
+                    Value::Number(Number::from_f64(f).ok_or_else(|| {
+                        syntax_error(operand.as_span(), message)
+                    })?)
+                }
+            }
+        }
+        Rule::string_literal => Value::String(parse_string(operand)?),
+        Rule::true_literal => Value::Bool(true),
+        Rule::false_literal => Value::Bool(false),
+        _ => Value::Null,
+    };
+
+    Ok(Operand::Literal(literal))
+}
+
+fn parse_function(expr: Pair<Rule>) -> Result<Operand> {
+    let span = expr.as_span();
+    let mut inner = expr.into_inner();
+    let name = inner.next().ok_or(Error::PairsNextItemError)?;
+    let function = Function::from_name(name.as_str()).ok_or_else(|| {
+        syntax_error(name.as_span(), format!("Unknown function '{}'", name.as_str()))
+    })?;
+    let name = name.as_str();
+
+    let arg_spans: Vec<_> = inner.clone().map(|arg| arg.as_span()).collect();
+    let args = inner.map(parse_operand).collect::<Result<Vec<_>>>()?;
+    let parameter_types = function.parameter_types();
+    if args.len() != parameter_types.len() {
+        return Err(syntax_error(span, format!(
+            "Function '{}' expects {} argument(s)", name, parameter_types.len()
+        )));
+    }
+
+    for ((arg, span), parameter_type) in args.iter().zip(arg_spans).zip(parameter_types) {
+        match parameter_type {
+            FunctionType::Value => check_value_type(arg, span)?,
+            FunctionType::Nodes | FunctionType::Logical => {
+                if let Operand::Literal(_) = arg {
+                    return Err(syntax_error(span, format!(
+                        "Function '{}' does not accept a literal argument", name
+                    )));
+                }
+            }
+        }
+    }
+
+    Ok(Operand::Function(function, args))
+}
+
+/// Checks that the operand is a literal, a singular query or a function returning a value.
+fn check_value_type(operand: &Operand, span: Span) -> Result<()> {
+    match operand {
+        Operand::Function(function, _) if function.result_type() != FunctionType::Value => {
+            let message = format!("The result of function '{}' is not a value", function.name());
+            Err(syntax_error(span, message))
+        }
+        Operand::Current(_) | Operand::Root(_) if !operand.is_singular() => {
+            let message = "A non-singular query cannot be used as a value".to_owned();
+            Err(syntax_error(span, message))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn parse_int(int: Pair<Rule>) -> Result<isize> {
+    match int.as_str().parse::<i64>() {
+        Ok(i) if i.abs() <= MAX_INT => Ok(i as isize),
+        _ => {
+            let message = format!("The integer '{}' is out of range", int.as_str());
+            Err(syntax_error(int.as_span(), message))
+        }
+    }
+}
+
+fn parse_string(string_literal: Pair<Rule>) -> Result<String> {
+    unescape(first_inner(string_literal)?)
+}
+
+fn first_inner(pair: Pair<Rule>) -> Result<Pair<Rule>> {
+    pair.into_inner().next().ok_or(Error::PairsNextItemError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parser_rfc9535() -> Result<()> {
+        use PathItem::*;
+
+        assert_eq!(parse("$")?, &[][..]);
+        assert_eq!(
+            parse(r#"$.foo['bar', "é"][0, -1:][*] ..baz..*"#)?,
+            &[
+                Child("foo".into()),
+                Union(vec![Child("bar".into()), Child("\u{e9}".into())]),
+                Union(vec![Index(0), Slice { start: Some(-1), end: None, step: None }]),
+                Wildcard,
+                RecursiveDescent,
+                Child("baz".into()),
+                RecursiveDescent,
+                Wildcard,
+            ][..]
+        );
+
+        assert_eq!(
+            parse("$[?length(@.a) > 1 && !match(@.b, 'x.*')]")?,
+            &[
+                Filter(FilterExpr::And(
+                    Box::new(FilterExpr::Compare(
+                        Operand::Function(Function::Length, vec![
+                            Operand::Current(vec![Child("a".into())]),
+                        ]),
+                        Comparison::Gt,
+                        Operand::Literal(Value::from(1)),
+                    )),
+                    Box::new(FilterExpr::Not(Box::new(FilterExpr::Test(
+                        Operand::Function(Function::Match, vec![
+                            Operand::Current(vec![Child("b".into())]),
+                            Operand::Literal(Value::from("x.*")),
+                        ]),
+                    )))),
+                )),
+            ][..]
+        );
+
+        let invalid_expressions = [
+            "foo",
+            "$[foo]",
+            "$['a\"]",
+            "$[-]",
+            "$[01]",
+            "$[-0]",
+            "$[9007199254740992]",
+            "$. a",
+            "$.a ",
+            "$[?@.* == 1]",
+            "$[?length(@.a)]",
+            "$[?match(@.a, 'a') == true]",
+            "$[?count(1) > 0]",
+            "$[?foo(@.a)]",
+            "$[?length(@.a, 1) > 0]",
+            r"$['\ud83d']",
+        ];
+        for expression in &invalid_expressions {
+            assert!(parse(expression).is_err(), "{}", expression);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+//! JSON Patch (RFC 6902), a sequence of operations addressed by JSON Pointers.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::filter::json_equals;
+use crate::pointer::{parse_array_index, parse_pointer};
+use crate::error::{Error, Result};
+
+/// A JSON Patch operation, which (de)serializes as e.g. `{"op": "add", "path": "/a", "value": 1}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOp {
+    /// Inserts a member into an object or an element into an array (`-` appends), or replaces the
+    /// whole document if the path is empty.
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    /// Checks that the value at `path` is equal to `value`.
+    Test { path: String, value: Value },
+}
+
+/// Applies the operations in order. If any of them fails, `value` is left unchanged.
+pub fn apply_patch(value: &mut Value, patch: &[PatchOp]) -> Result<()> {
+    let mut patched = value.clone();
+    for op in patch {
+        apply(&mut patched, op)?;
+    }
+    *value = patched;
+    Ok(())
+}
+
+fn apply(doc: &mut Value, op: &PatchOp) -> Result<()> {
+    match op {
+        PatchOp::Add { path, value } => add(doc, path, value.clone()),
+        PatchOp::Remove { path } => remove(doc, path).map(|_| ()),
+        PatchOp::Replace { path, value } => {
+            *get_mut(doc, &parse_pointer(path)?, path)? = value.clone();
+            Ok(())
+        }
+        PatchOp::Move { from, path } => {
+            if from == path {
+                return get(doc, from).map(|_| ());
+            }
+            // A node cannot be moved into one of its own children.
+            if path.starts_with(from.as_str()) && path[from.len()..].starts_with('/') {
+                return Err(Error::PatchMoveError(from.to_owned(), path.to_owned()));
+            }
+            let value = remove(doc, from)?;
+            add(doc, path, value)
+        }
+        PatchOp::Copy { from, path } => {
+            let value = get(doc, from)?.clone();
+            add(doc, path, value)
+        }
+        PatchOp::Test { path, value } => {
+            if json_equals(get(doc, path)?, value) {
+                Ok(())
+            } else {
+                Err(Error::PatchTestError(path.to_owned()))
+            }
+        }
+    }
+}
+
+fn add(doc: &mut Value, path: &str, value: Value) -> Result<()> {
+    let mut tokens = parse_pointer(path)?;
+    let last = match tokens.pop() {
+        Some(last) => last,
+        None => {
+            *doc = value;
+            return Ok(());
+        }
+    };
+
+    match get_mut(doc, &tokens, path)? {
+        Value::Object(m) => {
+            m.insert(last, value);
+        }
+        Value::Array(a) => {
+            let index = if last == "-" {
+                a.len()
+            } else {
+                parse_array_index(&last)
+                    .filter(|&index| index <= a.len())
+                    .ok_or_else(|| Error::PointerNotFoundError(path.to_owned()))?
+            };
+            a.insert(index, value);
+        }
+        _ => return Err(Error::PointerNotFoundError(path.to_owned())),
+    }
+    Ok(())
+}
+
+fn remove(doc: &mut Value, path: &str) -> Result<Value> {
+    let mut tokens = parse_pointer(path)?;
+    let last = tokens.pop().ok_or_else(|| Error::InvalidPointerError(path.to_owned()))?;
+    let removed = match get_mut(doc, &tokens, path)? {
+        Value::Object(m) => m.remove(&last),
+        Value::Array(a) => {
+            parse_array_index(&last)
+                .filter(|&index| index < a.len())
+                .map(|index| a.remove(index))
+        }
+        _ => None,
+    };
+    removed.ok_or_else(|| Error::PointerNotFoundError(path.to_owned()))
+}
+
+fn get<'a>(doc: &'a Value, path: &str) -> Result<&'a Value> {
+    let mut curr = doc;
+    for token in parse_pointer(path)? {
+        curr = match curr {
+            Value::Object(m) => m.get(&token),
+            Value::Array(a) => parse_array_index(&token).and_then(|index| a.get(index)),
+            _ => None,
+        }.ok_or_else(|| Error::PointerNotFoundError(path.to_owned()))?;
+    }
+    Ok(curr)
+}
+
+fn get_mut<'a>(doc: &'a mut Value, tokens: &[String], path: &str) -> Result<&'a mut Value> {
+    let mut curr = doc;
+    for token in tokens {
+        curr = match curr {
+            Value::Object(m) => m.get_mut(token),
+            Value::Array(a) => parse_array_index(token).and_then(move |index| a.get_mut(index)),
+            _ => None,
+        }.ok_or_else(|| Error::PointerNotFoundError(path.to_owned()))?;
+    }
+    Ok(curr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn patch_operations() -> Result<()> {
+        let mut data = json!({
+            "foo": { "bar": [1, 2, 3] },
+            "baz": "qux",
+        });
+
+        let patch: Vec<PatchOp> = serde_json::from_value(json!([
+            { "op": "test", "path": "/baz", "value": "qux" },
+            { "op": "add", "path": "/foo/bar/1", "value": 1.5 },
+            { "op": "add", "path": "/foo/bar/-", "value": 4 },
+            { "op": "remove", "path": "/foo/bar/0" },
+            { "op": "replace", "path": "/baz", "value": { "a/b": null } },
+            { "op": "copy", "from": "/foo/bar", "path": "/copied" },
+            { "op": "move", "from": "/baz/a~1b", "path": "/foo/moved" },
+            { "op": "test", "path": "/copied/0", "value": 1.5 },
+            { "op": "test", "path": "/copied/2", "value": 3.0 },
+        ]))?;
+        apply_patch(&mut data, &patch)?;
+
+        assert_eq!(data, json!({
+            "foo": { "bar": [1.5, 2, 3, 4], "moved": null },
+            "baz": {},
+            "copied": [1.5, 2, 3, 4],
+        }));
+
+        apply_patch(&mut data, &[PatchOp::Add { path: "".into(), value: json!([]) }])?;
+        assert_eq!(data, json!([]));
+
+        // Numeric tokens are member names on objects.
+        let mut data = json!({ "0": 1, "1": 2 });
+        let patch: Vec<PatchOp> = serde_json::from_value(json!([
+            { "op": "add", "path": "/2", "value": 3 },
+            { "op": "replace", "path": "/0", "value": 0 },
+            { "op": "remove", "path": "/1" },
+        ]))?;
+        apply_patch(&mut data, &patch)?;
+        assert_eq!(data, json!({ "0": 0, "2": 3 }));
+
+        Ok(())
+    }
+
+    #[test]
+    fn patch_rollback() {
+        let data = json!({ "a": { "b": [0] }, "c": 1 });
+
+        let failing_patches = [
+            vec![PatchOp::Remove { path: "/c".into() }, PatchOp::Remove { path: "/d".into() }],
+            vec![PatchOp::Add { path: "/a/b/2".into(), value: json!(1) }],
+            vec![PatchOp::Add { path: "/a/x/y".into(), value: json!(1) }],
+            vec![PatchOp::Replace { path: "/x".into(), value: json!(1) }],
+            vec![PatchOp::Remove { path: "".into() }],
+            vec![PatchOp::Remove { path: "/a/b/00".into() }],
+            vec![PatchOp::Move { from: "/a".into(), path: "/a/b/0".into() }],
+            vec![PatchOp::Copy { from: "/x".into(), path: "/y".into() }],
+            vec![
+                PatchOp::Replace { path: "/c".into(), value: json!(2) },
+                PatchOp::Test { path: "/c".into(), value: json!(1) },
+            ],
+            vec![PatchOp::Test { path: "c".into(), value: json!(1) }],
+        ];
+
+        for patch in &failing_patches {
+            let mut patched = data.clone();
+            assert!(apply_patch(&mut patched, patch).is_err(), "{:?}", patch);
+            assert_eq!(patched, data);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Conversion between JSONPaths and JSON Pointers (RFC 6901), e.g., `foo.bar[0]` and `/foo/bar/0`.
+
+use crate::{PathItem, Selector, normalized_path};
+use crate::error::{Error, Result};
+
+impl Selector {
+    /// Creates a selector from a JSON Pointer. As a pointer does not tell array indices from object
+    /// keys, each token is resolved against the actual value, so `/0` selects the member `"0"` of
+    /// an object or the first element of an array.
+    pub fn from_pointer(pointer: &str) -> Result<Self> {
+        let json_path = parse_pointer(pointer)?.into_iter().map(PathItem::Token).collect();
+        Ok(Self {
+            json_path,
+        })
+    }
+
+    /// Formats the JSONPath as a JSON Pointer. The JSONPath must be singular and must not contain
+    /// negative indices.
+    pub fn to_pointer(&self) -> Result<String> {
+        let mut pointer = String::new();
+        for (i, item) in self.json_path.iter().enumerate() {
+            pointer.push('/');
+            match *item {
+                PathItem::Child(ref ident) | PathItem::Token(ref ident) => {
+                    pointer.push_str(&escape_pointer_token(ident))
+                }
+                PathItem::Index(index) if index >= 0 => pointer.push_str(&index.to_string()),
+                PathItem::Index(index) => {
+                    let path = normalized_path(&self.json_path[..i]);
+                    return Err(Error::JsonInvalidArrayIndexError { path, index });
+                }
+                _ => return Err(Error::NotSingularPathError),
+            }
+        }
+        Ok(pointer)
+    }
+}
+
+/// Splits a JSON Pointer into unescaped reference tokens. The empty pointer refers to the whole
+/// document.
+pub(crate) fn parse_pointer(pointer: &str) -> Result<Vec<String>> {
+    if pointer.is_empty() {
+        return Ok(vec![]);
+    }
+    if !pointer.starts_with('/') {
+        return Err(Error::InvalidPointerError(pointer.to_owned()));
+    }
+
+    pointer[1..]
+        .split('/')
+        .map(|token| {
+            let mut unescaped = String::with_capacity(token.len());
+            let mut chars = token.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '~' => match chars.next() {
+                        Some('0') => unescaped.push('~'),
+                        Some('1') => unescaped.push('/'),
+                        _ => return Err(Error::InvalidPointerError(pointer.to_owned())),
+                    },
+                    _ => unescaped.push(c),
+                }
+            }
+            Ok(unescaped)
+        })
+        .collect()
+}
+
+/// Escapes a reference token of a JSON Pointer, i.e., `~` as `~0` and `/` as `~1`.
+pub fn escape_pointer_token(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+/// Parses an array index token, which is either `0` or a decimal number without leading zeros.
+pub(crate) fn parse_array_index(token: &str) -> Option<usize> {
+    let is_index = token == "0" ||
+        (!token.starts_with('0') && !token.is_empty() && token.bytes().all(|b| b.is_ascii_digit()));
+    if is_index {
+        token.parse().ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    use crate::Mode;
+
+    #[test]
+    fn pointer_conversion() -> Result<()> {
+        let data = json!({
+            "foo": ["bar", { "a/b": 1, "m~n": 2, "": 3 }],
+            "007": true,
+        });
+
+        let selector = Selector::from_pointer("/foo/1/a~1b")?;
+        assert_eq!(selector.get(&data), Some(&json!(1)));
+        assert_eq!(selector.to_pointer()?, "/foo/1/a~1b");
+
+        assert_eq!(Selector::from_pointer("/foo/1/m~0n")?.get(&data), Some(&json!(2)));
+        assert_eq!(Selector::from_pointer("/foo/1/")?.get(&data), Some(&json!(3)));
+        assert_eq!(Selector::from_pointer("/007")?.get(&data), Some(&json!(true)));
+        assert_eq!(Selector::from_pointer("")?.get(&data), Some(&data));
+
+        let selector = Selector::with_mode("$.foo['m~n'][0]", Mode::Rfc9535)?;
+        assert_eq!(selector.to_pointer()?, "/foo/m~0n/0");
+        assert_eq!(Selector::new("$")?.to_pointer()?, "");
+        assert!(Selector::new("foo[-1]")?.to_pointer().is_err());
+        assert!(Selector::new("foo[*]")?.to_pointer().is_err());
+
+        // A numeric token is a member name on objects and an index on arrays.
+        let mut data = json!({ "0": ["a", "b"], "1": { "1": "c" } });
+        let selector = Selector::from_pointer("/0/1")?;
+        assert_eq!(selector.get(&data), Some(&json!("b")));
+        assert_eq!(selector.select_paths(&data), ["$['0'][1]"]);
+        assert_eq!(selector.to_pointer()?, "/0/1");
+        assert_eq!(Selector::from_pointer("/1/1")?.get(&data), Some(&json!("c")));
+        assert_eq!(Selector::from_pointer("/1/0")?.get(&data), None);
+        Selector::from_pointer("/1/0")?.set(&mut data, json!("d"))?;
+        Selector::from_pointer("/2/1")?.set(&mut data, json!("e"))?;
+        assert_eq!(Selector::from_pointer("/0/0")?.remove(&mut data), Some(json!("a")));
+        assert_eq!(data, json!({ "0": ["b"], "1": { "1": "c", "0": "d" }, "2": [null, "e"] }));
+
+        assert!(Selector::from_pointer("foo").is_err());
+        assert!(Selector::from_pointer("/foo~2").is_err());
+        assert!(Selector::from_pointer("/foo~").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,535 @@
+//! Evaluates a `Selector` while deserializing, so only the matched nodes are built into `Value`s.
+//!
+//! The JSONPath works as an NFA whose states are the positions in `json_path`. Each node is visited
+//! with the set of states reaching it, and a subtree reached by no state is skipped with
+//! `IgnoredAny`. A node is only deserialized into a `Value` if it is matched, or if it has to be
+//! tested by a filter, and then the rest of the JSONPath is evaluated in memory on that node.
+//!
+//! Thus the memory use is bounded by the largest of these nodes, rather than the document, with
+//! two exceptions:
+//!
+//! - A filter may test any descendant of a node, so a filter under `..`, e.g., `..[?(@.a)]`, builds
+//!   each child of the root in turn, along with its whole subtree.
+//! - The items needing the length of an array (negative indices and slices) keep the last elements
+//!   of the array in memory until it is known whether they are selected, e.g., the last 2 elements
+//!   for `[-2]`. A slice with a step below -1 and without a non-negative start keeps all of them.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{BufReader, Read};
+
+use serde::de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{PathItem, FilterExpr, Selector, cmp_path, locate, signed_index, slice_contains};
+use crate::pointer::parse_array_index;
+use crate::error::{Error, Result};
+
+impl Selector {
+    /// Reads a JSON document from `reader` and calls `f` on each matched node in document order.
+    /// The nodes are the same as the ones returned by `select_distinct`, so unlike `select`, a
+    /// node is yielded only once even if it is matched in several ways.
+    ///
+    /// Filters referring to the root node `$` are not supported.
+    pub fn stream<R: Read, F: FnMut(Value)>(&self, reader: R, f: F) -> Result<()> {
+        if self.refers_to_root() {
+            return Err(Error::StreamRootFilterError);
+        }
+
+        let mut deserializer = serde_json::Deserializer::from_reader(BufReader::new(reader));
+        self.stream_deserializer(&mut deserializer, f)?;
+        deserializer.end()?;
+        Ok(())
+    }
+
+    /// The same as `stream`, but reads from an arbitrary self-describing `Deserializer`, e.g., a
+    /// `serde_json::Deserializer` in the middle of a larger input.
+    pub fn stream_deserializer<'de, D, F>(&self, deserializer: D, mut f: F)
+        -> std::result::Result<(), D::Error>
+        where D: Deserializer<'de>,
+              F: FnMut(Value),
+    {
+        if self.refers_to_root() {
+            return Err(de::Error::custom(Error::StreamRootFilterError));
+        }
+
+        let seed = NodeSeed {
+            json_path: &self.json_path,
+            states: vec![0],
+            filters: vec![],
+            emit: &mut f,
+        };
+        seed.deserialize(deserializer)
+    }
+}
+
+/// The key of a child node.
+#[derive(Clone, Copy)]
+enum Key<'k> {
+    Name(&'k str),
+    Index(usize),
+}
+
+/// The states reaching a node, in which `filters` are the ones that can only be reached if the
+/// node passes the filter.
+type States<'p> = (Vec<usize>, Vec<(usize, &'p FilterExpr)>);
+
+struct NodeSeed<'p, 'e, F> {
+    json_path: &'p [PathItem],
+    states: Vec<usize>,
+    filters: Vec<(usize, &'p FilterExpr)>,
+    emit: &'e mut F,
+}
+
+impl<'p, 'e, F: FnMut(Value)> NodeSeed<'p, 'e, F> {
+    /// Tests the node against the filters, and evaluates the rest of the JSONPath in memory from
+    /// each state.
+    fn emit_in_memory(&mut self, value: &Value) {
+        for &(s, filter) in &self.filters {
+            if filter.test(value, value) {
+                self.states.push(s);
+            }
+        }
+
+        let mut nodes: Vec<(Vec<PathItem>, &Value)> = self.states
+            .iter()
+            .flat_map(|&s| locate(&self.json_path[s..], value, value))
+            .collect();
+        nodes.sort_by(|(a, _), (b, _)| cmp_path(a, b));
+        nodes.dedup_by(|(a, _), (b, _)| a == b);
+        for (_path, v) in nodes {
+            (self.emit)(v.clone());
+        }
+    }
+
+    /// Evaluates the JSONPath in memory on the element `index` of an array of length `len`.
+    fn emit_element(&mut self, index: usize, len: usize, value: &Value) {
+        let states = child_states(self.json_path, &self.states, Key::Index(index), Some(len));
+        self.child(states).emit_in_memory(value);
+    }
+
+    fn child<'a>(&'a mut self, (states, filters): States<'p>) -> NodeSeed<'p, 'a, F> {
+        NodeSeed {
+            json_path: self.json_path,
+            states,
+            filters,
+            emit: &mut *self.emit,
+        }
+    }
+}
+
+impl<'de, 'p, 'e, F: FnMut(Value)> DeserializeSeed<'de> for NodeSeed<'p, 'e, F> {
+    type Value = ();
+
+    fn deserialize<D>(mut self, deserializer: D) -> std::result::Result<(), D::Error>
+        where D: Deserializer<'de>
+    {
+        let json_path = self.json_path;
+
+        // The node has to be built in order to be tested.
+        if !self.filters.is_empty() {
+            let value = Value::deserialize(deserializer)?;
+            self.emit_in_memory(&value);
+            return Ok(());
+        }
+
+        // `..` also selects the node itself.
+        let mut i = 0;
+        while i < self.states.len() {
+            let s = self.states[i];
+            if let Some(PathItem::RecursiveDescent) = json_path.get(s) {
+                self.states.push(s + 1);
+            }
+            i += 1;
+        }
+        self.states.sort_unstable();
+        self.states.dedup();
+
+        if self.states.is_empty() {
+            IgnoredAny::deserialize(deserializer)?;
+        } else if self.states.contains(&json_path.len()) {
+            let value = Value::deserialize(deserializer)?;
+            self.emit_in_memory(&value);
+        } else {
+            deserializer.deserialize_any(self)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'de, 'p, 'e, F: FnMut(Value)> Visitor<'de> for NodeSeed<'p, 'e, F> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any JSON value")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(mut self, mut map: A) -> std::result::Result<(), A::Error> {
+        loop {
+            let key_seed = KeySeed {
+                json_path: self.json_path,
+                states: &self.states,
+            };
+            match map.next_key_seed(key_seed)? {
+                Some(states) => map.next_value_seed(self.child(states))?,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> std::result::Result<(), A::Error> {
+        let json_path = self.json_path;
+        if !self.states.iter().any(|&s| needs_length(&json_path[s])) {
+            for index in 0.. {
+                let states = child_states(json_path, &self.states, Key::Index(index), None);
+                if seq.next_element_seed(self.child(states))?.is_none() {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+
+        // Keeps the last elements until it is known whether they are selected. An element is
+        // selected in the same way by any array with more than `window` elements after it.
+        let window = self.states
+            .iter()
+            .try_fold(0, |w, &s| Some(w.max(window(&json_path[s])?)))
+            .unwrap_or(usize::MAX);
+        let mut pending = VecDeque::new();
+        let mut len = 0;
+        while let Some(value) = seq.next_element::<Value>()? {
+            pending.push_back(value);
+            len += 1;
+            if pending.len() > window {
+                if let Some(value) = pending.pop_front() {
+                    self.emit_element(len - window - 1, len, &value);
+                }
+            }
+        }
+        for (i, value) in pending.iter().enumerate() {
+            self.emit_element(len - pending.len() + i, len, value);
+        }
+        Ok(())
+    }
+
+    fn visit_bool<E>(self, _v: bool) -> std::result::Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_i64<E>(self, _v: i64) -> std::result::Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_u64<E>(self, _v: u64) -> std::result::Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_f64<E>(self, _v: f64) -> std::result::Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_str<E>(self, _v: &str) -> std::result::Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_unit<E>(self) -> std::result::Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_none<E>(self) -> std::result::Result<(), E> {
+        Ok(())
+    }
+}
+
+/// Reads a key of a map and computes the states of the corresponding value without allocating the
+/// key.
+struct KeySeed<'p, 's> {
+    json_path: &'p [PathItem],
+    states: &'s [usize],
+}
+
+impl<'de, 'p, 's> DeserializeSeed<'de> for KeySeed<'p, 's> {
+    type Value = States<'p>;
+
+    fn deserialize<D>(self, deserializer: D) -> std::result::Result<States<'p>, D::Error>
+        where D: Deserializer<'de>
+    {
+        deserializer.deserialize_str(self)
+    }
+}
+
+impl<'de, 'p, 's> Visitor<'de> for KeySeed<'p, 's> {
+    type Value = States<'p>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a string key")
+    }
+
+    fn visit_str<E>(self, key: &str) -> std::result::Result<States<'p>, E> {
+        Ok(child_states(self.json_path, self.states, Key::Name(key), None))
+    }
+}
+
+/// Computes the states of the child `key`. The length of the array, `len`, is only needed by the
+/// items for which `needs_length` is `true`.
+fn child_states<'p>(json_path: &'p [PathItem], states: &[usize], key: Key, len: Option<usize>)
+    -> States<'p>
+{
+    let mut child_states = Vec::new();
+    let mut filters = Vec::new();
+    for &s in states {
+        match &json_path[s] {
+            PathItem::RecursiveDescent => child_states.push(s),
+            PathItem::Union(items) => {
+                for item in items {
+                    transit(item, s, key, len, &mut child_states, &mut filters);
+                }
+            }
+            item => transit(item, s, key, len, &mut child_states, &mut filters),
+        }
+    }
+    child_states.sort_unstable();
+    child_states.dedup();
+    (child_states, filters)
+}
+
+/// Moves from state `s` to the child `key` through `item`.
+fn transit<'p>(
+    item: &'p PathItem,
+    s: usize,
+    key: Key,
+    len: Option<usize>,
+    states: &mut Vec<usize>,
+    filters: &mut Vec<(usize, &'p FilterExpr)>,
+) {
+    let is_selected = match (item, key) {
+        (_, Key::Index(k)) if needs_length(item) => {
+            len.is_some_and(|len| selects_index(item, k, len))
+        }
+        (PathItem::Child(name), Key::Name(k)) => name == k,
+        (PathItem::Index(i), Key::Index(k)) => *i >= 0 && *i as usize == k,
+        (PathItem::Token(token), Key::Name(k)) => token == k,
+        (PathItem::Token(token), Key::Index(k)) => parse_array_index(token) == Some(k),
+        (PathItem::Wildcard, _) => true,
+        (PathItem::Slice { start, end, step }, Key::Index(k)) => {
+            // Negative bounds and steps are handled above, see `needs_length`.
+            let (start, step) = (start.unwrap_or(0) as usize, step.unwrap_or(1) as usize);
+            step != 0 &&
+                k >= start &&
+                end.is_none_or(|end| k < end as usize) &&
+                (k - start) % step == 0
+        }
+        (PathItem::Filter(filter), _) => {
+            filters.push((s + 1, filter));
+            false
+        }
+        _ => false,
+    };
+
+    if is_selected {
+        states.push(s + 1);
+    }
+}
+
+/// Returns `true` if the index or the slice selects the element `k` of an array of length `len`.
+fn selects_index(item: &PathItem, k: usize, len: usize) -> bool {
+    match item {
+        PathItem::Index(index) => signed_index(*index, len) == Some(k),
+        PathItem::Slice { start, end, step } => slice_contains(*start, *end, *step, len, k),
+        _ => false,
+    }
+}
+
+/// Returns how many elements there must be after an element of an array to tell whether the item
+/// selects it, or `None` if only the whole array tells.
+fn window(item: &PathItem) -> Option<usize> {
+    let magnitude = |bound: Option<isize>| bound.filter(|&b| b < 0).map_or(0, isize::unsigned_abs);
+    match item {
+        PathItem::Index(index) => Some(magnitude(Some(*index))),
+        PathItem::Slice { start, end, step } => {
+            // With a step below -1, the indices are counted from the upper bound, which may depend
+            // on the length.
+            if step.is_some_and(|step| step < -1) {
+                return None;
+            }
+            Some(magnitude(*start).max(magnitude(*end)))
+        }
+        PathItem::Union(items) => items.iter().try_fold(0, |w, item| Some(w.max(window(item)?))),
+        _ => Some(0),
+    }
+}
+
+/// Returns `true` if the item cannot be applied without knowing the length of the array.
+fn needs_length(item: &PathItem) -> bool {
+    match item {
+        PathItem::Index(index) => *index < 0,
+        PathItem::Slice { start, end, step } => {
+            [start, end, step].iter().any(|bound| bound.is_some_and(|i| i < 0))
+        }
+        PathItem::Union(items) => items.iter().any(needs_length),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    use crate::Mode;
+
+    fn stream(selector: &Selector, data: &Value) -> Vec<Value> {
+        let mut nodes = vec![];
+        selector
+            .stream(data.to_string().as_bytes(), |v| nodes.push(v))
+            .unwrap();
+        nodes
+    }
+
+    #[test]
+    fn stream_same_as_select() -> Result<()> {
+        let data = json!({
+            "store": {
+                "book": [
+                    { "category": "reference", "author": "Nigel Rees", "price": 8.95 },
+                    { "category": "fiction", "author": "Evelyn Waugh", "price": 12.99 },
+                    { "category": "fiction", "author": "Herman Melville", "price": 8.99,
+                      "isbn": "0-553-21311-3" },
+                    { "category": "fiction", "author": "J. R. R. Tolkien", "price": 22.99,
+                      "isbn": "0-395-19395-8" }
+                ],
+                "bicycle": { "color": "red", "price": 19.95 }
+            },
+            "expensive": 10
+        });
+
+        let expressions = [
+            "$",
+            "store.book[*].author",
+            "..author",
+            "store.*",
+            "store..price",
+            "..book[2]",
+            "..book[-1]",
+            "..book[0:2].author",
+            "..book[1::2].price",
+            "..book[?(@.isbn)].author",
+            "..book[?(@.price < 10)]",
+            "store.book[?(@.category == 'fiction')][?(@ > 10)]",
+            "nothing..here",
+            "expensive.price",
+        ];
+        for expression in &expressions {
+            let selector = Selector::new(expression)?;
+            let expected: Vec<Value> = selector.select(&data).into_iter().cloned().collect();
+            assert_eq!(stream(&selector, &data), expected, "{}", expression);
+        }
+
+        // Nodes are always yielded in document order, i.e., pre-order.
+        let selector = Selector::new("..book[::-1].price")?;
+        assert_eq!(
+            stream(&selector, &data),
+            vec![json!(8.95), json!(12.99), json!(8.99), json!(22.99)]
+        );
+
+        let selector = Selector::new("..*")?;
+        let nodes = stream(&selector, &data);
+        assert_eq!(nodes.len(), selector.select(&data).len());
+        assert_eq!(nodes[3..5], [json!("red"), json!(19.95)]);
+
+        let selector = Selector::with_mode("$.store.book[0, 3]['author', 'price']", Mode::Rfc9535)?;
+        assert_eq!(
+            stream(&selector, &data),
+            vec![json!("Nigel Rees"), json!(8.95), json!("J. R. R. Tolkien"), json!(22.99)]
+        );
+
+        let data = json!({ "0": [{ "1": true }, 2] });
+        for pointer in ["/0/1", "/0/0/1", "/0/0/0"] {
+            let selector = Selector::from_pointer(pointer)?;
+            let expected: Vec<Value> = selector.select(&data).into_iter().cloned().collect();
+            assert_eq!(stream(&selector, &data), expected, "{}", pointer);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn stream_deduplicates_nodes() -> Result<()> {
+        let data = json!({ "a": { "a": { "b": 1 } } });
+        let selector = Selector::new("..a..b")?;
+        assert_eq!(selector.select(&data).len(), 2);
+        assert_eq!(stream(&selector, &data), vec![json!(1)]);
+        assert_eq!(selector.select_distinct(&data), vec![&json!(1)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn stream_length_dependent_items() -> Result<()> {
+        let expressions = [
+            "$[-1]", "$[-3]", "$[:-2]", "$[-2:]", "$[-4:-1:2]", "$[1:-1]", "$[5:-5]", "$[::-1]",
+            "$[-1::-1]", "$[:1:-1]", "$[-2:0:-1]", "$[::-2]", "$[3::-2]", "$[-2::-3]", "$[0, -1]",
+            "$[-1, 1:]", "$..[-1]", "$[*][-2:]",
+        ];
+        for len in 0..7 {
+            let data: Value = (0..len).map(|i| json!([i, [i]])).collect();
+            for expression in &expressions {
+                let selector = Selector::with_mode(expression, Mode::Rfc9535)?;
+                let expected: Vec<Value> =
+                    selector.select_distinct(&data).into_iter().cloned().collect();
+                assert_eq!(stream(&selector, &data), expected, "{} on {}", expression, data);
+            }
+        }
+
+        // The elements are yielded before the end of the array.
+        let selector = Selector::new("[:-1]")?;
+        let mut nodes = vec![];
+        let input = (&b"[1, 2, 3, "[..]).chain(FailingReader);
+        assert!(selector.stream(input, |v| nodes.push(v)).is_err());
+        assert_eq!(nodes, vec![json!(1), json!(2)]);
+
+        Ok(())
+    }
+
+    struct FailingReader;
+
+    impl Read for FailingReader {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+    }
+
+    #[test]
+    fn stream_errors() -> Result<()> {
+        let selector = Selector::new("foo")?;
+        assert!(selector.stream(&b"{\"foo\": [1, 2"[..], |_| ()).is_err());
+        assert!(selector.stream(&b"{} {}"[..], |_| ()).is_err());
+
+        let selector = Selector::new("[?(@.a == $.b)]")?;
+        assert!(selector.stream(&b"[]"[..], |_| ()).is_err());
+
+        // A `serde_json::Deserializer` can be reused for multiple documents.
+        let mut deserializer = serde_json::Deserializer::from_str(r#"{"foo": 1} {"foo": 2}"#);
+        let mut nodes = vec![];
+        selector_stream_twice(&Selector::new("foo")?, &mut deserializer, &mut nodes);
+        assert_eq!(nodes, vec![json!(1), json!(2)]);
+
+        Ok(())
+    }
+
+    fn selector_stream_twice<'de, R>(
+        selector: &Selector,
+        deserializer: &mut serde_json::Deserializer<R>,
+        nodes: &mut Vec<Value>,
+    )
+        where R: serde_json::de::Read<'de>
+    {
+        for _ in 0..2 {
+            selector
+                .stream_deserializer(&mut *deserializer, |v| nodes.push(v))
+                .unwrap();
+        }
+    }
+}
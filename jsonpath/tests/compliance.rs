@@ -0,0 +1,93 @@
+//! Runs the JSONPath Compliance Test Suite against the RFC 9535 mode. The suite is vendored from
+//! <https://github.com/jsonpath-standard/jsonpath-compliance-test-suite> as `tests/data/cts.json`.
+
+use std::fs;
+use std::path::PathBuf;
+
+use serde_json::Value;
+
+use jsonpath::{Mode, Selector};
+
+/// The number of test cases in the vendored suite, so a case that is skipped or a truncated file is
+/// noticed.
+const CASE_COUNT: usize = 703;
+
+/// Returns the field of a test case, which is a failure if it is missing.
+fn field<'a>(case: &'a Value, name: &str) -> Result<&'a Value, String> {
+    case.get(name).ok_or_else(|| format!("missing `{}`", name))
+}
+
+fn array<'a>(case: &'a Value, name: &str) -> Result<&'a Vec<Value>, String> {
+    field(case, name)?.as_array().ok_or_else(|| format!("`{}` is not an array", name))
+}
+
+/// Runs a single test case. Returns a description of the failure, if any.
+fn run(case: &Value) -> Result<(), String> {
+    let expression = field(case, "selector")?
+        .as_str()
+        .ok_or("`selector` is not a string")?;
+    let selector = Selector::with_mode(expression, Mode::Rfc9535);
+
+    if case["invalid_selector"].as_bool() == Some(true) {
+        return match selector {
+            Ok(_) => Err("expected an invalid selector".to_owned()),
+            Err(_) => Ok(()),
+        };
+    }
+
+    let selector = selector.map_err(|e| format!("failed to parse: {}", e))?;
+    let document = field(case, "document")?;
+    let nodes: Vec<Value> = selector.select(document).into_iter().cloned().collect();
+    let paths: Vec<Value> = selector
+        .select_paths(document)
+        .into_iter()
+        .map(Value::String)
+        .collect();
+
+    // Some results are non-deterministic as the order of object members is unspecified.
+    let (results, results_paths) = match case.get("result") {
+        Some(result) => (vec![result], vec![field(case, "result_paths")?]),
+        None => (
+            array(case, "results")?.iter().collect(),
+            array(case, "results_paths")?.iter().collect(),
+        ),
+    };
+    if results.len() != results_paths.len() {
+        return Err("`results` and `results_paths` differ in length".to_owned());
+    }
+
+    let is_expected = results
+        .iter()
+        .zip(&results_paths)
+        .any(|(result, result_paths)| {
+            result.as_array() == Some(&nodes) && result_paths.as_array() == Some(&paths)
+        });
+    if is_expected {
+        Ok(())
+    } else {
+        Err(format!("got {:?} at {:?}", nodes, paths))
+    }
+}
+
+#[test]
+fn compliance_test_suite() {
+    let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    p.push("tests/data/cts.json");
+    let cts: Value = serde_json::from_str(&fs::read_to_string(p).unwrap()).unwrap();
+
+    let cases = cts["tests"].as_array().unwrap();
+    assert_eq!(cases.len(), CASE_COUNT);
+    let failures: Vec<String> = cases
+        .iter()
+        .filter_map(|case| {
+            run(case).err().map(|failure| {
+                format!("{} (`{}`): {}", case["name"], case["selector"], failure)
+            })
+        })
+        .collect();
+
+    assert!(
+        failures.is_empty(),
+        "{} test case(s) failed:\n{}", failures.len(), failures.join("\n")
+    );
+}
@@ -0,0 +1,108 @@
+//! Compares the throughput of concurrent `fetch_merged` calls on `Config`, which reads from
+//! atomically swapped snapshots, with the previous design of `get_merged` on a `Mutex<Local>`.
+//!
+//!     cargo run --release --example bench_fetch [THREADS] [ITERATIONS]
+
+use std::env;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use config::File as LibConfigFile;
+
+use merge_configs::config::Config;
+use merge_configs::local::Local;
+
+const BRANCHES: [&str; 4] = [
+    "itscrap.com",
+    "whorepresents.com",
+    "childrenswear.co.uk",
+    "localhost",
+];
+
+fn new_local() -> Local {
+    let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    p.push("assets/config.json");
+    let mut local = Local::new();
+    local.merge(LibConfigFile::from(p)).unwrap();
+    local
+}
+
+/// Calls `f` on the threads while `write` updates the config every millisecond, and prints the
+/// total number of calls per second.
+fn run<F, W>(name: &str, n_threads: usize, n_iterations: usize, f: F, write: W)
+    where F: Fn(&str) + Sync,
+          W: Fn(u32) + Sync,
+{
+    let is_done = AtomicBool::new(false);
+    let start = Instant::now();
+    thread::scope(|s| {
+        let readers: Vec<_> = (0..n_threads)
+            .map(|i| {
+                let f = &f;
+                s.spawn(move || {
+                    for j in 0..n_iterations {
+                        f(BRANCHES[(i + j) % BRANCHES.len()]);
+                    }
+                })
+            })
+            .collect();
+        s.spawn(|| {
+            let mut i = 0;
+            while !is_done.load(Ordering::Relaxed) {
+                write(i);
+                i += 1;
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        is_done.store(true, Ordering::Relaxed);
+    });
+
+    let elapsed = start.elapsed().as_secs_f64();
+    let throughput = (n_threads * n_iterations) as f64 / elapsed;
+    println!("{:<24} {:>8.3} s {:>14.0} calls/s", name, elapsed, throughput);
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let n_threads = args.next().and_then(|s| s.parse().ok()).unwrap_or(8);
+    let n_iterations = args.next().and_then(|s| s.parse().ok()).unwrap_or(100_000);
+    println!("{} threads x {} calls", n_threads, n_iterations);
+
+    let local = Arc::new(Mutex::new(new_local()));
+    run(
+        "Mutex<Local>::get_merged",
+        n_threads,
+        n_iterations,
+        |branch| {
+            local.lock().unwrap().get_merged(branch).unwrap();
+        },
+        |i| local.lock().unwrap().set_default_by("rating", i.into()).unwrap(),
+    );
+
+    let config = Config::new(new_local()).unwrap();
+    run(
+        "Config::fetch_merged",
+        n_threads,
+        n_iterations,
+        |branch| {
+            config.fetch_merged(branch).unwrap();
+        },
+        |i| config.set_default_by("rating", i).unwrap(),
+    );
+    run(
+        "Config::snapshot",
+        n_threads,
+        n_iterations,
+        |branch| {
+            assert!(config.snapshot().merged(branch).is_some());
+        },
+        |i| config.set_default_by("rating", i).unwrap(),
+    );
+}
@@ -1,57 +1,461 @@
-#![allow(dead_code)]
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
 
+use arc_swap::ArcSwap;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use serde::Deserialize;
 use serde_json::Value;
 
+use jsonpath::Selector;
+
+use crate::DEFAULT_ID;
 use crate::local::Local;
+use crate::provenance::Provenance;
+use crate::remote::{RemoteLayer, RemoteSource, RemoteTree};
+use crate::schema::Violation;
 use crate::value_ext::ValueExt;
-use crate::error::Result;
+use crate::error::{Error, Result};
 
+/// The config shared between threads.
+///
+/// The reads go to an immutable `Snapshot`, which is swapped atomically after every change, so
+/// they never block each other or the writers. The writes go to `local` under the lock.
 #[derive(Clone)]
 pub struct Config {
+    // The local and remote sources and the runtime values. Every write is applied to it under the
+    // lock, and then written through to a new snapshot before the lock is released.
     local: Arc<Mutex<Local>>,
+    // Serves all the reads except `explain` and `validate_all`, which need the layers in `local`.
+    snapshot: Arc<ArcSwap<Snapshot>>,
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+/// An immutable view of the config, with the merged trees of all the branches precomputed. The
+/// trees are shared with the readers by `Config::fetch_merged`.
+#[derive(Debug)]
+pub struct Snapshot {
+    default: Arc<Value>,
+    overrides: Value,
+    merged: HashMap<String, Arc<Value>>,
+}
+
+impl Snapshot {
+    /// Builds the snapshot. The branches which fail to merge, e.g., those in a cycle, are left
+    /// out, and reading them reports the error from `Local`.
+    fn build(local: &mut Local) -> Result<Self> {
+        let default = Arc::new(local.default_cache()?.clone());
+        let overrides = local.override_cache()?.clone();
+        let mut merged = HashMap::new();
+        for branch in overrides.as_map()?.keys() {
+            if let Ok(value) = local.get_merged(branch) {
+                merged.insert(branch.to_owned(), Arc::new(value));
+            }
+        }
+        Ok(Self {
+            default,
+            overrides,
+            merged,
+        })
+    }
+
+    /// Returns the default tree.
+    pub fn default_tree(&self) -> &Value {
+        &self.default
+    }
+
+    /// Returns the overrides of `branch`, or the default tree for `_default_`.
+    pub fn branch(&self, branch: &str) -> Option<&Value> {
+        if branch == DEFAULT_ID {
+            return Some(&self.default);
+        }
+        self.overrides.get(branch)
+    }
+
+    /// Returns the merged tree of `branch`, or the default tree for `_default_`.
+    pub fn merged(&self, branch: &str) -> Option<&Value> {
+        self.merged_arc(branch).map(|value| &**value)
+    }
+
+    fn merged_arc(&self, branch: &str) -> Option<&Arc<Value>> {
+        if branch == DEFAULT_ID {
+            return Some(&self.default);
+        }
+        self.merged.get(branch)
+    }
+}
+
+/// A change of the value subscribed by `Config::subscribe`. `None` means the key does not exist.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueChange {
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+struct Subscriber {
+    branch: String,
+    selector: Selector,
+    value: Option<Value>,
+    sender: Sender<ValueChange>,
+}
 
-    // Currently it has no `remote`, so all of the public APIs just wrap around `local`.
+/// Watches the files for `Config::watch`, which stops when it is dropped.
+pub struct ConfigWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+/// Watches the remote sources for `Config::watch_remotes`, which stops when it is dropped.
+pub struct RemoteWatcher {
+    is_stopped: Arc<AtomicBool>,
+}
+
+impl Drop for RemoteWatcher {
+    fn drop(&mut self) {
+        self.is_stopped.store(true, Ordering::Relaxed);
+    }
 }
 
 impl Config {
-    pub fn new(local: Local) -> Self {
-        Self {
+    pub fn new(mut local: Local) -> Result<Self> {
+        let snapshot = Snapshot::build(&mut local)?;
+        Ok(Self {
             local: Arc::new(Mutex::new(local)),
+            snapshot: Arc::new(ArcSwap::from_pointee(snapshot)),
+            subscribers: Arc::new(Mutex::new(vec![])),
+        })
+    }
+
+    /// Returns the current snapshot, which stays unchanged while it is held.
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.snapshot.load_full()
+    }
+
+    /// Reloads the local sources and the remote sources whose versions have changed, and notifies
+    /// the subscribers of the changed values.
+    pub fn refresh(&self) -> Result<()> {
+        let trees = self.fetch_remotes()?;
+        self.update(|local| {
+            local.apply_remote_trees(trees);
+            local.reload();
+            Ok(())
+        })
+    }
+
+    /// Fetches the remote sources whose versions have changed. Returns whether any is updated.
+    pub fn refresh_remotes(&self) -> Result<bool> {
+        let trees = self.fetch_remotes()?;
+        if trees.is_empty() {
+            return Ok(false);
+        }
+        self.update(|local| Ok(local.apply_remote_trees(trees)))
+    }
+
+    /// Fetches the remote source and layers it over the local sources and the remote sources added
+    /// before.
+    pub fn add_remote<R>(&self, remote: R) -> Result<()>
+        where R: RemoteSource + 'static
+    {
+        let layer = RemoteLayer::new(Arc::new(remote))?;
+        self.update(|local| local.add_remote_layer(layer).map(|_| ()))
+    }
+
+    /// Watches the remote sources added so far with `RemoteSource::watch`, and refreshes them when
+    /// their versions change. Each watch call blocks for up to `timeout`, which also bounds how
+    /// long the threads outlive the returned watcher. The errors are ignored like in `watch`.
+    pub fn watch_remotes(&self, timeout: Duration) -> Result<RemoteWatcher> {
+        let is_stopped = Arc::new(AtomicBool::new(false));
+        let remotes = self.local.lock()?.remote_versions();
+        for (source, mut version) in remotes {
+            let config = self.clone();
+            let is_stopped = Arc::clone(&is_stopped);
+            thread::spawn(move || {
+                while !is_stopped.load(Ordering::Relaxed) {
+                    match source.watch(&version, timeout) {
+                        // Keeps the old version on errors, so the change is retried.
+                        Ok(Some(new_version)) => match config.refresh_remotes() {
+                            Ok(_) => version = new_version,
+                            Err(_) => thread::sleep(timeout),
+                        },
+                        Ok(None) => (),
+                        // Backs off, as the source may fail right away again.
+                        Err(_) => thread::sleep(timeout),
+                    }
+                }
+            });
+        }
+
+        Ok(RemoteWatcher {
+            is_stopped,
+        })
+    }
+
+    /// Subscribes to the merged value of `key` in `branch` (or the default value for
+    /// `_default_`). A `ValueChange` is sent whenever the value changes after a refresh or a set.
+    pub fn subscribe(&self, branch: &str, key: &str) -> Result<Receiver<ValueChange>> {
+        let selector = Selector::new(key)?;
+        let value = Self::lookup(&self.snapshot.load(), branch, &selector);
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock()?.push(Subscriber {
+            branch: branch.to_owned(),
+            selector,
+            value,
+            sender,
+        });
+        Ok(receiver)
+    }
+
+    /// Watches the files and refreshes the config when they change. A burst of changes within
+    /// `debounce` triggers a single refresh. The errors of the refreshes are ignored, so a file
+    /// being edited is simply picked up by the next change.
+    pub fn watch<P: AsRef<Path>>(&self, files: &[P], debounce: Duration) -> Result<ConfigWatcher> {
+        let files = files
+            .iter()
+            .map(|f| f.as_ref().canonicalize())
+            .collect::<std::result::Result<Vec<PathBuf>, _>>()?;
+
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        // Watches the parent directories, as editors often replace a file rather than write it.
+        for file in &files {
+            if let Some(dir) = file.parent() {
+                watcher.watch(dir, RecursiveMode::NonRecursive)?;
+            }
         }
+
+        let config = self.clone();
+        let is_watched = move |event: notify::Result<notify::Event>| {
+            event.is_ok_and(|e| e.paths.iter().any(|p| files.contains(p)))
+        };
+        thread::spawn(move || {
+            // The loop ends when the watcher is dropped and the channel is disconnected.
+            while let Ok(event) = receiver.recv() {
+                if !is_watched(event) {
+                    continue;
+                }
+                loop {
+                    match receiver.recv_timeout(debounce) {
+                        Ok(_) => continue,
+                        Err(RecvTimeoutError::Timeout) => break,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+                let _ = config.refresh();
+            }
+        });
+
+        Ok(ConfigWatcher {
+            _watcher: watcher,
+        })
+    }
+
+    /// Explains where the merged value of `key` in `branch` comes from, including the shadowed
+    /// values.
+    pub fn explain(&self, branch: &str, key: &str) -> Result<Provenance> {
+        self.local.lock()?.explain(branch, key)
+    }
+
+    /// Validates the default tree and the merged tree of every branch against the schema.
+    pub fn validate_all(&self) -> Result<Vec<Violation>> {
+        self.local.lock()?.validate_all()
     }
 
     pub fn set_default_by<T: Into<Value>>(&self, key: &str, value: T) -> Result<()> {
-        self.local.lock()?.set_default_by(key, value.into())
+        self.update(|local| local.set_default_by(key, value.into()))
     }
 
     pub fn fetch_default_by<'de, T: Deserialize<'de>>(&self, key: &str) -> Result<T> {
-        let mut local = self.local.lock()?;
-        let default_cache = local.default_cache()?;
-        Ok(T::deserialize(default_cache.get_value(key)?.clone())?)
+        self.fetch_override_by(DEFAULT_ID, key)
     }
 
     pub fn set_override_by<T: Into<Value>>(&self, branch: &str, key: &str, value: T)
         -> Result<()>
     {
-        self.local.lock()?.set_override_by(branch, key, value.into())
+        self.update(|local| local.set_override_by(branch, key, value.into()))
     }
 
     pub fn fetch_override_by<'de, T: Deserialize<'de>>(&self, branch: &str, key: &str)
         -> Result<T>
+    {
+        let selector = Selector::new(key)?;
+        let snapshot = self.snapshot.load();
+        let tree = snapshot
+            .branch(branch)
+            .ok_or_else(|| Error::BranchNotFoundError(branch.to_owned()))?;
+        let value = selector
+            .get(tree)
+            .ok_or_else(|| Error::KeyNotFoundError(key.to_owned()))?;
+        Ok(T::deserialize(value.clone())?)
+    }
+
+    /// Returns the merged tree of `branch`, or the default tree for `_default_`. The tree is
+    /// shared with the current snapshot rather than cloned.
+    pub fn fetch_merged(&self, branch: &str) -> Result<Arc<Value>> {
+        match self.snapshot.load().merged_arc(branch) {
+            Some(value) => Ok(Arc::clone(value)),
+            // Gets the error.
+            None => self.local.lock()?.get_merged(branch).map(Arc::new),
+        }
+    }
+
+    pub fn fetch_merged2(&self, branch: &str) -> Result<Arc<Value>> {
+        self.fetch_merged(branch)
+    }
+
+    /// Fetches the trees of the remote sources whose versions have changed without holding the
+    /// lock of `local`.
+    fn fetch_remotes(&self) -> Result<Vec<RemoteTree>> {
+        let remotes = self.local.lock()?.remote_versions();
+        let mut trees = vec![];
+        for (source, version) in remotes {
+            trees.extend(RemoteTree::fetch(source, version)?);
+        }
+        Ok(trees)
+    }
+
+    /// Applies the change to `local`, swaps in a new snapshot and notifies the subscribers.
+    fn update<T, F>(&self, f: F) -> Result<T>
+        where F: FnOnce(&mut Local) -> Result<T>
     {
         let mut local = self.local.lock()?;
-        let override_cache = local.override_cache()?;
-        let override_key = format!("{}.{}", branch, key);
-        Ok(T::deserialize(override_cache.get_value(&override_key)?.clone())?)
+        let result = f(&mut local);
+        // Even a failed change may have been partially applied.
+        let snapshot = Arc::new(Snapshot::build(&mut local)?);
+        self.snapshot.store(Arc::clone(&snapshot));
+        // Notifies under the lock of `local`, so the changes are sent in order.
+        self.notify_subscribers(&snapshot)?;
+        result
+    }
+
+    fn lookup(snapshot: &Snapshot, branch: &str, selector: &Selector) -> Option<Value> {
+        snapshot.merged(branch).and_then(|tree| selector.get(tree)).cloned()
     }
 
-    pub fn fetch_merged(&self, branch: &str) -> Result<Value> {
-        self.local.lock()?.get_merged(branch)
+    /// Sends the changed values to the subscribers, and drops those who have hung up.
+    fn notify_subscribers(&self, snapshot: &Snapshot) -> Result<()> {
+        self.subscribers.lock()?.retain_mut(|s| {
+            let value = Self::lookup(snapshot, &s.branch, &s.selector);
+            if value == s.value {
+                return true;
+            }
+
+            let old = std::mem::replace(&mut s.value, value.clone());
+            s.sender.send(ValueChange { old, new: value }).is_ok()
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use config::File as LibConfigFile;
+    use serde_json::json;
+
+    #[test]
+    fn config_subscribe() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("merge-configs-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let file = dir.join("config.json");
+        let content = |rating: u32| {
+            json!({ "rating": 0, "_override_": { "whorepresents.com": { "rating": rating } } })
+                .to_string()
+        };
+        fs::write(&file, content(95))?;
+
+        let mut local = Local::new();
+        local.merge(LibConfigFile::from(file.clone()))?;
+        let config = Config::new(local)?;
+        let rating = config.subscribe("whorepresents.com", "rating")?;
+        let site_name = config.subscribe("_default_", "site_name")?;
+
+        config.set_default_by("site_name", "Home")?;
+        let change = ValueChange { old: None, new: Some(json!("Home")) };
+        assert_eq!(site_name.try_recv().ok(), Some(change));
+        assert!(rating.try_recv().is_err());
+
+        let _watcher = config.watch(&[&file], Duration::from_millis(300))?;
+        for i in 1..=5 {
+            fs::write(&file, content(95 + i))?;
+        }
+        let change = rating.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(change, ValueChange { old: Some(json!(95)), new: Some(json!(100)) });
+        assert!(rating.recv_timeout(Duration::from_millis(600)).is_err());
+        assert!(site_name.try_recv().is_err());
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn config_remotes() -> Result<()> {
+        use crate::remote::HttpSource;
+        use crate::remote::tests::TestServer;
+
+        let server = TestServer::start(&json!({ "rating": 1 }));
+        let config = Config::new(Local::new())?;
+        let rating = config.subscribe("_default_", "rating")?;
+        config.add_remote(HttpSource::new(&server.url))?;
+        assert_eq!(rating.try_recv().ok(), Some(ValueChange { old: None, new: Some(json!(1)) }));
+        assert!(!config.refresh_remotes()?);
+
+        let watcher = config.watch_remotes(Duration::from_secs(5))?;
+        server.update(&json!({ "rating": 2 }));
+        let change = rating.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(change, ValueChange { old: Some(json!(1)), new: Some(json!(2)) });
+        drop(watcher);
+
+        server.update(&json!([2]));
+        assert!(matches!(config.refresh_remotes(), Err(Error::NotJsonMapError)));
+        assert_eq!(config.fetch_default_by::<u32>("rating")?, 2);
+        Ok(())
     }
 
-    pub fn fetch_merged2(&self, branch: &str) -> Result<Value> {
-        self.local.lock()?.get_merged2(branch)
+    #[test]
+    fn config_snapshot() -> Result<()> {
+        let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        p.push("assets/config.json");
+        let mut local = Local::new();
+        local.merge(LibConfigFile::from(p))?;
+        let config = Config::new(local)?;
+
+        let snapshot = config.snapshot();
+        assert_eq!(snapshot.merged("whorepresents.com").unwrap()["rating"], 95);
+        assert_eq!(config.fetch_override_by::<u32>("whorepresents.com", "rating")?, 95);
+        assert_eq!(config.fetch_default_by::<String>("site_name")?, "Joe's server");
+        assert!(config.fetch_merged("no.such.branch").is_err());
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let config = config.clone();
+                thread::spawn(move || {
+                    let mut last = 0;
+                    for _ in 0..1000 {
+                        let rating = config.fetch_merged("localhost").unwrap()["rating"]
+                            .as_u64()
+                            .unwrap();
+                        assert!(rating >= last);
+                        last = rating;
+                    }
+                })
+            })
+            .collect();
+        for i in 1..=100 {
+            config.set_override_by("localhost", "rating", i)?;
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        // The snapshot taken before is not affected.
+        assert_eq!(snapshot.merged("localhost").unwrap()["rating"], 0);
+        assert_eq!(config.snapshot().merged("localhost").unwrap()["rating"], 100);
+        Ok(())
     }
 }
@@ -1,46 +1,95 @@
+use std::io::Error as IoError;
 use std::net::AddrParseError;
 use std::sync::PoisonError;
 
 use config::ConfigError;
+use jsonpath::error::{Error as JsonPathError, JsonType};
 use serde_json::Error as JsonError;
 
-pub use failure::{format_err, Fail, Error as FailureError};
+use crate::schema::Violation;
+
+use thiserror::Error;
 pub type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Fail, Debug)]
+#[derive(Error, Debug)]
 pub enum Error {
-    #[fail(display = "The value is not a JSON map")]
+    #[error("The value is not a JSON map")]
     NotJsonMapError,
 
-    #[fail(display = "The index/key is invalid for JSON array/object")]
+    #[error("The index/key is invalid for JSON array/object")]
     JsonInvalidIndexError,
 
-    #[fail(display = "The lock is poisoned: {}", _0)]
+    #[error("The key '{0}' does not exist")]
+    KeyNotFoundError(String),
+
+    #[error("The branch '{0}' does not exist")]
+    BranchNotFoundError(String),
+
+    #[error("The schema is invalid: {0}")]
+    InvalidSchemaError(String),
+
+    #[error("The config violates the schema:{}", format_violations(.0))]
+    ValidationError(Vec<Violation>),
+
+    #[error("Remote source failure: {0}")]
+    RemoteError(String),
+
+    #[error("The branches form a cycle: {}", .0.join(" -> "))]
+    BranchCycleError(Vec<String>),
+
+    #[error("The parent of the branch '{0}' is not a string")]
+    InvalidParentError(String),
+
+    #[error("The argument '{0}' is not in the form of `--set PATH=VALUE`")]
+    InvalidArgumentError(String),
+
+    #[error("Cannot coerce '{value}' of '{key}' into {expected}")]
+    CoercionError {
+        key: String,
+        value: String,
+        expected: JsonType,
+    },
+
+    #[error("The lock is poisoned: {0}")]
     PoisonError(String),
 
-    #[fail(display = "{}", _0)]
-    AddrParseError(#[fail(cause)] AddrParseError),
+    #[error("{0}")]
+    AddrParseError(#[from] AddrParseError),
 
-    #[fail(display = "{}", _0)]
-    ConfigError(#[fail(cause)] ConfigError),
+    #[error("{0}")]
+    IoError(#[from] IoError),
 
-    #[fail(display = "{}", _0)]
-    JsonError(#[fail(cause)] JsonError),
-}
+    #[error("{0}")]
+    NotifyError(#[from] notify::Error),
+
+    #[error("{0}")]
+    ConfigError(#[from] ConfigError),
+
+    #[error("{0}")]
+    JsonError(#[from] JsonError),
+
+    #[error("{0}")]
+    TomlError(#[from] toml::ser::Error),
+
+    #[error("{0}")]
+    TomlParseError(#[from] toml::de::Error),
 
-macro_rules! impl_from {
-    ($e:ident) => {
-        impl From<$e> for Error {
-            fn from(e: $e) -> Self {
-                Error::$e(e)
-            }
-        }
-    };
+    #[error("TOML cannot represent the null at '{0}'")]
+    TomlNullError(String),
+
+    #[error("{0}")]
+    YamlError(#[from] serde_yaml::Error),
+
+    #[error("The file format is not supported: {0}")]
+    UnsupportedFormatError(String),
+
+    #[error("{0}")]
+    JsonPathError(#[from] JsonPathError),
 }
 
-impl_from!(AddrParseError);
-impl_from!(ConfigError);
-impl_from!(JsonError);
+fn format_violations(violations: &[Violation]) -> String {
+    violations.iter().map(|v| format!("\n    {}", v)).collect()
+}
 
 impl<T> From<PoisonError<T>> for Error {
     fn from(e: PoisonError<T>) -> Self {
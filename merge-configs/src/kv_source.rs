@@ -0,0 +1,173 @@
+//! Sources made of key-value pairs, i.e., environment variables and command-line arguments.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use config::{ConfigError, Source, Value as LibConfigValue};
+use serde::Deserialize;
+use serde_json::{Value, Map};
+
+use jsonpath::error::JsonType;
+
+use crate::error::{Error, Result};
+
+/// A tree in the layout of a source, built from key-value pairs by `Local::merge_env` and
+/// `Local::merge_args`.
+#[derive(Clone)]
+pub struct KeyValueSource {
+    name: String,
+    tree: Map<String, Value>,
+}
+
+impl KeyValueSource {
+    pub(crate) fn new(name: String, tree: Map<String, Value>) -> Self {
+        Self { name, tree }
+    }
+}
+
+// Only the name is printed rather than the whole tree.
+impl fmt::Debug for KeyValueSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+impl Source for KeyValueSource {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> std::result::Result<HashMap<String, LibConfigValue>, ConfigError> {
+        self.tree
+            .iter()
+            .map(|(k, v)| {
+                let v = LibConfigValue::deserialize(v.clone())
+                    .map_err(|e| ConfigError::Foreign(Box::new(e)))?;
+                Ok((k.clone(), v))
+            })
+            .collect()
+    }
+}
+
+/// Stands for a `.` in the names of environment variables, as most shells do not allow dots.
+const ENV_DOT: &str = "_DOT_";
+
+/// Splits the name of an environment variable like `APP__server__addr` into the key segments
+/// after the prefix, e.g., `["server", "addr"]`. `_DOT_` is unescaped into `.`, so
+/// `APP__whorepresents_DOT_com__rating` gives `["whorepresents.com", "rating"]`.
+pub(crate) fn split_env_key(name: &str, prefix: &str) -> Option<Vec<String>> {
+    let rest = name.strip_prefix(prefix)?.strip_prefix("__")?;
+    let segments: Vec<String> = rest.split("__").map(|s| s.replace(ENV_DOT, ".")).collect();
+    if segments.iter().any(|s| s.is_empty()) {
+        return None;
+    }
+    Some(segments)
+}
+
+/// Collects the `PATH=VALUE` pairs of the `--set PATH=VALUE` and `--set=PATH=VALUE` options, and
+/// ignores the other arguments.
+pub(crate) fn parse_set_args<I, S>(args: I) -> Result<Vec<(String, String)>>
+    where I: IntoIterator<Item = S>,
+          S: AsRef<str>,
+{
+    let mut pairs = vec![];
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let pair = match arg.as_ref() {
+            "--set" => match args.next() {
+                Some(pair) => pair.as_ref().to_owned(),
+                None => return Err(Error::InvalidArgumentError("--set".to_owned())),
+            },
+            arg => match arg.strip_prefix("--set=") {
+                Some(pair) => pair.to_owned(),
+                None => continue,
+            },
+        };
+        match pair.split_once('=') {
+            Some((path, value)) if !path.is_empty() => {
+                pairs.push((path.to_owned(), value.to_owned()))
+            }
+            _ => return Err(Error::InvalidArgumentError(pair)),
+        }
+    }
+    Ok(pairs)
+}
+
+/// Coerces the raw string into the type of the existing value. Without an existing value (or if it
+/// is `null`), the raw string is parsed as JSON, and taken as a string if it is not valid JSON.
+pub(crate) fn coerce(key: &str, raw: &str, existing: Option<&Value>) -> Result<Value> {
+    let error = |expected: JsonType| Error::CoercionError {
+        key: key.to_owned(),
+        value: raw.to_owned(),
+        expected,
+    };
+
+    let existing = match existing {
+        Some(existing) if !existing.is_null() => existing,
+        _ => return Ok(serde_json::from_str(raw).unwrap_or_else(|_| raw.into())),
+    };
+    match existing {
+        Value::String(_) => Ok(raw.into()),
+        Value::Bool(_) => raw.parse::<bool>().map(Value::from).map_err(|_| error(JsonType::Bool)),
+        Value::Number(n) => {
+            let number = if n.is_f64() {
+                raw.parse::<f64>().ok().map(Value::from)
+            } else {
+                raw.parse::<i64>().ok().map(Value::from)
+                    .or_else(|| raw.parse::<u64>().ok().map(Value::from))
+            };
+            number.ok_or_else(|| error(JsonType::Number))
+        }
+        _ => {
+            let expected = JsonType::of(existing);
+            match serde_json::from_str::<Value>(raw) {
+                Ok(value) if JsonType::of(&value) == expected => Ok(value),
+                _ => Err(error(expected)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn kv_parsing() -> Result<()> {
+        let split = |name| split_env_key(name, "APP").unwrap();
+        assert_eq!(split("APP__server__addr"), ["server", "addr"]);
+        assert_eq!(split("APP__a.b"), ["a.b"]);
+        assert_eq!(split("APP__whorepresents_DOT_com__rating"), ["whorepresents.com", "rating"]);
+        assert_eq!(split("APP__a_DOT_b_DOT_c__d"), ["a.b.c", "d"]);
+        assert_eq!(split_env_key("APPLE__a", "APP"), None);
+        assert_eq!(split_env_key("APP__a____b", "APP"), None);
+
+        let args = ["app", "--set", "a=1", "-v", "--set=b.c==", "--set", "d="];
+        assert_eq!(parse_set_args(args)?, vec![
+            ("a".to_owned(), "1".to_owned()),
+            ("b.c".to_owned(), "=".to_owned()),
+            ("d".to_owned(), "".to_owned()),
+        ]);
+        assert!(parse_set_args(["--set"]).is_err());
+        assert!(parse_set_args(["--set", "=1"]).is_err());
+        assert!(parse_set_args(["--set=a"]).is_err());
+
+        assert_eq!(coerce("k", "5", Some(&json!("x")))?, json!("5"));
+        assert_eq!(coerce("k", "5", Some(&json!(0)))?, json!(5));
+        assert_eq!(coerce("k", "5", Some(&json!(0.5)))?, json!(5.0));
+        assert_eq!(coerce("k", "true", Some(&json!(false)))?, json!(true));
+        assert_eq!(coerce("k", "[1]", Some(&json!([])))?, json!([1]));
+        assert_eq!(coerce("k", "5", None)?, json!(5));
+        assert_eq!(coerce("k", "a:b", Some(&Value::Null))?, json!("a:b"));
+        assert!(coerce("k", "yes", Some(&json!(false))).is_err());
+        assert!(coerce("k", "1.5", Some(&json!(1))).is_err());
+        match coerce("k", "{}", Some(&json!([]))) {
+            Err(e) => assert_eq!(e.to_string(), "Cannot coerce '{}' of 'k' into an array"),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        Ok(())
+    }
+}
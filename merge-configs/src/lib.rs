@@ -1,11 +1,18 @@
 
+pub mod config;
+pub mod error;
+pub mod kv_source;
 pub mod local;
+pub mod merge;
+pub mod provenance;
 pub mod remote;
+pub mod schema;
 
-mod config;
-mod nested_source;
+mod file;
+mod save;
 mod value_ext;
-mod error;
 
 pub const DEFAULT_ID: &str = "_default_";
 pub const OVERRIDE_ID: &str = "_override_";
+/// The key in the overrides of a branch naming its parent branch, which is `_default_` if absent.
+pub const PARENT_ID: &str = "_parent_";
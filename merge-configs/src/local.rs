@@ -1,64 +1,314 @@
-use std::iter::Extend;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-pub use config::{Config as LibConfig, Value as LibConfigValue, Source};
+pub use config::{FileFormat, Value as LibConfigValue, Source};
 use serde::Deserialize;
-use serde_json::Value;
+use serde_json::{Value, Map};
 
-use crate::{DEFAULT_ID, OVERRIDE_ID};
-use crate::nested_source::NestedSource;
-use crate::value_ext::ValueExt;
-use crate::error::Result;
+use jsonpath::{Selector, escape_pointer_token};
 
-// FIXME: config-rs does not support keys with dots and JSONPath's `[valid.key.with.dots]` access
-// pattern. See <https://github.com/mehcode/config-rs/issues/110>.
+use crate::{DEFAULT_ID, OVERRIDE_ID, PARENT_ID};
+use crate::file;
+use crate::kv_source::{self, KeyValueSource};
+use crate::merge::{self, MergeStrategy};
+use crate::provenance::{Origin, OriginKind, Provenance};
+use crate::remote::{RemoteLayer, RemoteSource, RemoteTree};
+use crate::save::{self, KeyOrder};
+use crate::schema::{Schema, Violation};
+use crate::value_ext::ValueExt;
+use crate::error::{Error, Result};
 
+/// The local configuration, made of the sources merged in order, the remote sources layered over
+/// them, and the values set at runtime on top.
+///
+/// The merged tree is kept as `{ "_default_": {..}, "_override_": { <branch>: {..}, .. } }`. Branch
+/// names are used as they are, so they can contain dots (e.g., `whorepresents.com`), while keys
+/// are JSONPaths in the lenient mode of the `jsonpath` crate, e.g., `server.addr` for a nested key
+/// and `["a.b"].c` for a key with dots.
+///
+/// A branch can inherit from another one by naming it with the `_parent_` key in its overrides,
+/// e.g., `site.co.uk` → `region.eu` → `_default_`.
 #[derive(Debug)]
 pub struct Local {
-    inner_config: LibConfig,
+    sources: Vec<LocalSource>,
+    remotes: Vec<RemoteLayer>,
+    // The values set at runtime, as a tree per branch.
+    runtime: Map<String, Value>,
     cache: Option<Value>,
+    // The layers which `cache` is built from, so `explain` and `save` see what is served.
+    layers: Vec<Layer>,
+    // The merged trees of branches, which are cleared along with `cache`.
+    merged_cache: HashMap<String, Value>,
+    merge_strategy: MergeStrategy,
+    schema: Option<Schema>,
+}
+
+impl Default for Local {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Local {
     pub fn new() -> Self {
         Self {
-            inner_config: LibConfig::new(),
+            sources: vec![],
+            remotes: vec![],
+            runtime: Map::new(),
             cache: None,
+            layers: vec![],
+            merged_cache: HashMap::new(),
+            merge_strategy: MergeStrategy::default(),
+            schema: None,
         }
     }
 
+    /// Sets how `get_merged` merges the overrides of a branch into the default tree.
+    pub fn set_merge_strategy(&mut self, merge_strategy: MergeStrategy) {
+        self.merge_strategy = merge_strategy;
+        self.merged_cache.clear();
+    }
+
+    /// Sets the schema that the merged tree of every branch must conform to. The sources merged
+    /// afterwards are validated against it.
+    pub fn set_schema(&mut self, schema: Schema) {
+        self.schema = Some(schema);
+    }
+
+    /// Merges the source. If a schema is set and any branch violates it with the source, the
+    /// source is not merged, and all the violations are reported.
     pub fn merge<T>(&mut self, source: T) -> Result<&mut Self>
         where
             T: Source + Send + Sync,
-            T: Clone,
             T: 'static,
     {
-        self.inner_config.merge(NestedSource::from_source(source))?;
+        let name = format!("source #{}", self.sources.len() + 1);
+        self.merge_named(&name, source)
+    }
+
+    /// Merges the source like `merge`, and describes it by `name` in the provenance of values.
+    pub fn merge_named<T>(&mut self, name: &str, source: T) -> Result<&mut Self>
+        where
+            T: Source + Send + Sync,
+            T: 'static,
+    {
+        self.merge_source(LocalSource::Lib(Box::new(source), name.to_owned(), true))
+    }
+
+    /// Merges a JSON, TOML or YAML file, whose format is told by the extension. The source is
+    /// named by the path, and `save` writes back the values read from it.
+    pub fn merge_file<P: AsRef<Path>>(&mut self, path: P) -> Result<&mut Self> {
+        let path = path.as_ref();
+        let format = save::format_of(path)?;
+        self.merge_source(LocalSource::File(path.to_owned(), format))
+    }
+
+    fn merge_source(&mut self, source: LocalSource) -> Result<&mut Self> {
+        self.sources.push(source);
+        self.reset_cache();
+        if let Err(e) = self.check_schema() {
+            self.sources.pop();
+            self.reset_cache();
+            return Err(e);
+        }
         Ok(self)
     }
 
-    // FIXME: currently `key` cannot contain dots
+    /// Fetches the remote source and layers it over the local sources and the remote sources added
+    /// before.
+    pub fn add_remote<R>(&mut self, remote: R) -> Result<&mut Self>
+        where R: RemoteSource + 'static
+    {
+        self.add_remote_layer(RemoteLayer::new(Arc::new(remote))?)
+    }
+
+    /// Layers the remote source fetched by `RemoteLayer::new`.
+    pub(crate) fn add_remote_layer(&mut self, layer: RemoteLayer) -> Result<&mut Self> {
+        self.remotes.push(layer);
+        self.reset_cache();
+        if let Err(e) = self.check_schema() {
+            self.remotes.pop();
+            self.reset_cache();
+            return Err(e);
+        }
+        Ok(self)
+    }
+
+    /// Fetches the remote sources whose versions have changed. Returns whether any is updated.
+    pub fn refresh_remotes(&mut self) -> Result<bool> {
+        let mut is_updated = false;
+        for remote in &mut self.remotes {
+            is_updated |= remote.refresh()?;
+        }
+        if is_updated {
+            self.reset_cache();
+        }
+        Ok(is_updated)
+    }
+
+    /// Returns the remote sources with the versions of their trees, so they can be refreshed
+    /// without borrowing `self`.
+    pub(crate) fn remote_versions(&self) -> Vec<(Arc<dyn RemoteSource>, String)> {
+        self.remotes.iter().map(|r| (Arc::clone(&r.source), r.version.clone())).collect()
+    }
+
+    /// Takes the trees fetched from the remote sources returned by `remote_versions`. The sources
+    /// which are no longer layered are skipped. Returns whether any is updated.
+    pub(crate) fn apply_remote_trees(&mut self, trees: Vec<RemoteTree>) -> bool {
+        let mut is_updated = false;
+        for tree in trees {
+            let remote = self.remotes.iter_mut().find(|r| Arc::ptr_eq(&r.source, &tree.source));
+            if let Some(remote) = remote {
+                is_updated |= remote.apply(tree);
+            }
+        }
+        if is_updated {
+            self.reset_cache();
+        }
+        is_updated
+    }
+
+    /// Reloads the local sources and the remote sources whose versions have changed.
+    pub fn refresh(&mut self) -> Result<()> {
+        self.refresh_remotes()?;
+        self.reload();
+        Ok(())
+    }
+
+    /// Reloads the local sources.
+    pub(crate) fn reload(&mut self) {
+        self.reset_cache();
+    }
+
+    /// Merges the environment variables named `<prefix>__<key>` as a source, where the segments of
+    /// the key are separated by `__`, e.g., `APP__server__addr` for `server.addr`. If the first
+    /// segment is an existing branch, the rest is an override of it, e.g.,
+    /// `APP__whorepresents.com__rating`. As most shells do not allow dots in the names, `_DOT_`
+    /// stands for a `.`, e.g., `APP__whorepresents_DOT_com__rating`. The values are coerced into
+    /// the types of the existing values.
+    pub fn merge_env(&mut self, prefix: &str) -> Result<&mut Self> {
+        let mut entries = vec![];
+        for (name, raw) in std::env::vars() {
+            let segments = match kv_source::split_env_key(&name, prefix) {
+                Some(segments) => segments,
+                None => continue,
+            };
+            let (branch, segments) = match segments.split_first() {
+                Some((first, rest)) if !rest.is_empty() && self.has_branch(first)? => {
+                    (first.to_string(), rest)
+                }
+                _ => (DEFAULT_ID.to_owned(), &segments[..]),
+            };
+            let pointer: String = segments
+                .iter()
+                .map(|s| format!("/{}", escape_pointer_token(s)))
+                .collect();
+            entries.push((branch, name.clone(), Selector::from_pointer(&pointer)?, raw));
+        }
+
+        let tree = self.build_tree(entries)?;
+        let name = format!("env {}__*", prefix);
+        let source = KeyValueSource::new(name.clone(), tree);
+        self.merge_source(LocalSource::Lib(Box::new(source), name, false))
+    }
+
+    /// Merges the `--set PATH=VALUE` options in the arguments as a source, and ignores the other
+    /// arguments. If `PATH` starts with an existing branch, the rest is a key overridden in the
+    /// branch, e.g., `whorepresents.com.rating`, and otherwise it is a default key. The values are
+    /// coerced into the types of the existing values.
+    pub fn merge_args<I, S>(&mut self, args: I) -> Result<&mut Self>
+        where I: IntoIterator<Item = S>,
+              S: AsRef<str>,
+    {
+        let mut branches: Vec<String> = self.override_cache()?.as_map()?.keys().cloned().collect();
+        // Prefers the longest branch, e.g., `a.b` rather than `a` for `a.b.c`.
+        branches.sort_by_key(|b| std::cmp::Reverse(b.len()));
+
+        let mut entries = vec![];
+        for (path, raw) in kv_source::parse_set_args(args)? {
+            let branch = branches.iter().find(|b| {
+                path.strip_prefix(b.as_str())
+                    .is_some_and(|rest| rest.starts_with('.') || rest.starts_with('['))
+            });
+            let (branch, key) = match branch {
+                Some(b) => (b.clone(), path[b.len()..].trim_start_matches('.')),
+                None => (DEFAULT_ID.to_owned(), path.as_str()),
+            };
+            entries.push((branch, path.clone(), Selector::new(key)?, raw));
+        }
+
+        let tree = self.build_tree(entries)?;
+        let source = KeyValueSource::new("args".to_owned(), tree);
+        self.merge_source(LocalSource::Lib(Box::new(source), "args".to_owned(), false))
+    }
+
+    fn has_branch(&mut self, branch: &str) -> Result<bool> {
+        Ok(self.override_cache()?.get(branch).is_some())
+    }
+
+    /// Builds a tree in the layout of a source from the entries of the branch, the name, the key
+    /// and the raw value, coercing the values into the types of the existing ones.
+    fn build_tree(&mut self, entries: Vec<(String, String, Selector, String)>)
+        -> Result<Map<String, Value>>
+    {
+        let mut tree = Value::Object(Map::new());
+        for (branch, name, selector, raw) in entries {
+            let existing = selector.get(&self.get_merged(&branch)?).cloned();
+            let value = kv_source::coerce(&name, &raw, existing.as_ref())?;
+            // A default key such as `_override_` may have replaced the tree of overrides.
+            let target = if branch == DEFAULT_ID {
+                &mut tree
+            } else {
+                let overrides = tree
+                    .as_map_mut()?
+                    .entry(OVERRIDE_ID)
+                    .or_insert_with(|| Value::Object(Map::new()));
+                overrides.as_map_mut()?.entry(branch).or_insert(Value::Null)
+            };
+            selector.set(target, value)?;
+        }
+
+        match tree {
+            Value::Object(m) => Ok(m),
+            _ => Err(Error::NotJsonMapError),
+        }
+    }
+
     pub fn set_default_by(&mut self, key: &str, value: Value) -> Result<()> {
         self.set_override_by(DEFAULT_ID, key, value)
     }
 
-    // FIXME: currently `key` cannot contain dots
+    /// Sets the value of `key` in `branch` at runtime. With a schema set, a value making any branch
+    /// invalid is rejected, like a source by `merge`, and the previous values are kept.
     pub fn set_override_by(&mut self, branch: &str, key: &str, value: Value) -> Result<()> {
-        let cfg_value = LibConfigValue::deserialize(value)?;
-        let override_key = format!("{}.{}", branch, key);
-        self.inner_config.set(&override_key, cfg_value)?;
+        let selector = Selector::new(key)?;
+        let previous = self.runtime.get(branch).cloned();
+        let tree = self.runtime.entry(branch).or_insert(Value::Null);
+        let result = selector.set(tree, value).map_err(Error::from);
         self.reset_cache();
+        if let Err(e) = result.and_then(|_| self.check_schema()) {
+            match previous {
+                Some(tree) => self.runtime.insert(branch.to_owned(), tree),
+                None => self.runtime.remove(branch),
+            };
+            self.reset_cache();
+            return Err(e);
+        }
         Ok(())
     }
 
-    // FIXME: currently `key` cannot contain dots
     pub fn get_default_by(&mut self, key: &str) -> Result<Value> {
         self.get_branch_by(DEFAULT_ID, key)
     }
 
-    // FIXME: currently `key` cannot contain dots
     pub fn get_branch_by(&mut self, branch: &str, key: &str) -> Result<Value> {
-        let override_key = format!("{}.{}", branch, key);
-        Ok(self.inner_config.get(&override_key)?)
+        let selector = Selector::new(key)?;
+        selector
+            .get(self.branch_cache(branch)?)
+            .cloned()
+            .ok_or_else(|| Error::KeyNotFoundError(key.to_owned()))
     }
 
     pub fn get_default(&mut self) -> Result<Value> {
@@ -66,7 +316,31 @@ impl Local {
     }
 
     pub fn get_branch(&mut self, branch: &str) -> Result<Value> {
-        Ok(self.inner_config.get(&branch)?)
+        Ok(self.branch_cache(branch)?.clone())
+    }
+
+    /// Saves the local sources merged by `merge_file` or `merge` and the values set at runtime, in
+    /// the layout of a source, i.e., the default settings with the overrides under `_override_`.
+    /// The remote sources, environment variables and arguments are not saved, as they are not part
+    /// of the file. The sources merged by `merge` are saved as `config` collects them. JSON, TOML
+    /// and YAML are supported, and TOML fails on `null`s. If the file exists, the order of its keys
+    /// is kept, and the file is replaced atomically.
+    pub fn save<P: AsRef<Path>>(&mut self, path: P, format: FileFormat) -> Result<()> {
+        let path = path.as_ref();
+        self.cache()?;
+        let (default, overrides) = merge_layers(self.layers.iter().filter(|layer| layer.is_saved));
+        let mut tree = match default {
+            Value::Object(m) => m,
+            _ => unreachable!("The default tree is an object"),
+        };
+        tree.insert(OVERRIDE_ID.to_owned(), overrides);
+
+        let layout = fs::read_to_string(path)
+            .ok()
+            .and_then(|text| KeyOrder::parse(&text, format));
+        let content = save::to_string(&Value::Object(tree), format, layout.as_ref())?;
+        file::write_atomically(path, content.as_bytes())?;
+        Ok(())
     }
 
     pub fn cache(&mut self) -> Result<&Value> {
@@ -87,45 +361,275 @@ impl Local {
         cache.get_value(OVERRIDE_ID)
     }
 
-    // FIXME: currently `branch` cannot contain dots
+    /// Deep merges the overrides of the ancestors of `branch`, from the farthest one, and then the
+    /// overrides of `branch` into the default tree with the merge strategy.
     pub fn get_merged(&mut self, branch: &str) -> Result<Value> {
-        if self.cache.is_none() {
-            self.update_cache()?;
+        if let Some(value) = self.merged_cache.get(branch) {
+            return Ok(value.clone());
         }
 
-        let mut value = self.cache()?.clone();
-        let m_dst = value.as_map_mut()?;
-        let m_src = self.cache()?.get_value(branch)?.as_map()?;
-        // FIXME: wrong implementation. It cannot handle nested maps.
-        m_dst.extend(m_src.clone());
-
+        let lineage = self.lineage(branch)?;
+        let mut value = self.default_cache()?.clone();
+        for b in lineage.iter().rev() {
+            let mut overrides = self.branch_cache(b)?.as_map()?.clone();
+            overrides.remove(PARENT_ID);
+            self.merge_strategy.merge(&mut value, Value::Object(overrides));
+        }
+        self.merged_cache.insert(branch.to_owned(), value.clone());
         Ok(value)
     }
 
-    // WORKAROUND: supports `branch` with dots
+    /// Returns `branch` followed by its ancestors, excluding `_default_`.
+    pub fn lineage(&mut self, branch: &str) -> Result<Vec<String>> {
+        let mut lineage: Vec<String> = vec![];
+        let mut curr = branch.to_owned();
+        while curr != DEFAULT_ID {
+            if lineage.contains(&curr) {
+                lineage.push(curr);
+                return Err(Error::BranchCycleError(lineage));
+            }
+
+            let parent = match self.branch_cache(&curr)?.get(PARENT_ID) {
+                None => DEFAULT_ID.to_owned(),
+                Some(Value::String(parent)) => parent.clone(),
+                Some(_) => return Err(Error::InvalidParentError(curr)),
+            };
+            lineage.push(curr);
+            curr = parent;
+        }
+        Ok(lineage)
+    }
+
+    /// The same as `get_merged`, which used to be a workaround for the branches with dots.
     pub fn get_merged2(&mut self, branch: &str) -> Result<Value> {
-        let mut value = self.default_cache()?.clone();
-        let m_dst = value.as_map_mut()?;
-        let v_src = self.get_branch(branch)?;
-        let m_src = v_src.as_map()?;
-        // FIXME: wrong implementation. It cannot handle nested maps.
-        m_dst.extend(m_src.clone());
+        self.get_merged(branch)
+    }
 
-        Ok(value)
+    /// Validates the default tree and the merged tree of every branch against the schema. Returns
+    /// all the violations, or nothing if no schema is set.
+    pub fn validate_all(&mut self) -> Result<Vec<Violation>> {
+        let schema = match self.schema.take() {
+            Some(schema) => schema,
+            None => return Ok(vec![]),
+        };
+        let violations = self.validate_with(&schema);
+        self.schema = Some(schema);
+        violations
+    }
+
+    fn validate_with(&mut self, schema: &Schema) -> Result<Vec<Violation>> {
+        let mut violations = schema.validate(DEFAULT_ID, self.default_cache()?);
+        let branches: Vec<String> = self.override_cache()?.as_map()?.keys().cloned().collect();
+        for branch in branches {
+            violations.extend(schema.validate(&branch, &self.get_merged(&branch)?));
+        }
+        Ok(violations)
+    }
+
+    fn check_schema(&mut self) -> Result<()> {
+        let violations = self.validate_all()?;
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::ValidationError(violations))
+        }
+    }
+
+    /// Returns the default tree for `_default_`, or the overrides of other branches.
+    fn branch_cache(&mut self, branch: &str) -> Result<&Value> {
+        if branch == DEFAULT_ID {
+            return self.default_cache();
+        }
+        self.override_cache()?
+            .get(branch)
+            .ok_or_else(|| Error::BranchNotFoundError(branch.to_owned()))
     }
 
     #[inline]
     fn reset_cache(&mut self) {
         self.cache = None;
+        self.layers.clear();
+        self.merged_cache.clear();
+    }
+
+    /// Returns the layers of the config from the lowest precedence to the highest, each as the
+    /// default tree and the overrides of branches.
+    fn layers(&self) -> Result<Vec<Layer>> {
+        let mut layers = vec![];
+        for source in &self.sources {
+            let name = source.name();
+            layers.push(Layer::new(OriginKind::Source, name, source.tree()?, source.is_saved()));
+        }
+        for remote in &self.remotes {
+            let tree = remote.value.as_map()?.clone();
+            layers.push(Layer::new(OriginKind::Remote, remote.source.name(), tree, false));
+        }
+
+        let mut runtime = self.runtime.clone();
+        let mut tree = match runtime.remove(DEFAULT_ID) {
+            Some(Value::Object(m)) => m,
+            _ => Map::new(),
+        };
+        tree.insert(OVERRIDE_ID.to_owned(), Value::Object(runtime));
+        layers.push(Layer::new(OriginKind::Runtime, "runtime".to_owned(), tree, true));
+
+        Ok(layers)
     }
 
     fn update_cache(&mut self) -> Result<()> {
-        self.inner_config.refresh()?;
-        let cfg = self.inner_config.clone();
-        let val = cfg.try_into::<Value>()?;
-        self.cache = Some(val);
+        let layers = self.layers()?;
+        let (default, overrides) = merge_layers(layers.iter());
+        let mut cache = Map::new();
+        cache.insert(DEFAULT_ID.to_owned(), default);
+        cache.insert(OVERRIDE_ID.to_owned(), overrides);
+        self.cache = Some(Value::Object(cache));
+        self.layers = layers;
         Ok(())
     }
+
+    /// Explains the merged value of `key` in `branch` (or the default value for `_default_`) with
+    /// the values of the key in all the layers of the branch, its ancestors and `_default_`,
+    /// including the shadowed ones. The layers are the ones the cached tree is built from, so the
+    /// sources changed since the last refresh are not read.
+    pub fn explain(&mut self, branch: &str, key: &str) -> Result<Provenance> {
+        let selector = Selector::new(key)?;
+        let merged = self.get_merged(branch)?;
+        let value = selector.get(&merged).cloned();
+        let lineage = self.lineage(branch)?;
+
+        let layers = &self.layers;
+        let mut chain = vec![];
+        let origin = |layer: &Layer, branch: &str, value: &Value| Origin {
+            kind: layer.kind,
+            name: layer.name.clone(),
+            branch: branch.to_owned(),
+            value: value.clone(),
+            is_shadowed: false,
+        };
+        for branch in &lineage {
+            for layer in layers.iter().rev() {
+                let tree = layer.overrides.get(branch);
+                if let Some(v) = tree.and_then(|tree| selector.get(tree)) {
+                    chain.push(origin(layer, branch, v));
+                }
+            }
+        }
+        for layer in layers.iter().rev() {
+            let tree = Value::Object(layer.default.clone());
+            if let Some(v) = selector.get(&tree) {
+                chain.push(origin(layer, DEFAULT_ID, v));
+            }
+        }
+
+        let arrays = self.merge_strategy.array_strategy_at(&strategy_keys(&selector, &merged));
+        Ok(Provenance::new(value, chain, arrays))
+    }
+}
+
+/// Returns the keys of the location of `selector` in `tree` without the array indices, which
+/// choose the `ArrayStrategy` of the location. The keys are empty for a selector which is not
+/// singular.
+fn strategy_keys(selector: &Selector, tree: &Value) -> Vec<String> {
+    let pointer = selector.to_pointer().unwrap_or_default();
+    let mut keys = vec![];
+    let mut node = Some(tree);
+    for token in pointer.split('/').skip(1) {
+        let token = token.replace("~1", "/").replace("~0", "~");
+        node = match node {
+            Some(Value::Array(a)) => token.parse::<usize>().ok().and_then(|i| a.get(i)),
+            node => {
+                let child = node.and_then(|node| node.get(&token));
+                keys.push(token);
+                child
+            }
+        };
+    }
+    keys
+}
+
+/// A local source merged by `Local::merge_file`, `merge`, `merge_env` or `merge_args`.
+#[derive(Debug)]
+enum LocalSource {
+    // A source of `config` with its name, and whether it is saved by `save`, i.e., not made of
+    // key-value pairs.
+    Lib(Box<dyn Source + Send + Sync>, String, bool),
+    // A file read without `config`, which is always saved.
+    File(PathBuf, FileFormat),
+}
+
+impl LocalSource {
+    fn name(&self) -> String {
+        match self {
+            LocalSource::Lib(_, name, _) => name.clone(),
+            LocalSource::File(path, _) => path.display().to_string(),
+        }
+    }
+
+    fn is_saved(&self) -> bool {
+        match self {
+            LocalSource::Lib(_, _, is_saved) => *is_saved,
+            LocalSource::File(..) => true,
+        }
+    }
+
+    /// Reads the tree in the layout of a source.
+    fn tree(&self) -> Result<Map<String, Value>> {
+        match self {
+            LocalSource::Lib(source, ..) => {
+                let mut tree = Map::new();
+                for (k, v) in source.collect()? {
+                    tree.insert(k, Value::deserialize(v)?);
+                }
+                Ok(tree)
+            }
+            LocalSource::File(path, format) => {
+                match save::parse(&fs::read_to_string(path)?, *format)? {
+                    Value::Object(m) => Ok(m),
+                    // An empty YAML file.
+                    Value::Null => Ok(Map::new()),
+                    _ => Err(Error::NotJsonMapError),
+                }
+            }
+        }
+    }
+}
+
+/// Overlays the layers in order, and returns the default tree and the overrides of branches.
+fn merge_layers<'a>(layers: impl Iterator<Item = &'a Layer>) -> (Value, Value) {
+    let mut default = Value::Object(Map::new());
+    let mut overrides = Value::Object(Map::new());
+    for layer in layers {
+        merge::overlay(&mut default, Value::Object(layer.default.clone()));
+        merge::overlay(&mut overrides, Value::Object(layer.overrides.clone()));
+    }
+    (default, overrides)
+}
+
+/// A layer of the config, whose override part is lifted from the default tree.
+#[derive(Debug)]
+struct Layer {
+    kind: OriginKind,
+    name: String,
+    default: Map<String, Value>,
+    overrides: Map<String, Value>,
+    // Whether the layer is written by `Local::save`.
+    is_saved: bool,
+}
+
+impl Layer {
+    fn new(kind: OriginKind, name: String, mut tree: Map<String, Value>, is_saved: bool) -> Self {
+        let overrides = match tree.remove(OVERRIDE_ID) {
+            Some(Value::Object(m)) => m,
+            _ => Map::new(),
+        };
+        Self {
+            kind,
+            name,
+            default: tree,
+            overrides,
+            is_saved,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -135,9 +639,421 @@ mod tests {
     use std::net::SocketAddr;
     use std::path::PathBuf;
 
-    use config::File as LibConfigFile;
+    use config::{File as LibConfigFile, FileFormat};
     use serde_derive::Deserialize;
 
+    use crate::merge::ArrayStrategy;
+
+    #[test]
+    fn local_deep_merge() -> Result<()> {
+        let source = LibConfigFile::from_str(r#"{
+            "server": { "addr": "127.0.0.1:80", "tls": { "cert": "a.pem", "key": "a.key" } },
+            "hosts": [{ "name": "x", "port": 1 }],
+            "debug": true,
+            "_override_": {
+                "staging": {
+                    "server": { "tls": { "cert": "b.pem" } },
+                    "hosts": [{ "name": "x", "port": 2 }, { "name": "y", "port": 3 }],
+                    "debug": null
+                }
+            }
+        }"#, FileFormat::Json);
+
+        let mut local = Local::new();
+        local.merge(source)?;
+        let by_name = ArrayStrategy::MergeByKey("name".into());
+        local.set_merge_strategy(MergeStrategy::default().with_array_at(&["hosts"], by_name));
+
+        let expected = serde_json::json!({
+            "server": { "addr": "127.0.0.1:80", "tls": { "cert": "b.pem", "key": "a.key" } },
+            "hosts": [{ "name": "x", "port": 2 }, { "name": "y", "port": 3 }],
+        });
+        assert_eq!(local.get_merged("staging")?, expected);
+        assert_eq!(local.get_merged2("staging")?, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn local_dotted_branches() -> Result<()> {
+        let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        p.push("assets/config.json");
+        let source = LibConfigFile::from(p);
+
+        let mut local = Local::new();
+        local.merge(source)?;
+        assert_eq!(local.get_branch_by("whorepresents.com", "rating")?, 95);
+        assert_eq!(local.get_branch_by("childrenswear.co.uk", "site_name")?, "Children's wear");
+        assert!(local.get_branch_by("childrenswear.co.uk", "rating").is_err());
+        assert!(local.get_branch("childrenswear").is_err());
+
+        local.set_default_by(r#"["a.b"].c"#, 1.into())?;
+        local.set_override_by("childrenswear.co.uk", r#"["a.b"]['d.e']"#, 2.into())?;
+        local.set_override_by("example.org", "server_addr", "127.0.0.1:8080".into())?;
+        assert_eq!(local.get_default_by(r#"["a.b"].c"#)?, 1);
+        assert_eq!(local.get_default_by(r#"$['a.b']"#)?, serde_json::json!({ "c": 1 }));
+
+        let merged = local.get_merged("childrenswear.co.uk")?;
+        assert_eq!(merged["a.b"], serde_json::json!({ "c": 1, "d.e": 2 }));
+        assert_eq!(merged["site_name"], "Children's wear");
+        assert_eq!(merged["rating"], 0);
+        assert_eq!(local.get_merged("example.org")?["server_addr"], "127.0.0.1:8080");
+
+        Ok(())
+    }
+
+    #[test]
+    fn local_remote_layers() -> Result<()> {
+        use crate::remote::tests::TestServer;
+        use crate::remote::HttpSource;
+
+        let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        p.push("assets/config.json");
+        let server = TestServer::start(&serde_json::json!({
+            "rating": 1,
+            "_override_": { "whorepresents.com": { "site_name": "Central" } },
+        }));
+
+        let mut local = Local::new();
+        local.merge(LibConfigFile::from(p))?;
+        local.add_remote(HttpSource::new(&server.url))?;
+        local.set_override_by("whorepresents.com", "rating", 7.into())?;
+        assert_eq!(local.get_default_by("rating")?, 1);
+        assert_eq!(local.get_branch_by("whorepresents.com", "site_name")?, "Central");
+        assert_eq!(local.get_branch_by("whorepresents.com", "rating")?, 7);
+        assert_eq!(local.get_merged("localhost")?["site_name"], "Home");
+        assert!(!local.refresh_remotes()?);
+
+        server.update(&serde_json::json!({ "rating": 2 }));
+        assert!(local.refresh_remotes()?);
+        assert_eq!(local.get_default_by("rating")?, 2);
+        assert_eq!(local.get_branch_by("whorepresents.com", "site_name")?, "Who represents?");
+
+        Ok(())
+    }
+
+    #[test]
+    fn local_schema() -> Result<()> {
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        struct Settings {
+            server_addr: SocketAddr,
+            site_name: String,
+            rating: u32,
+        }
+
+        let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        p.push("assets/config.json");
+        let mut local = Local::new();
+        local.set_schema(Schema::of::<Settings>());
+        local.merge(LibConfigFile::from(p))?;
+        assert!(local.validate_all()?.is_empty());
+
+        let source = LibConfigFile::from_str(r#"{
+            "_override_": {
+                "itscrap.com": { "rating": -1 },
+                "whorepresents.com": { "server_addr": "localhost" }
+            }
+        }"#, FileFormat::Json);
+        let violations = match local.merge(source) {
+            Err(Error::ValidationError(violations)) => violations,
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        };
+        let violations: Vec<(&str, &str)> = violations
+            .iter()
+            .map(|v| (v.branch.as_str(), v.path.as_str()))
+            .collect();
+        assert_eq!(violations, vec![
+            ("itscrap.com", "/rating"),
+            ("whorepresents.com", "/server_addr"),
+        ]);
+        // The invalid source is not merged.
+        assert_eq!(local.get_branch_by("itscrap.com", "rating")?, 5);
+
+        // Neither are the invalid values set at runtime.
+        match local.set_override_by("localhost", "rating", "high".into()) {
+            Err(Error::ValidationError(violations)) => {
+                assert_eq!(violations.len(), 1);
+                assert_eq!(violations[0].branch, "localhost");
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(local.get_merged("localhost")?["rating"], 0);
+        assert!(local.set_default_by("server_addr", "localhost".into()).is_err());
+        assert!(local.set_override_by("new.site", "rating", (-1).into()).is_err());
+        assert!(local.get_branch("new.site").is_err());
+        local.set_override_by("localhost", "rating", 1.into())?;
+        assert!(local.validate_all()?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn local_explain() -> Result<()> {
+        use crate::remote::DirSource;
+
+        let dir = std::env::temp_dir()
+            .join(format!("merge-configs-explain-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let remote_dir = dir.join("remote");
+        std::fs::create_dir_all(&remote_dir)?;
+        std::fs::write(remote_dir.join("whorepresents.com.json"), r#"{ "rating": 100 }"#)?;
+        let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        p.push("assets/config.json");
+        let file = dir.join("config.json");
+        std::fs::copy(&p, &file)?;
+
+        let mut local = Local::new();
+        local.merge_file(&file)?;
+        local.merge_named("extra", LibConfigFile::from_str(r#"{ "extra": 1 }"#, FileFormat::Json))?;
+        local.add_remote(DirSource::new(&remote_dir))?;
+        local.set_override_by("whorepresents.com", "rating", 1024.into())?;
+
+        let provenance = local.explain("whorepresents.com", "rating")?;
+        assert_eq!(provenance.value, Some(1024.into()));
+        let chain: Vec<_> = provenance
+            .chain
+            .iter()
+            .map(|o| (o.kind, o.branch.as_str(), o.value.clone(), o.is_shadowed))
+            .collect();
+        assert_eq!(chain, vec![
+            (OriginKind::Runtime, "whorepresents.com", 1024.into(), false),
+            (OriginKind::Remote, "whorepresents.com", 100.into(), true),
+            (OriginKind::Source, "whorepresents.com", 95.into(), true),
+            (OriginKind::Source, DEFAULT_ID, 0.into(), true),
+        ]);
+        assert_eq!(provenance.chain[2].name, file.display().to_string());
+        assert_eq!(provenance.chain[1].name, remote_dir.display().to_string());
+        assert_eq!(local.explain(DEFAULT_ID, "extra")?.chain[0].name, "extra");
+
+        // Explains the values served until the edited file is reloaded.
+        let content = std::fs::read_to_string(&file)?.replace("95", "96");
+        std::fs::write(&file, content)?;
+        let rating = |local: &mut Local| -> Result<Value> {
+            let provenance = local.explain("whorepresents.com", "rating")?;
+            Ok(provenance.chain[2].value.clone())
+        };
+        assert_eq!(rating(&mut local)?, 95);
+        local.refresh()?;
+        assert_eq!(rating(&mut local)?, 96);
+
+        local.set_default_by("server", serde_json::json!({ "port": 80, "tls": false }))?;
+        local.set_override_by("localhost", "server.port", 8080.into())?;
+        let provenance = local.explain("localhost", "server")?;
+        assert_eq!(provenance.value, Some(serde_json::json!({ "port": 8080, "tls": false })));
+        assert!(provenance.chain.iter().all(|o| !o.is_shadowed));
+
+        let provenance = local.explain("localhost", "no_such_key")?;
+        assert_eq!((provenance.value, provenance.chain.len()), (None, 0));
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn local_explain_merge_strategies() -> Result<()> {
+        use serde_json::json;
+
+        let source = LibConfigFile::from_str(r#"{
+            "tags": ["a"],
+            "ids": [1],
+            "hosts": [{ "name": "x", "port": 1 }],
+            "legacy": true,
+            "_override_": {
+                "x.com": {
+                    "tags": ["b"],
+                    "ids": [2],
+                    "hosts": [{ "name": "x", "port": 2 }],
+                    "legacy": null
+                }
+            }
+        }"#, FileFormat::Json);
+        let mut local = Local::new();
+        local.set_merge_strategy(MergeStrategy::new(ArrayStrategy::Append)
+            .with_array_at(&["ids"], ArrayStrategy::Replace)
+            .with_array_at(&["hosts"], ArrayStrategy::MergeByKey("name".to_owned())));
+        local.merge(source)?;
+        local.set_override_by("x.com", "tags", json!(["c"]))?;
+
+        let mut explain = |key: &str| {
+            let provenance = local.explain("x.com", key).unwrap();
+            let chain: Vec<_> = provenance.chain.iter().map(|o| (o.kind, o.is_shadowed)).collect();
+            (provenance.value, chain)
+        };
+        let (runtime, source) = (OriginKind::Runtime, OriginKind::Source);
+        // The runtime array replaces the source one of the same branch, and is appended to the
+        // default one.
+        assert_eq!(explain("tags"), (
+            Some(json!(["a", "c"])),
+            vec![(runtime, false), (source, true), (source, false)],
+        ));
+        assert_eq!(explain("ids"), (Some(json!([2])), vec![(source, false), (source, true)]));
+        assert_eq!(explain("hosts"), (
+            Some(json!([{ "name": "x", "port": 2 }])),
+            vec![(source, false), (source, false)],
+        ));
+        // The `null` deleting the key is in the chain.
+        let provenance = local.explain("x.com", "legacy")?;
+        assert_eq!(provenance.value, None);
+        assert_eq!(provenance.chain[0].value, Value::Null);
+        assert_eq!(
+            provenance.chain.iter().map(|o| o.is_shadowed).collect::<Vec<_>>(),
+            vec![false, true]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn local_inheritance() -> Result<()> {
+        let source = LibConfigFile::from_str(r#"{
+            "currency": "USD",
+            "language": "en",
+            "rating": 0,
+            "_override_": {
+                "region.eu": { "currency": "EUR", "language": "de" },
+                "site.co.uk": { "_parent_": "region.eu", "language": "en-GB" },
+                "a": { "_parent_": "b" },
+                "b": { "_parent_": "a" },
+                "c": { "_parent_": "d" },
+                "e": { "_parent_": 0 }
+            }
+        }"#, FileFormat::Json);
+
+        let mut local = Local::new();
+        local.merge(source)?;
+        assert_eq!(local.lineage("site.co.uk")?, vec!["site.co.uk", "region.eu"]);
+        assert_eq!(local.get_merged("site.co.uk")?, serde_json::json!({
+            "currency": "EUR",
+            "language": "en-GB",
+            "rating": 0,
+        }));
+
+        local.set_override_by("region.eu", "rating", 5.into())?;
+        assert_eq!(local.get_merged("site.co.uk")?["rating"], 5);
+        let provenance = local.explain("site.co.uk", "rating")?;
+        let branches: Vec<&str> = provenance.chain.iter().map(|o| o.branch.as_str()).collect();
+        assert_eq!(branches, vec!["region.eu", DEFAULT_ID]);
+
+        match local.get_merged("a") {
+            Err(Error::BranchCycleError(cycle)) => assert_eq!(cycle, vec!["a", "b", "a"]),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(matches!(local.get_merged("c"), Err(Error::BranchNotFoundError(_))));
+        assert!(matches!(local.get_merged("e"), Err(Error::InvalidParentError(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn local_env_and_args() -> Result<()> {
+        let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        p.push("assets/config.json");
+        let mut local = Local::new();
+        local.merge(LibConfigFile::from(p))?;
+
+        std::env::set_var("MERGE_CONFIGS_TEST__rating", "3");
+        std::env::set_var("MERGE_CONFIGS_TEST__server__port", "8080");
+        std::env::set_var("MERGE_CONFIGS_TEST__itscrap.com__site_name", "42");
+        std::env::set_var("MERGE_CONFIGS_TEST__localhost_DOT_localdomain__rating", "1");
+        std::env::set_var("MERGE_CONFIGS_TEST__whorepresents_DOT_com__rating", "7");
+        local.merge_env("MERGE_CONFIGS_TEST")?;
+        assert_eq!(local.get_default_by("rating")?, 3);
+        assert_eq!(local.get_default_by("server.port")?, 8080);
+        assert_eq!(local.get_branch_by("itscrap.com", "site_name")?, "42");
+        assert_eq!(local.get_branch_by("whorepresents.com", "rating")?, 7);
+        assert_eq!(local.get_default_by(r#"["localhost.localdomain"].rating"#)?, 1);
+
+        local.merge_args(vec![
+            "--set", "whorepresents.com.rating=5",
+            "--set=server_addr=10.0.0.1:80",
+            "--verbose",
+            "--set", r#"_override_["new.site"].tags=["a"]"#,
+        ])?;
+        assert_eq!(local.get_branch_by("whorepresents.com", "rating")?, 5);
+        assert_eq!(local.get_default_by("server_addr")?, "10.0.0.1:80");
+        assert_eq!(local.get_branch_by("new.site", "tags")?, serde_json::json!(["a"]));
+
+        let provenance = local.explain("whorepresents.com", "rating")?;
+        assert_eq!(provenance.chain[0].name, "args");
+
+        match local.merge_args(["--set", "localhost.rating=high"]) {
+            Err(Error::CoercionError { key, .. }) => assert_eq!(key, "localhost.rating"),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        let args = ["--set", "_override_=1", "--set", "localhost.rating=1"];
+        assert!(matches!(local.merge_args(args), Err(Error::NotJsonMapError)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn local_save() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("merge-configs-save-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        std::env::set_var("MERGE_CONFIGS_SAVE_TEST__rating", "7");
+        let source = dir.join("source.json");
+        std::fs::write(&source, r#"{
+            "serverAddr": "127.0.0.1:80",
+            "siteName": "Joe's server",
+            "rating": 0,
+            "_override_": { "whorepresents.com": { "siteName": "Who represents?" } }
+        }"#)?;
+
+        for (name, format) in [
+            ("config.json", FileFormat::Json),
+            ("config.toml", FileFormat::Toml),
+            ("config.yaml", FileFormat::Yaml),
+        ] {
+            let path = dir.join(name);
+            let mut local = Local::new();
+            local.merge_file(&source)?;
+            if format == FileFormat::Json {
+                std::fs::copy(&source, &path)?;
+            }
+            local.set_override_by("whorepresents.com", "rating", 1024.into())?;
+            local.set_default_by("server.port", 80.into())?;
+            let expected = local.cache()?.clone();
+            // The environment variables are not saved.
+            local.merge_env("MERGE_CONFIGS_SAVE_TEST")?;
+            assert_eq!(local.get_default_by("rating")?, 7);
+            local.save(&path, format)?;
+
+            // The keys are kept as they are.
+            let mut reloaded = Local::new();
+            reloaded.merge_file(&path)?;
+            assert_eq!(reloaded.cache()?, &expected);
+            let site_name = reloaded.get_branch_by("whorepresents.com", "siteName")?;
+            assert_eq!(site_name, "Who represents?");
+            let entries = std::fs::read_dir(&dir)?.collect::<std::io::Result<Vec<_>>>()?;
+            assert!(!entries.iter().any(|e| e.path().extension().is_some_and(|ext| ext == "tmp")));
+
+            if format == FileFormat::Json {
+                // Keeps the order of the original file rather than the sorted one, and appends the
+                // new keys.
+                let text = std::fs::read_to_string(&path)?;
+                let keys = ["serverAddr", "siteName", "rating", "_override_", "\"server\""];
+                let positions: Vec<_> = keys
+                    .iter()
+                    .map(|k| text.find(k))
+                    .collect();
+                assert!(positions.iter().all(Option::is_some), "{}", text);
+                assert!(positions.windows(2).all(|w| w[0] < w[1]), "{}", text);
+            }
+        }
+
+        let mut local = Local::new();
+        local.merge_file(&source)?;
+        local.set_default_by("rating", Value::Null)?;
+        let path = dir.join("config.toml");
+        assert!(matches!(local.save(&path, FileFormat::Toml), Err(Error::TomlNullError(_))));
+        let error = local.merge_file(dir.join("config.ini")).unwrap_err();
+        assert!(matches!(error, Error::UnsupportedFormatError(_)));
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
     #[test]
     fn smoke_local() -> Result<()> {
         #[derive(Debug, Deserialize)]
@@ -147,33 +1063,34 @@ mod tests {
             pub rating: u32,
         }
 
-        // FIXME: Keys with dots happens to work when they are nested. See config-rs issue 110.
         let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         p.push("assets/config.json");
         let source = LibConfigFile::from(p);
 
-        // FIXME: use `get_merged` instead of `get_merged2` when config-rs is fixed.
-
         let mut local = Local::new();
         local.merge(source)?;
         println!("Local = {:#?}", local);
         println!("Local.cache = {:#?}", local.cache()?);
         let default_settings = Settings::deserialize(local.get_default()?)?;
-        let merged_settings = Settings::deserialize(local.get_merged2(r"whorepresents.com")?)?;
+        let merged_settings = Settings::deserialize(local.get_merged("whorepresents.com")?)?;
         println!("Local.default = {:#?}", default_settings);
         println!("Local.merged = {:#?}", merged_settings);
+        assert_eq!(merged_settings.rating, 95);
 
         // modifies config
         local.set_default_by("server_addr", "192.168.1.1:80".into())?;
-        local.set_override_by(r"whorepresents.com", "site_name", "Whore presents".into())?;
+        local.set_override_by("whorepresents.com", "site_name", "Whore presents".into())?;
         local.set_override_by("whorepresents.com", "rating", 1024.into())?;
 
         println!("\n================================\nAfter modification:\n");
         // Here deliberately uses `default_cache` rather than `get_default`.
         let default_settings = Settings::deserialize(local.default_cache()?.clone())?;
-        let merged_settings = Settings::deserialize(local.get_merged2("whorepresents.com")?)?;
+        let merged_settings = Settings::deserialize(local.get_merged("whorepresents.com")?)?;
         println!("Local.default = {:#?}", default_settings);
         println!("Local.merged = {:#?}", merged_settings);
+        assert_eq!(merged_settings.server_addr, default_settings.server_addr);
+        assert_eq!(merged_settings.site_name, "Whore presents");
+        assert_eq!(merged_settings.rating, 1024);
 
         Ok(())
     }
@@ -0,0 +1,185 @@
+//! Deep merge of JSON trees, used to apply a branch's overrides onto the `_default_` tree.
+
+use std::collections::HashMap;
+
+use serde_json::{Value, Map};
+
+/// How an array in the override tree is merged into the array at the same place.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum ArrayStrategy {
+    /// The override array replaces the original one.
+    #[default]
+    Replace,
+    /// The override elements are appended to the original ones.
+    Append,
+    /// The elements at the same index are merged, and the extra override elements are appended.
+    MergeByIndex,
+    /// The objects having the same value of the key field are merged, and the other override
+    /// elements are appended.
+    MergeByKey(String),
+}
+
+/// Merges objects recursively. An override member of `null` deletes the key, and arrays are merged
+/// with an `ArrayStrategy`, which can be set for the whole tree and for arrays at given places.
+#[derive(Debug, Clone, Default)]
+pub struct MergeStrategy {
+    arrays: ArrayStrategy,
+    arrays_at: HashMap<Vec<String>, ArrayStrategy>,
+}
+
+impl MergeStrategy {
+    pub fn new(arrays: ArrayStrategy) -> Self {
+        Self {
+            arrays,
+            arrays_at: HashMap::new(),
+        }
+    }
+
+    /// Sets the strategy for the array at `keys`, e.g., `&["server", "hosts"]`. The indices of
+    /// arrays are not part of the keys, so the strategy also applies to `server.hosts` inside the
+    /// elements of an array.
+    pub fn with_array_at<S: AsRef<str>>(mut self, keys: &[S], strategy: ArrayStrategy) -> Self {
+        let keys = keys.iter().map(|k| k.as_ref().to_owned()).collect();
+        self.arrays_at.insert(keys, strategy);
+        self
+    }
+
+    /// Merges `src` into `dst`.
+    pub fn merge(&self, dst: &mut Value, src: Value) {
+        self.merge_at(&mut vec![], dst, src);
+    }
+
+    fn merge_at(&self, keys: &mut Vec<String>, dst: &mut Value, src: Value) {
+        match (dst, src) {
+            (Value::Object(dst), Value::Object(src)) => self.merge_maps(keys, dst, src),
+            (Value::Array(dst), Value::Array(src)) => self.merge_arrays(keys, dst, src),
+            (dst, src) => *dst = src,
+        }
+    }
+
+    fn merge_maps(
+        &self,
+        keys: &mut Vec<String>,
+        dst: &mut Map<String, Value>,
+        src: Map<String, Value>,
+    ) {
+        for (k, v) in src {
+            if v.is_null() {
+                dst.remove(&k);
+                continue;
+            }
+
+            // A new object is merged into an empty one, so the `null`s inside are dropped as well.
+            let dst_v = dst.entry(k.as_str()).or_insert_with(|| match v {
+                Value::Object(_) => Value::Object(Map::new()),
+                _ => Value::Null,
+            });
+            keys.push(k);
+            self.merge_at(keys, dst_v, v);
+            keys.pop();
+        }
+    }
+
+    /// Returns the strategy for the array at `keys`, which are the keys set by `with_array_at`.
+    pub(crate) fn array_strategy_at(&self, keys: &[String]) -> &ArrayStrategy {
+        self.arrays_at.get(keys).unwrap_or(&self.arrays)
+    }
+
+    fn merge_arrays(&self, keys: &mut Vec<String>, dst: &mut Vec<Value>, src: Vec<Value>) {
+        match self.array_strategy_at(keys) {
+            ArrayStrategy::Replace => *dst = src,
+            ArrayStrategy::Append => dst.extend(src),
+            ArrayStrategy::MergeByIndex => {
+                let mut src = src.into_iter();
+                for (dst_v, v) in dst.iter_mut().zip(src.by_ref()) {
+                    self.merge_at(keys, dst_v, v);
+                }
+                dst.extend(src);
+            }
+            ArrayStrategy::MergeByKey(field) => {
+                for v in src {
+                    let found = v.get(field).and_then(|id| {
+                        dst.iter_mut().find(|dst_v| dst_v.get(field) == Some(id))
+                    });
+                    match found {
+                        Some(dst_v) => self.merge_at(keys, dst_v, v),
+                        None => dst.push(v),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Overlays `src` onto `dst`, the way a later source overrides an earlier one. Objects are overlaid
+/// recursively and anything else, including `null`, replaces the original value.
+pub(crate) fn overlay(dst: &mut Value, src: Value) {
+    match (dst, src) {
+        (Value::Object(dst), Value::Object(src)) => {
+            for (k, v) in src {
+                overlay(dst.entry(k).or_insert(Value::Null), v);
+            }
+        }
+        (dst, src) => *dst = src,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn merge_strategies() {
+        let dst = json!({
+            "server": { "addr": "127.0.0.1:80", "tls": { "cert": "a.pem", "key": "a.key" } },
+            "tags": ["a", "b"],
+            "hosts": [
+                { "name": "x", "port": 1, "weight": 1 },
+                { "name": "y", "port": 2 },
+            ],
+            "legacy": true,
+        });
+        let src = json!({
+            "server": { "tls": { "cert": "b.pem" } },
+            "tags": ["c"],
+            "hosts": [{ "name": "y", "port": 3 }, { "name": "z" }],
+            "legacy": null,
+            "new": { "a": null },
+        });
+
+        let mut merged = dst.clone();
+        MergeStrategy::default().merge(&mut merged, src.clone());
+        assert_eq!(merged, json!({
+            "server": { "addr": "127.0.0.1:80", "tls": { "cert": "b.pem", "key": "a.key" } },
+            "tags": ["c"],
+            "hosts": [{ "name": "y", "port": 3 }, { "name": "z" }],
+            "new": {},
+        }));
+
+        let mut merged = dst.clone();
+        MergeStrategy::new(ArrayStrategy::Append).merge(&mut merged, src.clone());
+        assert_eq!(merged["tags"], json!(["a", "b", "c"]));
+        assert_eq!(merged["hosts"].as_array().map(Vec::len), Some(4));
+
+        let mut merged = dst.clone();
+        MergeStrategy::new(ArrayStrategy::MergeByIndex).merge(&mut merged, src.clone());
+        assert_eq!(merged["tags"], json!(["c", "b"]));
+        assert_eq!(merged["hosts"], json!([
+            { "name": "y", "port": 3, "weight": 1 },
+            { "name": "z", "port": 2 },
+        ]));
+
+        let mut merged = dst.clone();
+        MergeStrategy::new(ArrayStrategy::Append)
+            .with_array_at(&["hosts"], ArrayStrategy::MergeByKey("name".to_owned()))
+            .merge(&mut merged, src);
+        assert_eq!(merged["tags"], json!(["a", "b", "c"]));
+        assert_eq!(merged["hosts"], json!([
+            { "name": "x", "port": 1, "weight": 1 },
+            { "name": "y", "port": 3 },
+            { "name": "z" },
+        ]));
+    }
+}
@@ -0,0 +1,110 @@
+//! Provenance of merged values, i.e., which layers of the config a value comes from.
+
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::merge::ArrayStrategy;
+
+/// The kind of a layer. The layers take precedence in the order of local sources, remote sources
+/// and values set at runtime, and all the `_default_` layers are below the branch ones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OriginKind {
+    Source,
+    Remote,
+    Runtime,
+}
+
+/// A value of the key found in a layer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Origin {
+    pub kind: OriginKind,
+    /// Describes the source, e.g., the path of a file or the URL of a remote source.
+    pub name: String,
+    /// `_default_` or the branch being explained.
+    pub branch: String,
+    pub value: Value,
+    /// Whether the value is left out of the merged value by a higher layer. Objects are merged, so
+    /// an object is only shadowed by a higher value which is not an object, and so are the arrays
+    /// of different branches unless they are merged with `ArrayStrategy::Replace`.
+    pub is_shadowed: bool,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} {} [{}]: {}", self.kind, self.name, self.branch, self.value)?;
+        if self.is_shadowed {
+            f.write_str(" (shadowed)")?;
+        }
+        Ok(())
+    }
+}
+
+/// The merged value of a key in a branch and where it comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Provenance {
+    /// The merged value, or `None` if the key does not exist.
+    pub value: Option<Value>,
+    /// The values found in the layers, from the highest precedence to the lowest.
+    pub chain: Vec<Origin>,
+}
+
+impl Provenance {
+    /// Builds the chain from the origins ordered from the highest precedence to the lowest, and
+    /// marks the shadowed ones. The layers of a branch are overlaid, so an array replaces the
+    /// lower ones of the same branch, while the branches are merged with `arrays`, the strategy
+    /// for the array at the key. A `null` deletes the key, so it shadows all the lower values.
+    pub(crate) fn new(value: Option<Value>, mut chain: Vec<Origin>, arrays: &ArrayStrategy)
+        -> Self
+    {
+        // Whether a higher value replaces all the lower ones.
+        let mut is_replaced = false;
+        let mut has_object_above = false;
+        // The branch of the lowest array which is merged with the arrays of lower branches.
+        let mut array_branch: Option<String> = None;
+        for origin in &mut chain {
+            if is_replaced {
+                origin.is_shadowed = true;
+                continue;
+            }
+
+            let has_array_above = array_branch.is_some();
+            match origin.value {
+                Value::Object(_) => {
+                    // An array above replaces the object along with what is merged into it.
+                    origin.is_shadowed = has_array_above;
+                    is_replaced = has_array_above;
+                    has_object_above = true;
+                }
+                Value::Array(_) if !has_object_above => {
+                    origin.is_shadowed = array_branch.as_ref() == Some(&origin.branch);
+                    if !origin.is_shadowed {
+                        match arrays {
+                            ArrayStrategy::Replace => is_replaced = true,
+                            _ => array_branch = Some(origin.branch.clone()),
+                        }
+                    }
+                }
+                // A scalar, a `null` or an array replaced by an object above.
+                _ => {
+                    origin.is_shadowed = has_object_above || has_array_above;
+                    is_replaced = true;
+                }
+            }
+        }
+        Self { value, chain }
+    }
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{}", value)?,
+            None => f.write_str("(none)")?,
+        }
+        for origin in &self.chain {
+            write!(f, "\n    {}", origin)?;
+        }
+        Ok(())
+    }
+}
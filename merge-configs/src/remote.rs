@@ -1 +1,446 @@
-// TODO
\ No newline at end of file
+//! Remote sources, whose values are layered over the local sources.
+//!
+//! A remote source provides a tree in the same layout as a local source, i.e., the default
+//! settings at the top level and the overrides of branches under `_override_`.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Debug;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{Value, Map};
+
+use crate::{DEFAULT_ID, OVERRIDE_ID};
+use crate::value_ext::ValueExt;
+use crate::error::{Error, Result};
+
+/// How often the default `RemoteSource::watch` polls the version.
+pub const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+pub trait RemoteSource: Debug + Send + Sync {
+    /// Fetches the whole tree.
+    fn fetch(&self) -> Result<Value>;
+
+    /// Describes the source in the provenance of values.
+    fn name(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    /// Returns an opaque version, which changes whenever the tree changes.
+    fn version(&self) -> Result<String>;
+
+    /// Blocks until the version differs from `version` or `timeout` elapses. Returns the new
+    /// version, or `None` on timeout. By default, it polls `version` periodically.
+    fn watch(&self, version: &str, timeout: Duration) -> Result<Option<String>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let new_version = self.version()?;
+            if new_version != version {
+                return Ok(Some(new_version));
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            thread::sleep(WATCH_POLL_INTERVAL.min(deadline - now));
+        }
+    }
+
+    /// Fetches the whole tree with its version, or returns `None` if the version is still
+    /// `version`. By default, the version is got before fetching, so a change in between is
+    /// caught by the next call.
+    fn fetch_if_changed(&self, version: Option<&str>) -> Result<Option<(String, Value)>> {
+        let new_version = self.version()?;
+        if version == Some(new_version.as_str()) {
+            return Ok(None);
+        }
+        Ok(Some((new_version, self.fetch()?)))
+    }
+}
+
+/// Fetches a JSON document over HTTP. The version is the `ETag` (or `Last-Modified`) header of a
+/// `HEAD` response, or the hash of the document if the server sends neither.
+#[derive(Debug, Clone)]
+pub struct HttpSource {
+    url: String,
+    agent: ureq::Agent,
+}
+
+impl HttpSource {
+    pub fn new(url: &str) -> Self {
+        Self::with_timeout(url, Duration::from_secs(10))
+    }
+
+    pub fn with_timeout(url: &str, timeout: Duration) -> Self {
+        Self {
+            url: url.to_owned(),
+            agent: ureq::AgentBuilder::new().timeout(timeout).build(),
+        }
+    }
+
+    /// Gets the document with its version.
+    fn get(&self) -> Result<(String, String)> {
+        let response = self
+            .agent
+            .get(&self.url)
+            .call()
+            .map_err(|e| Error::RemoteError(e.to_string()))?;
+        let version = header_version(&response);
+        let body = response
+            .into_string()
+            .map_err(|e| Error::RemoteError(e.to_string()))?;
+        let version = version.unwrap_or_else(|| hash_version(&body));
+        Ok((version, body))
+    }
+
+    /// Returns the version in the headers of a `HEAD` response, if any.
+    fn head_version(&self) -> Result<Option<String>> {
+        let response = self
+            .agent
+            .head(&self.url)
+            .call()
+            .map_err(|e| Error::RemoteError(e.to_string()))?;
+        Ok(header_version(&response))
+    }
+}
+
+fn header_version(response: &ureq::Response) -> Option<String> {
+    response.header("ETag").or_else(|| response.header("Last-Modified")).map(str::to_owned)
+}
+
+impl RemoteSource for HttpSource {
+    fn name(&self) -> String {
+        self.url.clone()
+    }
+
+    fn fetch(&self) -> Result<Value> {
+        Ok(serde_json::from_str(&self.get()?.1)?)
+    }
+
+    fn version(&self) -> Result<String> {
+        match self.head_version()? {
+            Some(version) => Ok(version),
+            None => Ok(self.get()?.0),
+        }
+    }
+
+    /// Gets the document only once, even if the server sends no version header and the version
+    /// is the hash of the document.
+    fn fetch_if_changed(&self, version: Option<&str>) -> Result<Option<(String, Value)>> {
+        if let Some(version) = version {
+            if self.head_version()?.is_some_and(|v| v == version) {
+                return Ok(None);
+            }
+        }
+
+        let (new_version, body) = self.get()?;
+        if version == Some(new_version.as_str()) {
+            return Ok(None);
+        }
+        Ok(Some((new_version, serde_json::from_str(&body)?)))
+    }
+}
+
+/// Reads a directory of JSON files, one per branch. `<branch>.json` holds the overrides of the
+/// branch, e.g., `whorepresents.com.json`, and `_default_.json` holds the default settings. The
+/// version is the hash of the names and contents of the files.
+#[derive(Debug, Clone)]
+pub struct DirSource {
+    dir: PathBuf,
+}
+
+impl DirSource {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            dir: dir.into(),
+        }
+    }
+
+    /// Returns the branches and contents of the JSON files, sorted by branch.
+    fn read_files(&self) -> Result<Vec<(String, String)>> {
+        let mut files = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if !path.is_file() || path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            if let Some(branch) = path.file_stem().and_then(|s| s.to_str()) {
+                files.push((branch.to_owned(), fs::read_to_string(&path)?));
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+}
+
+impl RemoteSource for DirSource {
+    fn name(&self) -> String {
+        self.dir.display().to_string()
+    }
+
+    fn fetch(&self) -> Result<Value> {
+        let mut tree = Map::new();
+        let mut overrides = Map::new();
+        for (branch, content) in self.read_files()? {
+            let value: Value = serde_json::from_str(&content)?;
+            if branch == DEFAULT_ID {
+                match value {
+                    Value::Object(m) => tree.extend(m),
+                    _ => return Err(Error::NotJsonMapError),
+                }
+            } else {
+                overrides.insert(branch, value);
+            }
+        }
+
+        tree.insert(OVERRIDE_ID.to_owned(), Value::Object(overrides));
+        Ok(Value::Object(tree))
+    }
+
+    fn version(&self) -> Result<String> {
+        let files = self.read_files()?;
+        let mut hasher = DefaultHasher::new();
+        files.hash(&mut hasher);
+        Ok(format!("{:016x}", hasher.finish()))
+    }
+}
+
+fn hash_version(content: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Fetches the tree of the source unless its version is still `version`, and checks that it is
+/// a map, as are the overrides under `_override_`.
+fn fetch_tree(
+    source: &dyn RemoteSource,
+    version: Option<&str>,
+) -> Result<Option<(String, Value)>> {
+    let fetched = source.fetch_if_changed(version)?;
+    if let Some((_, tree)) = &fetched {
+        if let Some(overrides) = tree.as_map()?.get(OVERRIDE_ID) {
+            overrides.as_map()?;
+        }
+    }
+    Ok(fetched)
+}
+
+/// A remote source with its last fetched tree.
+#[derive(Debug)]
+pub(crate) struct RemoteLayer {
+    pub(crate) source: Arc<dyn RemoteSource>,
+    pub(crate) version: String,
+    pub(crate) value: Value,
+}
+
+impl RemoteLayer {
+    pub(crate) fn new(source: Arc<dyn RemoteSource>) -> Result<Self> {
+        let (version, value) = fetch_tree(&*source, None)?
+            .ok_or_else(|| Error::RemoteError(format!("{} sent no tree", source.name())))?;
+        Ok(Self {
+            source,
+            version,
+            value,
+        })
+    }
+
+    /// Fetches the tree again if the version changes. Returns whether the tree is updated.
+    pub(crate) fn refresh(&mut self) -> Result<bool> {
+        match RemoteTree::fetch(Arc::clone(&self.source), self.version.clone())? {
+            Some(tree) => Ok(self.apply(tree)),
+            None => Ok(false),
+        }
+    }
+
+    /// Takes the tree fetched by `RemoteTree::fetch`, unless the layer has been updated since the
+    /// fetch started. Returns whether the tree is updated.
+    pub(crate) fn apply(&mut self, tree: RemoteTree) -> bool {
+        if tree.base_version != self.version {
+            return false;
+        }
+        self.version = tree.version;
+        self.value = tree.value;
+        true
+    }
+}
+
+/// A tree fetched from a remote source, which can be done without borrowing its layer.
+pub(crate) struct RemoteTree {
+    pub(crate) source: Arc<dyn RemoteSource>,
+    // The version of the layer which the fetch started from.
+    base_version: String,
+    version: String,
+    value: Value,
+}
+
+impl RemoteTree {
+    /// Fetches the tree of the source unless its version is still `base_version`.
+    pub(crate) fn fetch(source: Arc<dyn RemoteSource>, base_version: String)
+        -> Result<Option<Self>>
+    {
+        let fetched = fetch_tree(&*source, Some(&base_version))?;
+        Ok(fetched.map(|(version, value)| Self {
+            source,
+            base_version,
+            version,
+            value,
+        }))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::Mutex;
+
+    use serde_json::json;
+
+    /// A stand-in HTTP server serving a JSON document, whose `ETag` is bumped on every update.
+    pub(crate) struct TestServer {
+        pub(crate) url: String,
+        document: Arc<Mutex<(u32, String)>>,
+        // The methods of the requests received.
+        methods: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl TestServer {
+        pub(crate) fn start(document: &Value) -> Self {
+            Self::start_with(document, true)
+        }
+
+        pub(crate) fn start_with(document: &Value, has_etag: bool) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}/config.json", listener.local_addr().unwrap());
+            let document = Arc::new(Mutex::new((1, document.to_string())));
+            let methods = Arc::new(Mutex::new(vec![]));
+
+            let shared = Arc::clone(&document);
+            let received = Arc::clone(&methods);
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = match stream {
+                        Ok(stream) => stream,
+                        Err(_) => continue,
+                    };
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut request_line = String::new();
+                    reader.read_line(&mut request_line).unwrap();
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).unwrap() > 2 {
+                        line.clear();
+                    }
+
+                    let method = request_line.split(' ').next().unwrap_or_default();
+                    received.lock().unwrap().push(method.to_owned());
+
+                    let (etag, body) = shared.lock().unwrap().clone();
+                    let etag = match has_etag {
+                        true => format!("ETag: \"{}\"\r\n", etag),
+                        false => "".to_owned(),
+                    };
+                    let header = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                         Content-Length: {}\r\n{}Connection: close\r\n\r\n",
+                        body.len(),
+                        etag
+                    );
+                    stream.write_all(header.as_bytes()).unwrap();
+                    if request_line.starts_with("GET") {
+                        stream.write_all(body.as_bytes()).unwrap();
+                    }
+                }
+            });
+
+            Self { url, document, methods }
+        }
+
+        /// Returns and clears the methods of the requests received so far.
+        pub(crate) fn take_methods(&self) -> Vec<String> {
+            std::mem::take(&mut *self.methods.lock().unwrap())
+        }
+
+        pub(crate) fn update(&self, document: &Value) {
+            let mut guard = self.document.lock().unwrap();
+            guard.0 += 1;
+            guard.1 = document.to_string();
+        }
+    }
+
+    #[test]
+    fn http_source() -> Result<()> {
+        let server = TestServer::start(&json!({ "rating": 1 }));
+        let source = HttpSource::with_timeout(&server.url, Duration::from_secs(5));
+        assert_eq!(source.fetch()?, json!({ "rating": 1 }));
+
+        let version = source.version()?;
+        assert_eq!(version, "\"1\"");
+        assert_eq!(source.watch(&version, Duration::from_millis(10))?, None);
+
+        server.update(&json!({ "rating": 2 }));
+        assert_eq!(source.watch(&version, Duration::from_secs(5))?, Some("\"2\"".to_owned()));
+        assert_eq!(source.fetch()?, json!({ "rating": 2 }));
+
+        server.take_methods();
+        assert!(source.fetch_if_changed(Some("\"2\""))?.is_none());
+        assert_eq!(server.take_methods(), ["HEAD"]);
+        Ok(())
+    }
+
+    #[test]
+    fn http_source_without_etag() -> Result<()> {
+        let server = TestServer::start_with(&json!({ "rating": 1 }), false);
+        let source = Arc::new(HttpSource::with_timeout(&server.url, Duration::from_secs(5)));
+        let mut layer = RemoteLayer::new(source)?;
+        assert_eq!(layer.value, json!({ "rating": 1 }));
+        assert_eq!(server.take_methods(), ["GET"]);
+
+        // The version is the hash of the document, which is got only once.
+        assert!(!layer.refresh()?);
+        assert_eq!(server.take_methods(), ["HEAD", "GET"]);
+        server.update(&json!({ "rating": 2 }));
+        assert!(layer.refresh()?);
+        assert_eq!(layer.value, json!({ "rating": 2 }));
+        assert_eq!(server.take_methods(), ["HEAD", "GET"]);
+
+        // The trees which are not maps are rejected.
+        for tree in [json!([1]), json!({ "_override_": 1 })] {
+            server.update(&tree);
+            assert!(matches!(layer.refresh(), Err(Error::NotJsonMapError)));
+        }
+        assert_eq!(layer.value, json!({ "rating": 2 }));
+        Ok(())
+    }
+
+    #[test]
+    fn dir_source() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("merge-configs-dir-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("_default_.json"), r#"{ "rating": 1 }"#)?;
+        fs::write(dir.join("whorepresents.com.json"), r#"{ "rating": 95 }"#)?;
+        fs::write(dir.join("README.md"), "Not a branch")?;
+
+        let source = DirSource::new(&dir);
+        let mut layer = RemoteLayer::new(Arc::new(source.clone()))?;
+        assert_eq!(layer.value, json!({
+            "rating": 1,
+            "_override_": { "whorepresents.com": { "rating": 95 } },
+        }));
+        assert!(!layer.refresh()?);
+
+        fs::write(dir.join("childrenswear.co.uk.json"), r#"{ "rating": 0 }"#)?;
+        assert!(layer.refresh()?);
+        assert_eq!(layer.value["_override_"]["childrenswear.co.uk"], json!({ "rating": 0 }));
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
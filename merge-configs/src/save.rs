@@ -0,0 +1,230 @@
+//! Reading and writing config files, keeping the keys as they are and the key order of an
+//! existing file.
+
+use std::fmt;
+use std::path::Path;
+
+use config::FileFormat;
+use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+use serde_json::Value;
+
+use jsonpath::escape_pointer_token;
+
+use crate::error::{Error, Result};
+
+/// Returns the format of the file by its extension.
+pub(crate) fn format_of(path: &Path) -> Result<FileFormat> {
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+    match extension {
+        "json" => Ok(FileFormat::Json),
+        "toml" => Ok(FileFormat::Toml),
+        "yaml" | "yml" => Ok(FileFormat::Yaml),
+        _ => Err(Error::UnsupportedFormatError(path.display().to_string())),
+    }
+}
+
+/// Parses the text in the format into a tree.
+pub(crate) fn parse(text: &str, format: FileFormat) -> Result<Value> {
+    match format {
+        FileFormat::Json => Ok(serde_json::from_str(text)?),
+        FileFormat::Toml => Ok(toml::from_str(text)?),
+        FileFormat::Yaml => Ok(serde_yaml::from_str(text)?),
+        format => Err(Error::UnsupportedFormatError(format!("{:?}", format))),
+    }
+}
+
+/// The order of the keys in a document, recorded for the objects at all levels.
+#[derive(Debug, Default)]
+pub(crate) struct KeyOrder {
+    keys: Vec<(String, KeyOrder)>,
+    items: Vec<KeyOrder>,
+}
+
+impl KeyOrder {
+    /// Reads the key order of a document, or returns `None` if it cannot be parsed.
+    pub(crate) fn parse(text: &str, format: FileFormat) -> Option<Self> {
+        match format {
+            FileFormat::Json => serde_json::from_str(text).ok(),
+            FileFormat::Toml => toml::from_str(text).ok(),
+            FileFormat::Yaml => serde_yaml::from_str(text).ok(),
+            _ => None,
+        }
+    }
+
+    fn child(&self, key: &str) -> Option<&KeyOrder> {
+        self.keys.iter().find(|(k, _)| k == key).map(|(_, order)| order)
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyOrder {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(KeyOrderVisitor)
+    }
+}
+
+struct KeyOrderVisitor;
+
+impl<'de> Visitor<'de> for KeyOrderVisitor {
+    type Value = KeyOrder;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("any value")
+    }
+
+    fn visit_bool<E>(self, _v: bool) -> std::result::Result<KeyOrder, E> {
+        Ok(KeyOrder::default())
+    }
+
+    fn visit_i64<E>(self, _v: i64) -> std::result::Result<KeyOrder, E> {
+        Ok(KeyOrder::default())
+    }
+
+    fn visit_u64<E>(self, _v: u64) -> std::result::Result<KeyOrder, E> {
+        Ok(KeyOrder::default())
+    }
+
+    fn visit_f64<E>(self, _v: f64) -> std::result::Result<KeyOrder, E> {
+        Ok(KeyOrder::default())
+    }
+
+    fn visit_str<E>(self, _v: &str) -> std::result::Result<KeyOrder, E> {
+        Ok(KeyOrder::default())
+    }
+
+    fn visit_unit<E>(self) -> std::result::Result<KeyOrder, E> {
+        Ok(KeyOrder::default())
+    }
+
+    fn visit_none<E>(self) -> std::result::Result<KeyOrder, E> {
+        Ok(KeyOrder::default())
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, d: D) -> std::result::Result<KeyOrder, D::Error> {
+        KeyOrder::deserialize(d)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<KeyOrder, A::Error> {
+        let mut order = KeyOrder::default();
+        while let Some(item) = seq.next_element()? {
+            order.items.push(item);
+        }
+        Ok(order)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<KeyOrder, A::Error> {
+        let mut order = KeyOrder::default();
+        while let Some(key) = map.next_key::<String>()? {
+            let child = map.next_value()?;
+            order.keys.push((key, child));
+        }
+        Ok(order)
+    }
+}
+
+/// Serializes the objects with the keys in the recorded order first, and then the new keys.
+struct Ordered<'a> {
+    value: &'a Value,
+    order: Option<&'a KeyOrder>,
+}
+
+impl Serialize for Ordered<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self.value {
+            Value::Object(m) => {
+                let known = self.order.map_or(&[][..], |o| &o.keys[..]);
+                let keys = known
+                    .iter()
+                    .map(|(k, _)| k)
+                    .filter(|k| m.contains_key(*k))
+                    .chain(m.keys().filter(|k| !known.iter().any(|(known, _)| known == *k)));
+
+                let mut map = serializer.serialize_map(Some(m.len()))?;
+                for k in keys {
+                    let order = self.order.and_then(|o| o.child(k));
+                    map.serialize_entry(k, &Ordered { value: &m[k], order })?;
+                }
+                map.end()
+            }
+            Value::Array(a) => {
+                let mut seq = serializer.serialize_seq(Some(a.len()))?;
+                for (i, v) in a.iter().enumerate() {
+                    let order = self.order.and_then(|o| o.items.get(i));
+                    seq.serialize_element(&Ordered { value: v, order })?;
+                }
+                seq.end()
+            }
+            v => v.serialize(serializer),
+        }
+    }
+}
+
+/// Serializes the tree in the format, keeping the key order of `layout` if given.
+pub(crate) fn to_string(tree: &Value, format: FileFormat, layout: Option<&KeyOrder>)
+    -> Result<String>
+{
+    let ordered = Ordered { value: tree, order: layout };
+    match format {
+        FileFormat::Json => Ok(serde_json::to_string_pretty(&ordered)? + "\n"),
+        // Goes through `toml::Value`, which puts the plain values before the tables.
+        FileFormat::Toml => {
+            if let Some(pointer) = find_null(tree) {
+                return Err(Error::TomlNullError(pointer));
+            }
+            Ok(toml::to_string(&toml::Value::try_from(&ordered)?)?)
+        }
+        FileFormat::Yaml => Ok(serde_yaml::to_string(&ordered)?),
+        format => Err(Error::UnsupportedFormatError(format!("{:?}", format))),
+    }
+}
+
+/// Returns the JSON Pointer of the first `null` in the tree, which TOML cannot represent.
+fn find_null(value: &Value) -> Option<String> {
+    let children: Box<dyn Iterator<Item = (String, &Value)>> = match value {
+        Value::Null => return Some("".to_owned()),
+        Value::Object(m) => Box::new(m.iter().map(|(k, v)| (escape_pointer_token(k), v))),
+        Value::Array(a) => Box::new(a.iter().enumerate().map(|(i, v)| (i.to_string(), v))),
+        _ => return None,
+    };
+    children
+        .into_iter()
+        .find_map(|(token, child)| Some(format!("/{}{}", token, find_null(child)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn ordered_output() -> Result<()> {
+        let layout = r#"{ "z": 1, "a": { "y": [{ "q": 0, "p": 0 }], "x": 0 }, "gone": 0 }"#;
+        let layout = KeyOrder::parse(layout, FileFormat::Json).unwrap();
+        let tree = json!({ "a": { "x": 1, "y": [{ "p": 1, "q": 1 }], "new": 1 }, "z": 2, "b": 0 });
+
+        let output = to_string(&tree, FileFormat::Json, Some(&layout))?;
+        let compact: String = output.split_whitespace().collect();
+        assert_eq!(compact, r#"{"z":2,"a":{"y":[{"q":1,"p":1}],"x":1,"new":1},"b":0}"#);
+
+        let output = to_string(&tree, FileFormat::Toml, Some(&layout))?;
+        assert!(output.starts_with("z = 2\nb = 0\n"));
+        assert_eq!(toml::from_str::<Value>(&output).unwrap(), tree);
+
+        let output = to_string(&tree, FileFormat::Yaml, Some(&layout))?;
+        assert!(output.find("z: 2") < output.find("a:"));
+        assert_eq!(serde_yaml::from_str::<Value>(&output)?, tree);
+
+        assert!(KeyOrder::parse("{", FileFormat::Json).is_none());
+        assert!(to_string(&tree, FileFormat::Ini, None).is_err());
+
+        // TOML has no null, which would otherwise be dropped and no longer delete the key.
+        let tree = json!({ "a": 1, "_override_": { "x.com": { "b": [0, { "c/d": null }] } } });
+        match to_string(&tree, FileFormat::Toml, None) {
+            Err(Error::TomlNullError(p)) => assert_eq!(p, "/_override_/x.com/b/1/c~1d"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(to_string(&tree, FileFormat::Yaml, None).is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+//! Validation of the merged tree of every branch, against a JSON Schema or a `Deserialize` type.
+
+use std::fmt;
+
+use jsonschema::JSONSchema;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use serde_path_to_error::Segment;
+
+use jsonpath::escape_pointer_token;
+
+use crate::error::{Error, Result};
+
+/// A violation of the schema, located by the branch and the JSON Pointer in the merged tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub branch: String,
+    pub path: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] '{}': {}", self.branch, self.path, self.reason)
+    }
+}
+
+#[derive(Debug)]
+enum SchemaKind {
+    Json(Box<JSONSchema>),
+    Typed(fn(&Value) -> Option<(String, String)>),
+}
+
+#[derive(Debug)]
+pub struct Schema {
+    kind: SchemaKind,
+}
+
+impl Schema {
+    /// Compiles a JSON Schema, which reports all the violations in a tree.
+    pub fn from_json(schema: &Value) -> Result<Self> {
+        let schema = JSONSchema::compile(schema)
+            .map_err(|e| Error::InvalidSchemaError(e.to_string()))?;
+        Ok(Self {
+            kind: SchemaKind::Json(Box::new(schema)),
+        })
+    }
+
+    /// Derives the schema from a `Deserialize` type, which only reports the first violation in a
+    /// tree.
+    pub fn of<T: DeserializeOwned>() -> Self {
+        Self {
+            kind: SchemaKind::Typed(check_type::<T>),
+        }
+    }
+
+    /// Validates the merged tree of `branch`.
+    pub fn validate(&self, branch: &str, value: &Value) -> Vec<Violation> {
+        let violation = |path: String, reason: String| Violation {
+            branch: branch.to_owned(),
+            path,
+            reason,
+        };
+
+        match &self.kind {
+            SchemaKind::Json(schema) => match schema.validate(value) {
+                Ok(()) => vec![],
+                Err(errors) => errors
+                    .map(|e| violation(e.instance_path.to_string(), e.to_string()))
+                    .collect(),
+            },
+            SchemaKind::Typed(check) => check(value)
+                .map(|(path, reason)| violation(path, reason))
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+fn check_type<T: DeserializeOwned>(value: &Value) -> Option<(String, String)> {
+    let e = serde_path_to_error::deserialize::<_, T>(value).err()?;
+    let mut path = String::new();
+    for segment in e.path().iter() {
+        path.push('/');
+        match segment {
+            Segment::Seq { index } => path.push_str(&index.to_string()),
+            Segment::Map { key } => path.push_str(&escape_pointer_token(key)),
+            Segment::Enum { variant } => path.push_str(variant),
+            Segment::Unknown => path.push('?'),
+        }
+    }
+    Some((path, e.into_inner().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_derive::Deserialize;
+    use serde_json::json;
+
+    #[test]
+    fn schema_violations() -> Result<()> {
+        let value = json!({ "server": { "port": "80", "a/b": 1 }, "rating": -1 });
+
+        let schema = Schema::from_json(&json!({
+            "type": "object",
+            "properties": {
+                "server": {
+                    "type": "object",
+                    "properties": { "port": { "type": "integer" } },
+                    "additionalProperties": false,
+                },
+                "rating": { "minimum": 0 },
+            },
+            "required": ["site_name"],
+        }))?;
+        let mut violations = schema.validate("localhost", &value);
+        violations.sort_by(|a, b| a.path.cmp(&b.path));
+        let paths: Vec<&str> = violations.iter().map(|v| v.path.as_str()).collect();
+        assert_eq!(paths, vec!["", "/rating", "/server", "/server/port"]);
+        assert!(violations.iter().all(|v| v.branch == "localhost"));
+        assert!(violations[3].to_string().starts_with("[localhost] '/server/port': "));
+
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        struct Server {
+            port: u16,
+        }
+        #[allow(dead_code)]
+        #[derive(Deserialize)]
+        struct Settings {
+            server: Server,
+        }
+        let violations = Schema::of::<Settings>().validate("localhost", &value);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].path, "/server/port");
+        assert!(violations[0].reason.starts_with("invalid type: string \"80\""));
+
+        assert!(Schema::from_json(&json!({ "type": 0 })).is_err());
+        Ok(())
+    }
+}
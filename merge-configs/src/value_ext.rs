@@ -7,14 +7,10 @@ use crate::error::{Error, Result};
 pub trait ValueExt {
     /// Similar to `as_object`, but returns `Result`.
     fn as_map(&self) -> Result<&Map<String, Value>>;
-    /// The mutable version of `as_map`.
+    /// Similar to `as_object_mut`, but returns `Result`.
     fn as_map_mut(&mut self) -> Result<&mut Map<String, Value>>;
-    /// Moves into inner map.
-    fn into_map(self) -> Result<Map<String, Value>>;
     /// `get` that returns `Result`.
     fn get_value<I: Index>(&self, index: I) -> Result<&Value>;
-    /// `get_mut` that returns `Result`.
-    fn get_value_mut<I: Index>(&mut self, index: I) -> Result<&mut Value>;
 }
 
 impl ValueExt for Value {
@@ -28,20 +24,8 @@ impl ValueExt for Value {
             .ok_or_else(|| Error::NotJsonMapError)
     }
 
-    fn into_map(self) -> Result<Map<String, Value>> {
-        match self {
-            Value::Object(m) => Ok(m),
-            _ => Err(Error::NotJsonMapError),
-        }
-    }
-
     fn get_value<I: Index>(&self, index: I) -> Result<&Value> {
         self.get(index)
             .ok_or_else(|| Error::JsonInvalidIndexError)
     }
-
-    fn get_value_mut<I: Index>(&mut self, index: I) -> Result<&mut Value> {
-        self.get_mut(index)
-            .ok_or_else(|| Error::JsonInvalidIndexError)
-    }
 }